@@ -1,60 +1,262 @@
-use std::collections::HashMap;
+use std::{collections::HashSet, panic::{self, AssertUnwindSafe}, sync::Mutex};
 
-use crate::model::dto::{SimpleFileInfo, FileInfo};
+use crate::infra::cancellation;
 
-use super::command::Command;
-
-#[derive(Debug)]
-pub enum ContextData {
-    String(String),
-    FileList(Vec<SimpleFileInfo>),
-    FileInfo(Vec<FileInfo>),
-}
+use super::{
+    command::Command,
+    context::{Context, ContextError, ContextKey, JobResult},
+    job::{CommandStatus, Job, JobClass, JobError, JobStatus},
+    retry::{self, panic_message, RetryError},
+};
 
 /// 动作
-/// 包含一组命令
+/// 包含一组命令，命令之间的依赖关系组成有向无环图
+/// 依赖的命令都成功后才执行，没有依赖关系的命令在线程池中并行执行
 /// 可以并行执行不同动作
 pub struct Action {
+    name: String,
+    class: JobClass,
     commands: Vec<Box<dyn Command + Send + Sync>>,
+    /// 每个命令依赖的命令序号
+    dependencies: Vec<Vec<usize>>,
+}
+
+/// 动作执行过程中的状态
+struct ExecutionState {
+    context: Context,
+    statuses: Vec<CommandStatus>,
+    /// 已经开始执行的命令
+    started: Vec<usize>,
+    /// 第一个失败的命令的错误，失败后不再开始新的命令
+    error: Option<JobError>,
 }
 
 impl Action {
     pub fn new() -> Action {
         Action {
+            name: "action".to_string(),
+            class: JobClass::default(),
             commands: Vec::new(),
+            dependencies: Vec::new(),
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 设置动作名称，用作任务名称
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    /// 任务类型，决定执行的线程池、并发数和优先级
+    pub fn class(&self) -> JobClass {
+        self.class
+    }
+
+    pub fn set_class(&mut self, class: JobClass) {
+        self.class = class;
+    }
+
+    /// 所有命令的名称
+    pub fn command_names(&self) -> Vec<String> {
+        self.commands.iter().map(|command| command.name()).collect()
+    }
+
+    /// 添加命令，依赖前一个命令
     pub fn add_command(&mut self, command: Box<dyn Command + Send + Sync>) {
-        self.commands.push(command);
+        let dependencies: Vec<usize> = self.commands.len().checked_sub(1).into_iter().collect();
+        self.add_command_after(command, &dependencies);
     }
 
     pub fn add_commands(&mut self, commands: Vec<Box<dyn Command + Send + Sync>>) {
-        self.commands.extend(commands);
+        for command in commands {
+            self.add_command(command);
+        }
+    }
+
+    /// 添加依赖指定命令的命令
+    /// @param command 命令
+    /// @param dependencies 依赖的命令序号，只能依赖已经添加的命令
+    /// @return 命令序号
+    pub fn add_command_after(&mut self, command: Box<dyn Command + Send + Sync>, dependencies: &[usize]) -> usize {
+        self.commands.push(command);
+        self.dependencies.push(dependencies.to_vec());
+        self.commands.len() - 1
     }
 
-    pub fn execute(&self) {
-        let mut action_context: HashMap<&str, ContextData> = HashMap::new();
-        // 存放已经执行过的命令
-        let mut executed_command_stack: Vec<&Box<dyn Command + Send + Sync>> = Vec::new();
-        for command in self.commands.iter() {
-            executed_command_stack.push(command);
-            match command.execute(&mut action_context) {
-                Err(err) => {
-                    println!("Error from command: {}", err);
-                    // 倒过来执行回滚操作
-                    while let Some(command) = executed_command_stack.pop() {
-                        command.rollback(&mut action_context);
+    /// 检查依赖关系，以及每个命令需要的输入数据都由它直接或间接依赖的命令产生
+    /// @return 第一个错误
+    pub fn validate(&self) -> Result<(), ContextError> {
+        // 每个命令直接或间接依赖的命令
+        let mut ancestors: Vec<HashSet<usize>> = Vec::with_capacity(self.commands.len());
+        for (index, command) in self.commands.iter().enumerate() {
+            let mut command_ancestors: HashSet<usize> = HashSet::new();
+            for &dependency in &self.dependencies[index] {
+                if dependency >= index {
+                    return Err(ContextError::InvalidDependency { command: command.name(), dependency });
+                }
+                command_ancestors.insert(dependency);
+                command_ancestors.extend(&ancestors[dependency]);
+            }
+            let produced: HashSet<ContextKey> = command_ancestors.iter()
+                .flat_map(|&ancestor| self.commands[ancestor].outputs())
+                .collect();
+            if let Some(input) = command.inputs().into_iter().find(|input| !produced.contains(input)) {
+                return Err(ContextError::MissingInput { command: command.name(), input: input.name() });
+            }
+            ancestors.push(command_ancestors);
+        }
+        Ok(())
+    }
+
+    /// 执行动作，并把执行状态记录到任务中
+    /// 命令失败、panic 或任务被取消时不再开始新的命令，等待正在执行的命令结束后，
+    /// 按依赖关系倒序回滚已经执行过的命令
+    /// @param job 任务
+    pub fn execute(&self, job: &Job) {
+        job.set_status(JobStatus::Running);
+        let state = Mutex::new(ExecutionState {
+            context: Context::new(),
+            statuses: vec![CommandStatus::Pending; self.commands.len()],
+            started: Vec::new(),
+            error: None,
+        });
+        rayon::scope(|scope| self.start_ready_commands(scope, &state, job));
+        let mut state = state.into_inner().unwrap();
+
+        let error = match state.error.take() {
+            Some(error) => error,
+            None => {
+                job.set_result(take_result(&mut state.context));
+                job.set_status(JobStatus::Succeeded);
+                return;
+            },
+        };
+        let cancelled = matches!(error, JobError::Cancelled { .. });
+        job.set_error(error);
+
+        // 命令只依赖序号更小的命令，按序号倒序回滚就是依赖关系的倒序
+        state.started.sort_unstable();
+        let mut rollback_succeeded = true;
+        for &index in state.started.iter().rev() {
+            let command = &self.commands[index];
+            match panic::catch_unwind(AssertUnwindSafe(|| command.rollback(&mut state.context))) {
+                Ok(_) => {
+                    if job.command_status(index) != CommandStatus::Failed {
+                        job.set_command_status(index, CommandStatus::RolledBack);
                     }
-                    break;
                 },
-                _ => {}
+                Err(payload) => {
+                    println!("Rollback of command {} panicked: {}", command.name(), panic_message(payload));
+                    rollback_succeeded = false;
+                },
             }
         }
+        let status = match (rollback_succeeded, cancelled) {
+            (false, _) => JobStatus::Failed,
+            (true, true) => JobStatus::Cancelled,
+            (true, false) => JobStatus::RolledBack,
+        };
+        job.set_result(take_result(&mut state.context));
+        job.set_status(status);
+    }
+
+    /// 开始执行依赖的命令都已经成功的命令，每个命令结束后再检查一次
+    fn start_ready_commands<'s>(&'s self, scope: &rayon::Scope<'s>, state: &'s Mutex<ExecutionState>, job: &'s Job) {
+        let mut guard = state.lock().unwrap();
+        if guard.error.is_some() {
+            return;
+        }
+        for index in 0..self.commands.len() {
+            let ready = guard.statuses[index] == CommandStatus::Pending
+                && self.dependencies[index].iter().all(|&dependency| guard.statuses[dependency] == CommandStatus::Succeeded);
+            if !ready {
+                continue;
+            }
+            guard.statuses[index] = CommandStatus::Running;
+            guard.started.push(index);
+            // 其他没有结束的命令不再需要的输入数据交给这个命令，可以取出或修改
+            let moved: Vec<ContextKey> = self.commands[index].inputs().into_iter()
+                .filter(|input| {
+                    !(0..self.commands.len()).any(|other| {
+                        other != index
+                            && matches!(guard.statuses[other], CommandStatus::Pending | CommandStatus::Running)
+                            && self.commands[other].inputs().contains(input)
+                    })
+                })
+                .collect();
+            let mut context = guard.context.fork(&moved);
+            job.set_command_status(index, CommandStatus::Running);
+            scope.spawn(move |scope| {
+                let command = &self.commands[index];
+                let result = execute_command(index, command.as_ref(), &mut context, job);
+                {
+                    let mut guard = state.lock().unwrap();
+                    guard.context.merge(context);
+                    match result {
+                        Ok(_) => {
+                            guard.statuses[index] = CommandStatus::Succeeded;
+                            job.set_command_status(index, CommandStatus::Succeeded);
+                        },
+                        Err(error) => {
+                            println!("Error from command: {}", error);
+                            guard.statuses[index] = CommandStatus::Failed;
+                            job.set_command_status(index, CommandStatus::Failed);
+                            guard.error.get_or_insert(error);
+                        },
+                    }
+                }
+                self.start_ready_commands(scope, state, job);
+            });
+        }
     }
 }
 
-/// 将一组命令包装成动作
+/// 执行命令，失败时按命令的重试策略重试，每次尝试都记录到任务中
+/// panic 不重试
+/// @param index 命令序号
+/// @param command 命令
+/// @param context 动作上下文
+/// @param job 任务
+fn execute_command(index: usize, command: &(dyn Command + Send + Sync), context: &mut Context, job: &Job) -> Result<(), JobError> {
+    let progress = job.progress(index);
+    let timeout = command.timeout();
+    let result = retry::run_with_retry(
+        &format!("Command {}", command.name()),
+        &command.retry_policy(),
+        job.cancel_token(),
+        |attempt| job.add_attempt(index, attempt),
+        |_| {
+            let cancel = match timeout {
+                Some(timeout) => job.cancel_token().with_timeout(timeout),
+                None => job.cancel_token().clone(),
+            };
+            // 开始执行前已经取消时不再执行
+            cancel.check()?;
+            command.execute(context, &progress, &cancel)
+        },
+    );
+    result.map_err(|err| match err {
+        RetryError::Panicked(message) => JobError::CommandPanicked { command: command.name(), message },
+        RetryError::Cancelled => JobError::Cancelled { command: command.name() },
+        RetryError::Failed(err) if cancellation::is_cancelled(&err) => JobError::Cancelled { command: command.name() },
+        RetryError::Failed(err) if cancellation::is_timed_out(&err) => JobError::CommandTimedOut {
+            command: command.name(),
+            timeout: timeout.map(|timeout| timeout.as_millis()).unwrap_or(0),
+        },
+        RetryError::Failed(err) => JobError::CommandFailed { command: command.name(), message: err.to_string() },
+    })
+}
+
+/// 取出任务结果
+fn take_result(context: &mut Context) -> Option<serde_json::Value> {
+    context.remove::<JobResult>().map(|result| result.0)
+}
+
+/// 将一组命令包装成按顺序执行的动作
+/// 命令的输入数据没有由前面的命令产生时 panic，在创建动作时就能发现命令顺序错误
 /// 
 /// 例子:
 /// ```
@@ -68,6 +270,9 @@ macro_rules! action {
             $(
                 action.add_command(Box::new($command));
             )*
+            if let Err(err) = action.validate() {
+                panic!("Invalid action: {}", err);
+            }
             Box::new(action)
         }
     };
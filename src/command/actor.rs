@@ -1,59 +1,273 @@
-use std::sync::{mpsc::{SendError, Sender, channel}, Mutex};
+use std::{
+    collections::{HashMap, VecDeque},
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
 
+use anyhow::{anyhow, Result};
 use once_cell::sync::Lazy;
 use rayon::ThreadPool;
+use tokio::sync::oneshot;
 
-use super::action::Action;
+use crate::{config::app_config, infra::time_utils, model::dto::{QueuedJob, RecoveryPolicy}, repository::job_queue};
 
-pub static GLOBAL_ACTOR: Lazy<Mutex<Actor>> = Lazy::new(|| {
-    Mutex::new(Actor::new())
+use super::{action::Action, job::{self, Job, JobClass, JobError, JobHandle, JobStatus, ResourcePool}, registry};
+
+pub static GLOBAL_ACTOR: Lazy<Actor> = Lazy::new(Actor::new);
+
+/// 以计算为主的线程池
+static CPU_POOL: Lazy<ThreadPool> = Lazy::new(|| {
+    rayon::ThreadPoolBuilder::new().num_threads(num_cpus::get()).build().unwrap()
+});
+
+/// 以读写文件为主的线程池
+static IO_POOL: Lazy<ThreadPool> = Lazy::new(|| {
+    rayon::ThreadPoolBuilder::new().num_threads(app_config::IO_POOL_THREADS).build().unwrap()
 });
 
-static THREAD_POOL: Lazy<ThreadPool> = Lazy::new(|| { rayon::ThreadPoolBuilder::new().num_threads(num_cpus::get()).build().unwrap() });
+/// 用户等待结果的短任务的线程池
+static INTERACTIVE_POOL: Lazy<ThreadPool> = Lazy::new(|| {
+    rayon::ThreadPoolBuilder::new().num_threads(app_config::INTERACTIVE_POOL_THREADS).build().unwrap()
+});
+
+/// 检查同名任务和加入队列之间不能有其他同名任务加入
+static EXCLUSIVE_LOCK: Mutex<()> = Mutex::new(());
+
+/// 优先级为 1 时每次执行增加的虚拟时间
+static STRIDE: u64 = 1 << 20;
+
+/// 等待执行的任务
+type Task = Box<dyn FnOnce() + Send>;
+
+fn thread_pool(pool: ResourcePool) -> &'static ThreadPool {
+    match pool {
+        ResourcePool::Io => &IO_POOL,
+        ResourcePool::Cpu => &CPU_POOL,
+        ResourcePool::Interactive => &INTERACTIVE_POOL,
+    }
+}
+
+/// 每种任务类型的调度状态
+#[derive(Default)]
+struct ClassState {
+    queue: VecDeque<Task>,
+    running: usize,
+    /// 虚拟时间，每次执行增加 STRIDE / 优先级，总是选择虚拟时间最小的类型
+    pass: u64,
+}
+
+impl ClassState {
+    fn is_active(&self) -> bool {
+        !self.queue.is_empty() || self.running > 0
+    }
+}
+
+#[derive(Default)]
+struct ActorState {
+    classes: HashMap<JobClass, ClassState>,
+    /// 每个线程池正在执行的任务数
+    pool_running: HashMap<ResourcePool, usize>,
+}
 
 /// 动作执行者
 /// 注意：不是 Actor 设计模式
+/// 按任务类型排队，每种类型不超过各自的并发数，每个线程池同时执行的任务不超过线程数
+/// 多种类型竞争同一个线程池时按优先级比例分配执行机会（stride 调度），低优先级的任务不会饿死
 pub struct Actor {
-    tx: Sender<Box<Action>>,
+    state: Mutex<ActorState>,
 }
 
 impl Actor {
     pub fn new() -> Actor {
-        let (sender, receiver) = channel();
-        let actor = Actor {
-            tx: sender
-        };
-        std::thread::spawn(move || {
-            loop {
-                match receiver.recv() {
-                    Ok(action) => {
-                        THREAD_POOL.spawn(move || action.execute());
-                    },
-                    Err(error) => {
-                        if error.to_string().contains("closed channel") {
-                            break;
-                        }
-                        println!("{}", error);
-                    }
-                }
+        Actor {
+            state: Mutex::new(ActorState::default()),
+        }
+    }
+
+    /// 把任务加入队列
+    /// @param class 任务类型
+    /// @param task 任务
+    pub fn spawn<F: FnOnce() + Send + 'static>(&'static self, class: JobClass, task: F) {
+        let mut state = self.state.lock().unwrap();
+        // 空闲后重新加入竞争的类型从当前最小的虚拟时间开始，不能用空闲时积累的机会
+        let min_pass = state.classes.values()
+            .filter(|class_state| class_state.is_active())
+            .map(|class_state| class_state.pass)
+            .min();
+        let class_state = state.classes.entry(class).or_default();
+        if !class_state.is_active() {
+            if let Some(min_pass) = min_pass {
+                class_state.pass = class_state.pass.max(min_pass);
             }
+        }
+        class_state.queue.push_back(Box::new(task));
+        self.dispatch(&mut state);
+    }
+
+    /// 执行动作
+    /// @param action 动作
+    /// @param job 任务
+    pub fn add_action(&'static self, action: Box<Action>, job: Arc<Job>) {
+        self.spawn(action.class(), move || {
+            // 持久化的任务在结束后才从队列中删除，重启时可以恢复
+            job_queue::mark_started(job.id());
+            action.execute(&job);
+            job_queue::remove(job.id());
         });
-        actor
     }
 
-    pub fn add_action(&mut self, action: Box<Action>) -> Result<(), SendError<Box<Action>>> {
-        self.tx.send(action)
+    /// 从队列中取出可以执行的任务交给线程池
+    fn dispatch(&'static self, state: &mut ActorState) {
+        loop {
+            let class = JobClass::ALL.iter()
+                .filter(|class| {
+                    let pool = class.pool();
+                    let pool_running = state.pool_running.get(&pool).copied().unwrap_or(0);
+                    pool_running < thread_pool(pool).current_num_threads()
+                        && state.classes.get(class)
+                            .map(|class_state| !class_state.queue.is_empty() && class_state.running < class.max_concurrency())
+                            .unwrap_or(false)
+                })
+                .min_by_key(|class| (state.classes[class].pass, std::cmp::Reverse(class.priority())))
+                .copied();
+            let class = match class {
+                Some(class) => class,
+                None => break,
+            };
+            let class_state = state.classes.get_mut(&class).unwrap();
+            let task = class_state.queue.pop_front().unwrap();
+            class_state.running += 1;
+            class_state.pass += STRIDE / class.priority() as u64;
+            *state.pool_running.entry(class.pool()).or_default() += 1;
+            thread_pool(class.pool()).spawn(move || {
+                if panic::catch_unwind(AssertUnwindSafe(task)).is_err() {
+                    println!("Task of class {:?} panicked", class);
+                }
+                self.finish(class);
+            });
+        }
     }
+
+    /// 任务结束，释放并发数并调度等待的任务
+    fn finish(&'static self, class: JobClass) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(class_state) = state.classes.get_mut(&class) {
+            class_state.running -= 1;
+        }
+        if let Some(running) = state.pool_running.get_mut(&class.pool()) {
+            *running -= 1;
+        }
+        self.dispatch(&mut state);
+    }
+}
+
+/// 在指定类型的线程池中执行，并等待结果
+/// 用于接口中耗时的操作（例如生成封面缩略图），与后台任务一起调度
+/// @param class 任务类型
+/// @param f 操作
+/// @return 操作的结果，操作 panic 时返回错误
+pub async fn run<T, F>(class: JobClass, f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    GLOBAL_ACTOR.spawn(class, move || {
+        let _ = sender.send(f());
+    });
+    receiver.await.map_err(|_| anyhow!("Task panicked"))
 }
 
 /// 执行动作
 /// @param action 动作
-pub fn act(action: Box<Action>) {
-    let mut actor = GLOBAL_ACTOR.lock().unwrap();
-    match actor.add_action(action) {
-        Ok(_) => {},
-        Err(error) => {
-            println!("{}", error);
-        }
+/// @return 任务句柄，用于查询执行状态和等待结果
+pub fn act(action: Box<Action>) -> JobHandle {
+    let job = Job::create(action.name(), action.class(), action.command_names());
+    submit(action, job)
+}
+
+/// 提交动作给执行者
+fn submit(action: Box<Action>, job: Arc<Job>) -> JobHandle {
+    let handle = JobHandle::new(job.clone());
+    // 没有使用 action! 创建的动作在这里检查
+    if let Err(err) = action.validate() {
+        println!("{}", err);
+        job_queue::remove(job.id());
+        job.set_error(JobError::InvalidAction { message: err.to_string() });
+        job.set_status(JobStatus::Failed);
+        return handle;
     }
+    GLOBAL_ACTOR.add_action(action, job);
+    handle
+}
+
+/// 持久化并执行动作
+/// 重启时没有结束的任务会根据恢复方式继续执行
+/// @param name 登记的动作名称
+/// @param args 动作参数
+/// @param recovery 恢复方式
+/// @return 任务句柄
+pub fn enqueue(name: &str, args: serde_json::Value, recovery: RecoveryPolicy) -> Result<JobHandle> {
+    let action = registry::create_action(name, &args)?;
+    let job = Job::create(action.name(), action.class(), action.command_names());
+    job_queue::set(&QueuedJob {
+        id: job.id(),
+        name: name.to_string(),
+        args,
+        recovery,
+        runs: 0,
+        created_at: time_utils::time_to_millis(&SystemTime::now()),
+    });
+    Ok(submit(action, job))
+}
+
+/// 持久化并执行动作，同名任务没有结束时不重复执行
+/// @param name 登记的动作名称
+/// @param args 动作参数
+/// @param recovery 恢复方式
+/// @return 新的任务句柄，同名任务没有结束时返回 Ok(Err) 和正在执行的任务
+pub fn enqueue_exclusive(name: &str, args: serde_json::Value, recovery: RecoveryPolicy) -> Result<std::result::Result<JobHandle, JobHandle>> {
+    let _guard = EXCLUSIVE_LOCK.lock().unwrap();
+    if let Some(job) = job::find_unfinished(name) {
+        return Ok(Err(job));
+    }
+    enqueue(name, args, recovery).map(Ok)
+}
+
+/// 恢复重启前没有结束的任务
+/// 没有开始执行的任务总是继续执行，已经开始执行的任务根据恢复方式重新执行或者标记为失败
+/// @return 恢复的任务
+pub fn recover() -> Vec<JobHandle> {
+    recover_jobs(job_queue::list())
+}
+
+/// 恢复指定的任务
+/// @param queued_jobs 队列中的任务
+/// @return 恢复的任务
+pub fn recover_jobs(queued_jobs: Vec<QueuedJob>) -> Vec<JobHandle> {
+    queued_jobs.into_iter()
+        .map(|queued| {
+            let resume = queued.runs == 0
+                || (queued.recovery == RecoveryPolicy::Resume && queued.runs <= app_config::JOB_MAX_RECOVERIES);
+            let error = if resume {
+                match registry::create_action(&queued.name, &queued.args) {
+                    Ok(action) => {
+                        println!("Resume job {} ({})", queued.id, queued.name);
+                        let job = Job::create_with_id(queued.id, action.name(), action.class(), action.command_names());
+                        return submit(action, job);
+                    },
+                    Err(err) => JobError::InvalidAction { message: err.to_string() },
+                }
+            } else {
+                JobError::Interrupted { runs: queued.runs }
+            };
+            println!("Discard job {} ({}): {}", queued.id, queued.name, error);
+            job_queue::remove(queued.id);
+            let job = Job::create_with_id(queued.id, &queued.name, JobClass::default(), Vec::new());
+            job.set_error(error);
+            job.set_status(JobStatus::Failed);
+            JobHandle::new(job)
+        })
+        .collect()
 }
\ No newline at end of file
@@ -1,92 +1,860 @@
-use anyhow::{Result, Ok};
+use anyhow::{anyhow, Result, Ok};
 use radix_fmt::radix;
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator, IntoParallelRefMutIterator};
-use std::{collections::{HashMap, HashSet}, fs, path::PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{collections::{HashMap, HashSet}, path::{Path, PathBuf}, sync::atomic::{AtomicU64, Ordering}, time::Duration};
 
 use crate::{
-    infra::{file_utils, hash_utils},
-    model::dto::FileInfo,
-    repository::file_info, config::app_config,
+    action,
+    infra::{
+        cancellation::{self, CancellationToken},
+        cover_utils, file_utils, hash_utils, transcode_cache,
+        transcode_preset::{self, Preset},
+    },
+    model::dto::{FileInfo, RecoveryPolicy, SimpleFileInfo},
+    repository::{file_info, change_log}, config::app_config,
 };
 
-use super::action::ContextData;
+use super::{
+    action::Action,
+    actor::{enqueue, enqueue_exclusive},
+    context::{short_type_name, Context, ContextKey, JobResult},
+    job::{AttemptInfo, JobClass, JobHandle, Progress},
+    retry::{self, RetryError, RetryPolicy},
+};
+
+/// 扫描到的文件列表
+pub struct SimpleFileList(pub Vec<SimpleFileInfo>);
+
+/// 已经计算文件信息 Hash 的文件列表
+pub struct HashedFileList(pub Vec<SimpleFileInfo>);
+
+/// 新生成的文件信息
+pub struct GeneratedFileInfo(pub Vec<FileInfo>);
+
+/// 重试后仍然无法生成文件信息的文件，下次扫描时重新生成
+pub struct SkippedFiles(pub Vec<SkippedFile>);
+
+/// 无法生成文件信息的文件
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedFile {
+    pub path: String,
+    /// 每次尝试的记录
+    pub attempts: Vec<AttemptInfo>,
+}
+
+/// 删除的文件信息，用于回滚
+pub struct RemovedFileInfo(pub Vec<FileInfo>);
+
+/// 保存时被覆盖的文件信息，用于回滚
+pub struct ReplacedFileInfo(pub Vec<FileInfo>);
+
+/// 根据重命名事件沿用原来信息的文件信息: (移动前, 移动后)
+pub struct RenamedFileInfo(pub Vec<(FileInfo, FileInfo)>);
+
+/// 根据音频数据找到的移动或重命名的文件信息: (移动前, 移动后)
+pub struct MovedFileInfo(pub Vec<(FileInfo, FileInfo)>);
+
+/// 增量更新的范围
+pub struct UpdateScopes(pub Vec<UpdateScope>);
+
+/// 封面或编码设置变化的范围，范围内的文件没有变化也重新生成文件信息
+pub struct RefreshScopes(pub Vec<UpdateScope>);
 
 /// 命令
 /// 一组命令组合成一个动作
-/// 动作中的命令会按顺序串行执行
-/// 多个命令之间可以共享内存
+/// 动作中的命令按依赖关系执行，没有依赖关系的命令会并行执行
+/// 多个命令之间通过上下文共享数据
 pub trait Command {
+    /// 命令名称，默认为类型名称
+    fn name(&self) -> String {
+        short_type_name::<Self>().to_string()
+    }
+    /// 命令需要的上下文数据，创建动作时检查是否由前面的命令产生
+    fn inputs(&self) -> Vec<ContextKey> {
+        Vec::new()
+    }
+    /// 命令产生的上下文数据
+    fn outputs(&self) -> Vec<ContextKey> {
+        Vec::new()
+    }
+    /// 失败时的重试策略，默认不重试
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::none()
+    }
+    /// 每次尝试的超时时间，超时后取消令牌返回 TimedOut 错误，默认不限制
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
     /// 执行命令
     /// @param context 动作上下文
-    fn execute(&self, context: &mut HashMap<&str, ContextData>) -> Result<()>;
+    /// @param progress 命令进度
+    /// @param cancel 取消令牌，耗时的命令需要定期检查，取消时返回 Cancelled 错误
+    fn execute(&self, context: &mut Context, progress: &Progress, cancel: &CancellationToken) -> Result<()>;
     /// 失败时的回滚
     /// @param context 动作上下文
-    fn rollback(&self, _context: &mut HashMap<&str, ContextData>) {
+    fn rollback(&self, _context: &mut Context) {
         // do nothing
     }
 }
 
 /// 扫描目录下的所有音频文件
-struct ScanMediaFile;
+pub struct ScanMediaFile;
 impl Command for ScanMediaFile {
-    fn execute(&self, context: &mut HashMap<&str, ContextData>) -> Result<()> {
+    fn outputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<SimpleFileList>()]
+    }
+
+    fn execute(&self, context: &mut Context, progress: &Progress, _cancel: &CancellationToken) -> Result<()> {
         let audio_file_list = file_utils::list_audio_file();
-        context.insert("simple_file_list", ContextData::FileList(audio_file_list));
+        progress.set_total(audio_file_list.len() as u64);
+        progress.set_done(audio_file_list.len() as u64);
+        context.insert(SimpleFileList(audio_file_list));
         Ok(())
     }
 }
 
 /// 计算文件信息 Hash
-struct CalcFileInfoHash;
+pub struct CalcFileInfoHash;
 impl Command for CalcFileInfoHash {
-    fn execute(&self, context: &mut HashMap<&str, ContextData>) -> Result<()> {
-        if let ContextData::FileList(file_list) = context.get_mut("simple_file_list").unwrap() {
-            // 计算 Hash
-            file_list.par_iter_mut().for_each(|file_info| {
-                let hash = radix(hash_utils::hash_media_file_info(file_info), 36).to_string();
-                file_info.file_info_hash = Some(hash);
-            });
-        }
+    fn inputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<SimpleFileList>()]
+    }
+
+    fn outputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<HashedFileList>()]
+    }
+
+    fn execute(&self, context: &mut Context, progress: &Progress, _cancel: &CancellationToken) -> Result<()> {
+        let mut file_list = context.take::<SimpleFileList>()?.0;
+        progress.set_total(file_list.len() as u64);
+        // 计算 Hash
+        file_list.par_iter_mut().for_each(|file_info| {
+            let hash = radix(hash_utils::hash_media_file_info(file_info), 36).to_string();
+            file_info.file_info_hash = Some(hash);
+            progress.inc(1);
+        });
+        context.insert(HashedFileList(file_list));
         Ok(())
     }
 }
 
 /// 清理旧数据
-struct CleanStorage;
+/// 删除媒体库中已经不存在的文件信息，增量更新时只删除更新范围内的文件信息
+pub struct CleanStorage;
 impl Command for CleanStorage {
-    fn execute(&self, context: &mut HashMap<&str, ContextData>) -> Result<()> {
-        if let ContextData::FileList(simple_file_list) = context.get("simple_file_list").unwrap() {
-            // 获取文件 Hash 集合
-            let file_info_hash_set: HashSet<String> = simple_file_list.iter().map(|simple_file_info| {
-                simple_file_info.file_info_hash.as_ref().unwrap().clone()
-            }).collect();
-
-            // 清理旧的文件信息
-            let old_file_info_hash_set = file_info::list_key();
-            old_file_info_hash_set.into_iter().for_each(|old_file_info_hash| {
-                if file_info_hash_set.contains(&old_file_info_hash) {
-                    // 删除数据库中的文件信息
-                    file_info::remove(&old_file_info_hash);
-                }
-            });
+    fn inputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<HashedFileList>()]
+    }
+
+    fn outputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<RemovedFileInfo>()]
+    }
+
+    fn execute(&self, context: &mut Context, _progress: &Progress, _cancel: &CancellationToken) -> Result<()> {
+        let mut removed_file_info_list: Vec<FileInfo> = Vec::new();
+        let HashedFileList(simple_file_list) = context.require::<HashedFileList>()?;
+        let scopes = context.get::<UpdateScopes>();
+        // 获取文件 Hash 集合
+        let file_info_hash_set: HashSet<&String> = simple_file_list.iter()
+            .filter_map(|simple_file_info| simple_file_info.file_info_hash.as_ref())
+            .collect();
+
+        // 清理旧的文件信息
+        for (old_file_info_hash, old_file_info) in file_info::list() {
+            let in_scope = scopes
+                .map(|UpdateScopes(scopes)| {
+                    let path = file_utils::list_to_path(&old_file_info.path);
+                    scopes.iter().any(|scope| scope.contains(&path))
+                })
+                .unwrap_or(true);
+            if in_scope && !file_info_hash_set.contains(&old_file_info_hash) {
+                // 删除数据库中的文件信息，保留一份用于回滚
+                file_info::remove(&old_file_info_hash);
+                removed_file_info_list.push(old_file_info);
+            }
         }
-        
+        println!("Removed {} file infos", removed_file_info_list.len());
+        context.insert(RemovedFileInfo(removed_file_info_list));
         Ok(())
     }
+
+    fn rollback(&self, context: &mut Context) {
+        if let Some(RemovedFileInfo(removed_file_info_list)) = context.get::<RemovedFileInfo>() {
+            for file_info in removed_file_info_list {
+                file_info::set(&file_info.file_info_hash, file_info);
+            }
+        }
+    }
+}
+
+/// 判断文件是否需要重新生成文件信息
+/// 文件信息 Hash 已存在时不需要，但 cuesheet 关联的整轨文件变化或在刷新范围内时需要
+/// @param simple 文件
+/// @param refresh 刷新范围
+fn need_generate(simple: &SimpleFileInfo, refresh: &[UpdateScope]) -> bool {
+    if refresh.iter().any(|scope| scope.contains(&simple.path)) {
+        return true;
+    }
+    let old_file_info = match simple.file_info_hash.as_ref().and_then(file_info::get) {
+        Some(old_file_info) => old_file_info,
+        None => return true,
+    };
+    match &old_file_info.cue_media_path {
+        Some(cue_media_path) => {
+            let media_file_info_hash = file_utils::path_to_simple_file_info(Path::new(cue_media_path))
+                .map(|media_simple| radix(hash_utils::hash_media_file_info(&media_simple), 36).to_string())
+                .ok();
+            media_file_info_hash != old_file_info.cue_media_file_info_hash
+        },
+        None => false,
+    }
 }
 
-/// 生成详细的媒体信息并存储，同时提取专辑封面
-struct GenerateStorage;
+/// 生成详细的媒体信息，同时提取专辑封面
+/// 只处理新增或变化的文件
+pub struct GenerateStorage;
 impl Command for GenerateStorage {
-    fn execute(&self, context: &mut HashMap<&str, ContextData>) -> Result<()> {
-        if let ContextData::FileList(simple_file_list) = context.get("simple_file_list").unwrap() {
-            // 生成详细的文件信息
-            let file_info_list: Vec<FileInfo> = simple_file_list.par_iter()
-                .map(FileInfo::from_simple)
+    fn inputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<HashedFileList>()]
+    }
+
+    fn outputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<GeneratedFileInfo>(), ContextKey::of::<SkippedFiles>()]
+    }
+
+    fn execute(&self, context: &mut Context, progress: &Progress, cancel: &CancellationToken) -> Result<()> {
+        let HashedFileList(simple_file_list) = context.require::<HashedFileList>()?;
+        let refresh: &[UpdateScope] = context.get::<RefreshScopes>().map(|RefreshScopes(scopes)| scopes.as_slice()).unwrap_or_default();
+        progress.set_total(simple_file_list.len() as u64);
+        let _cover_cache = cover_utils::cache_scope();
+        // 生成详细的文件信息，取消后跳过剩余的文件，多次失败的文件跳过，下次扫描时重新生成
+        let results: Vec<Result<FileInfo, SkippedFile>> = simple_file_list.par_iter()
+            .filter(|simple| {
+                let need = !cancel.is_cancelled() && need_generate(simple, refresh);
+                if !need {
+                    progress.inc(1);
+                }
+                need
+            })
+            .filter_map(|simple| {
+                let result = generate_with_retry(simple, cancel);
+                progress.inc(1);
+                result
+            })
+            .collect();
+        // 取消时生成的文件信息不完整，不能保存
+        cancel.check()?;
+
+        let (file_info_list, skipped): (Vec<_>, Vec<_>) = results.into_iter().partition(|result| result.is_ok());
+        context.insert(GeneratedFileInfo(file_info_list.into_iter().filter_map(|result| result.ok()).collect()));
+        context.insert(SkippedFiles(skipped.into_iter().filter_map(|result| result.err()).collect()));
+        Ok(())
+    }
+}
+
+/// 生成文件信息，媒体库在网络存储上时，读取文件可能遇到暂时性的 I/O 错误，按文件重试
+/// @param simple 文件
+/// @param cancel 取消标记
+/// @return 取消时返回 None，重试后仍然失败时返回每次尝试的记录
+fn generate_with_retry(simple: &SimpleFileInfo, cancel: &CancellationToken) -> Option<Result<FileInfo, SkippedFile>> {
+    let policy = RetryPolicy::on_io_error(app_config::COMMAND_MAX_ATTEMPTS);
+    let mut attempts = Vec::new();
+    let result = retry::run_with_retry(
+        &format!("Generate file info {}", simple.path.display()),
+        &policy,
+        cancel,
+        |attempt| attempts.push(attempt),
+        |_| FileInfo::from_simple_with_cancel(simple, cancel),
+    );
+    let err = match result {
+        Err(err) => err,
+        result => return result.ok().map(Result::Ok),
+    };
+    match err {
+        RetryError::Cancelled => return None,
+        RetryError::Failed(_) if cancel.is_cancelled() => return None,
+        RetryError::Panicked(message) => println!("Skip file {} after panic: {}", simple.path.display(), message),
+        RetryError::Failed(err) => println!("Skip file {} after {} attempts: {}", simple.path.display(), attempts.len(), err),
+    }
+    Some(Err(SkippedFile { path: simple.path.to_string_lossy().to_string(), attempts }))
+}
+
+/// 保存生成的文件信息
+pub struct SaveStorage;
+impl Command for SaveStorage {
+    fn inputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<GeneratedFileInfo>()]
+    }
+
+    fn outputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<ReplacedFileInfo>()]
+    }
+
+    fn execute(&self, context: &mut Context, progress: &Progress, cancel: &CancellationToken) -> Result<()> {
+        let moved = moved_file_info_hashes(context);
+        // 保存前记录已有的文件信息，中途失败时也能恢复
+        let GeneratedFileInfo(file_info_list) = context.require::<GeneratedFileInfo>()?;
+        let replaced: Vec<FileInfo> = file_info_list.iter()
+            .filter(|file_info| !moved.contains(&file_info.file_info_hash))
+            .filter_map(|file_info| file_info::get(&file_info.file_info_hash))
+            .collect();
+        context.insert(ReplacedFileInfo(replaced));
+        let GeneratedFileInfo(file_info_list) = context.require::<GeneratedFileInfo>()?;
+        progress.set_total(file_info_list.len() as u64);
+        for file_info in file_info_list.iter().filter(|file_info| !moved.contains(&file_info.file_info_hash)) {
+            cancel.check()?;
+            file_info::set(&file_info.file_info_hash, file_info);
+            progress.inc(1);
+        }
+        progress.set_done(file_info_list.len() as u64);
+        println!("Saved {} file infos", file_info_list.len() - moved.len());
+        Ok(())
+    }
+
+    fn rollback(&self, context: &mut Context) {
+        // 恢复被覆盖的文件信息，删除新增的文件信息
+        if let Some(GeneratedFileInfo(file_info_list)) = context.get::<GeneratedFileInfo>() {
+            let moved = moved_file_info_hashes(context);
+            let replaced: HashMap<&String, &FileInfo> = context.get::<ReplacedFileInfo>()
+                .map(|ReplacedFileInfo(replaced)| replaced.iter().map(|file_info| (&file_info.file_info_hash, file_info)).collect())
+                .unwrap_or_default();
+            for file_info in file_info_list.iter().filter(|file_info| !moved.contains(&file_info.file_info_hash)) {
+                match replaced.get(&file_info.file_info_hash) {
+                    Some(old_file_info) => file_info::set(&old_file_info.file_info_hash, old_file_info),
+                    None => file_info::remove(&file_info.file_info_hash),
+                }
+            }
+        }
+    }
+}
+
+/// 移动后的文件信息 Hash，这些文件信息由 DetectMoves 保存
+fn moved_file_info_hashes(context: &Context) -> HashSet<String> {
+    context.get::<MovedFileInfo>()
+        .map(|MovedFileInfo(moved)| moved.iter().map(|(_, file_info)| file_info.file_info_hash.clone()).collect())
+        .unwrap_or_default()
+}
+
+/// 撤销移动，恢复移动前的文件信息
+fn undo_moves(moved: &[(FileInfo, FileInfo)]) {
+    for (old_file_info, file_info) in moved.iter().rev() {
+        file_info::rename(&file_info.file_info_hash, old_file_info);
+    }
+}
+
+/// 音频数据的标识，音频数据相同的文件视为同一个文件
+/// cuesheet 引用其他文件，不参与移动检测
+fn audio_identity(file_info: &FileInfo) -> Option<String> {
+    let is_plain_audio = file_info.cue_media_path.is_none()
+        && !file_info.medias.is_empty()
+        && file_info.medias.iter().all(|media| !media.audio_hash.is_empty());
+    if !is_plain_audio {
+        return None;
+    }
+    let audio_hashes: Vec<&str> = file_info.medias.iter().map(|media| media.audio_hash.as_str()).collect();
+    Some(audio_hashes.join(","))
+}
+
+/// 根据音频数据 Hash 找出移动或重命名的文件
+/// 新生成的文件信息与已经不存在的文件信息音频数据相同时视为移动，替换原来的文件信息并记录为移动
+/// 多个已经不存在的文件音频数据相同时无法判断，按删除和新增处理
+pub struct DetectMoves;
+impl Command for DetectMoves {
+    fn inputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<HashedFileList>(), ContextKey::of::<GeneratedFileInfo>()]
+    }
+
+    fn outputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<MovedFileInfo>()]
+    }
+
+    fn execute(&self, context: &mut Context, _progress: &Progress, cancel: &CancellationToken) -> Result<()> {
+        let HashedFileList(simple_file_list) = context.require::<HashedFileList>()?;
+        let GeneratedFileInfo(file_info_list) = context.require::<GeneratedFileInfo>()?;
+        // 增量更新时只有更新范围内的文件可能已经不存在
+        let scopes = context.get::<UpdateScopes>();
+        let file_info_hash_set: HashSet<&String> = simple_file_list.iter()
+            .filter_map(|simple_file_info| simple_file_info.file_info_hash.as_ref())
+            .collect();
+        // 已经不存在的文件，音频数据相同的文件有多个时为 None
+        let mut disappeared: HashMap<String, Option<FileInfo>> = HashMap::new();
+        for (old_file_info_hash, old_file_info) in file_info::list() {
+            let in_scope = scopes
+                .map(|UpdateScopes(scopes)| {
+                    let path = file_utils::list_to_path(&old_file_info.path);
+                    scopes.iter().any(|scope| scope.contains(&path))
+                })
+                .unwrap_or(true);
+            if !in_scope || file_info_hash_set.contains(&old_file_info_hash) {
+                continue;
+            }
+            if let Some(identity) = audio_identity(&old_file_info) {
+                disappeared.entry(identity)
+                    .and_modify(|old| *old = None)
+                    .or_insert(Some(old_file_info));
+            }
+        }
+
+        let mut moved_file_info_list: Vec<(FileInfo, FileInfo)> = Vec::new();
+        for file_info in file_info_list {
+            cancel.check()?;
+            // 刷新的文件仍然在原来的位置，不是移动后的文件
+            if file_info::get(&file_info.file_info_hash).is_some() {
+                continue;
+            }
+            let old_file_info = audio_identity(file_info)
+                .and_then(|identity| disappeared.remove(&identity))
+                .flatten();
+            if let Some(old_file_info) = old_file_info {
+                file_info::rename(&old_file_info.file_info_hash, file_info);
+                moved_file_info_list.push((old_file_info, file_info.clone()));
+            }
+        }
+        println!("Detected {} moved files", moved_file_info_list.len());
+        context.insert(MovedFileInfo(moved_file_info_list));
+        Ok(())
+    }
+
+    fn rollback(&self, context: &mut Context) {
+        if let Some(MovedFileInfo(moved)) = context.get::<MovedFileInfo>() {
+            undo_moves(moved);
+        }
+    }
+}
+
+/// 删除源文件已经不在媒体库中的转码缓存
+pub struct CleanTranscodeCache;
+impl Command for CleanTranscodeCache {
+    fn execute(&self, _context: &mut Context, _progress: &Progress, _cancel: &CancellationToken) -> Result<()> {
+        transcode_cache::prune_orphans();
+        Ok(())
+    }
+}
+
+/// 淘汰超出容量的转码缓存
+pub struct EvictTranscodeCache;
+impl Command for EvictTranscodeCache {
+    fn execute(&self, _context: &mut Context, _progress: &Progress, _cancel: &CancellationToken) -> Result<()> {
+        transcode_cache::evict(app_config::TRANSCODE_CACHE_MAX_SIZE);
+        Ok(())
+    }
+}
+
+/// 压缩变更记录，只保留最新的若干条
+pub struct CompactChangeLog;
+impl Command for CompactChangeLog {
+    fn execute(&self, _context: &mut Context, _progress: &Progress, _cancel: &CancellationToken) -> Result<()> {
+        change_log::compact(app_config::CHANGE_LOG_MAX_ENTRIES);
+        Ok(())
+    }
+}
+
+/// 检查数据完整性
+/// 媒体文件已经不存在或变化、封面图片已经丢失时，增量更新这些文件所在的目录，
+/// 删除缓存文件已经不存在的转码缓存记录
+pub struct CheckIntegrity;
+impl Command for CheckIntegrity {
+    fn outputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<JobResult>()]
+    }
+
+    fn execute(&self, context: &mut Context, progress: &Progress, cancel: &CancellationToken) -> Result<()> {
+        let file_infos = file_info::list();
+        progress.set_total(file_infos.len() as u64);
+        let mut paths: Vec<PathBuf> = Vec::new();
+        let mut refresh: HashSet<PathBuf> = HashSet::new();
+        for file_info in file_infos.values() {
+            cancel.check()?;
+            let path = file_utils::list_to_path(&file_info.path);
+            let unchanged = file_utils::path_to_simple_file_info(&path)
+                .map(|simple| simple.size == file_info.size && simple.last_modified == file_info.last_modified)
+                .unwrap_or(false);
+            if !unchanged {
+                paths.push(path);
+            } else if file_info.cover_hash.as_ref().is_some_and(|cover_hash| !app_config::ORIGIN_COVER_PATH.join(cover_hash).is_file()) {
+                refresh.extend(path.parent().map(Path::to_path_buf));
+            }
+            progress.inc(1);
+        }
+        let mut result = serde_json::json!({
+            "checked": file_infos.len(),
+            "changedFiles": paths.len(),
+            "missingCovers": refresh.len(),
+            "missingTranscodeCache": transcode_cache::remove_missing(),
+        });
+        if !paths.is_empty() || !refresh.is_empty() {
+            let args = UpdateArgs { paths, renames: Vec::new(), refresh: refresh.into_iter().collect() };
+            let job = enqueue("update-files", serde_json::to_value(args)?, RecoveryPolicy::Resume)?;
+            result["updateJob"] = job.id().into();
+        }
+        println!("Integrity check finished: {}", result);
+        context.insert(JobResult(result));
+        Ok(())
+    }
+}
+
+/// 汇总扫描结果
+pub struct SummarizeScan;
+impl Command for SummarizeScan {
+    fn inputs(&self) -> Vec<ContextKey> {
+        vec![
+            ContextKey::of::<HashedFileList>(),
+            ContextKey::of::<GeneratedFileInfo>(),
+            ContextKey::of::<RemovedFileInfo>(),
+        ]
+    }
+
+    fn outputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<JobResult>()]
+    }
+
+    fn execute(&self, context: &mut Context, _progress: &Progress, _cancel: &CancellationToken) -> Result<()> {
+        let mut result = serde_json::json!({
+            "scanned": context.require::<HashedFileList>()?.0.len(),
+            "generated": context.require::<GeneratedFileInfo>()?.0.len(),
+            "removed": context.require::<RemovedFileInfo>()?.0.len(),
+        });
+        if let Some(RenamedFileInfo(renamed)) = context.get::<RenamedFileInfo>() {
+            result["renamed"] = renamed.len().into();
+        }
+        if let Some(MovedFileInfo(moved)) = context.get::<MovedFileInfo>() {
+            result["moved"] = moved.len().into();
+        }
+        // 重试后仍然失败的文件，只列出前面的一部分
+        if let Some(SkippedFiles(skipped)) = context.get::<SkippedFiles>().filter(|SkippedFiles(skipped)| !skipped.is_empty()) {
+            result["skipped"] = skipped.len().into();
+            result["skippedFiles"] = serde_json::to_value(skipped.iter().take(app_config::SCAN_WARNING_REPORT_LIMIT).collect::<Vec<_>>())?;
+        }
+        // 标签或封面有问题的文件，只列出前面的一部分
+        let GeneratedFileInfo(file_info_list) = context.require::<GeneratedFileInfo>()?;
+        let warned: Vec<&FileInfo> = file_info_list.iter().filter(|file_info| !file_info.warnings.is_empty()).collect();
+        if !warned.is_empty() {
+            result["warned"] = warned.len().into();
+            result["warnings"] = warned.iter()
+                .take(app_config::SCAN_WARNING_REPORT_LIMIT)
+                .map(|file_info| serde_json::json!({
+                    "path": file_info.path.join("/"),
+                    "warnings": file_info.warnings,
+                }))
                 .collect();
+        }
+        println!("Scan finished: {}", result);
+        context.insert(JobResult(result));
+        Ok(())
+    }
+}
+
+/// 创建扫描媒体库的动作
+/// 扫描文件，为新增或变化的文件生成文件信息并保存，删除已经不存在的文件信息
+pub fn create_scan_action() -> Box<Action> {
+    let mut action = Box::new(Action::new());
+    let scan = action.add_command_after(Box::new(ScanMediaFile), &[]);
+    let hash = action.add_command_after(Box::new(CalcFileInfoHash), &[scan]);
+    let generate = action.add_command_after(Box::new(GenerateStorage), &[hash]);
+    // 先找出移动的文件，清理旧的文件信息时不会删除移动前的文件信息
+    let detect = action.add_command_after(Box::new(DetectMoves), &[generate]);
+    // 保存新的文件信息和清理旧的文件信息互不影响，并行执行
+    let save = action.add_command_after(Box::new(SaveStorage), &[detect]);
+    let clean = action.add_command_after(Box::new(CleanStorage), &[detect]);
+    // 根据保存后的文件信息清理转码缓存
+    action.add_command_after(Box::new(CleanTranscodeCache), &[save, clean]);
+    action.add_command_after(Box::new(SummarizeScan), &[save, clean]);
+    action.set_name("scan");
+    action.set_class(JobClass::Scan);
+    action
+}
+
+/// 创建淘汰转码缓存的动作
+pub fn create_evict_transcode_cache_action() -> Box<Action> {
+    let mut action = action![EvictTranscodeCache];
+    action.set_name("evict-transcode-cache");
+    action.set_class(JobClass::Maintenance);
+    action
+}
+
+/// 创建压缩变更记录的动作
+pub fn create_compact_change_log_action() -> Box<Action> {
+    let mut action = action![CompactChangeLog];
+    action.set_name("compact-change-log");
+    action.set_class(JobClass::Maintenance);
+    action
+}
+
+/// 创建检查数据完整性的动作
+pub fn create_check_integrity_action() -> Box<Action> {
+    let mut action = action![CheckIntegrity];
+    action.set_name("check-integrity");
+    action.set_class(JobClass::Maintenance);
+    action
+}
+
+/// 扫描媒体库
+/// 扫描任务会持久化，重启后重新扫描
+/// @return 新的扫描任务，正在扫描时返回 Err 和正在执行的扫描任务
+pub fn scan_library() -> std::result::Result<JobHandle, JobHandle> {
+    enqueue_exclusive("scan", serde_json::Value::Null, RecoveryPolicy::Resume)
+        .expect("Scan action is registered")
+}
+
+/// 批量转码参数
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscodeArgs {
+    /// 转码预设名称（例如: "opus-128"）
+    pub preset: String,
+    /// 需要转码的文件信息 Hash，不指定时转码所有文件
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_info_hashes: Option<Vec<String>>,
+}
+
+/// 批量转码到转码缓存
+/// 已经有缓存的文件直接跳过，中断后重新执行相当于继续转码
+/// 与播放时一样，只缓存单个音频文件，不处理 cuesheet
+/// 转码结果是缓存，失败时不需要回滚
+pub struct TranscodeFiles {
+    preset: &'static Preset,
+    file_info_hashes: Option<Vec<String>>,
+}
+impl Command for TranscodeFiles {
+    fn outputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<JobResult>()]
+    }
 
-            context.insert("file_info", ContextData::FileInfo(file_info_list));
+    fn execute(&self, context: &mut Context, progress: &Progress, cancel: &CancellationToken) -> Result<()> {
+        let file_info_list: Vec<FileInfo> = match &self.file_info_hashes {
+            Some(file_info_hashes) => file_info_hashes.iter().filter_map(|file_info_hash| file_info::find(file_info_hash)).collect(),
+            None => file_info::list().into_values().collect(),
+        };
+        let targets: Vec<(String, PathBuf)> = file_info_list.iter()
+            .filter_map(|file_info| match (&file_info.cue_media_path, file_info.medias.as_slice()) {
+                (None, [media]) if !media.audio_hash.is_empty() => {
+                    let media_path = file_utils::resolve_media_path(&file_info.path).ok()?;
+                    Some((media.audio_hash.clone(), media_path))
+                },
+                _ => None,
+            })
+            .collect();
+        progress.set_total(targets.len() as u64);
+
+        let variant = &self.preset.name;
+        let extension = self.preset.stream_format.extension;
+        let cached = AtomicU64::new(0);
+        let transcoded = AtomicU64::new(0);
+        let failed = AtomicU64::new(0);
+        targets.par_iter().for_each(|(audio_hash, media_path)| {
+            if cancel.is_cancelled() {
+                return;
+            }
+            if transcode_cache::get(audio_hash, variant).is_some() {
+                cached.fetch_add(1, Ordering::Relaxed);
+            } else {
+                let transcoder = self.preset.transcoder(cancel);
+                let result = transcode_cache::get_or_transcode(audio_hash, variant, extension, |path| {
+                    transcoder.transcode(media_path, &path.to_path_buf())
+                });
+                match result {
+                    Err(err) if cancellation::is_cancelled(&err) => return,
+                    Err(err) => {
+                        println!("Failed to transcode {}: {}", media_path.display(), err);
+                        failed.fetch_add(1, Ordering::Relaxed);
+                    },
+                    _ => {
+                        transcoded.fetch_add(1, Ordering::Relaxed);
+                    },
+                }
+            }
+            progress.inc(1);
+        });
+        cancel.check()?;
+
+        let result = serde_json::json!({
+            "preset": variant,
+            "transcoded": transcoded.load(Ordering::Relaxed),
+            "cached": cached.load(Ordering::Relaxed),
+            "failed": failed.load(Ordering::Relaxed),
+        });
+        println!("Transcode finished: {}", result);
+        context.insert(JobResult(result));
+        Ok(())
+    }
+}
+
+/// 创建批量转码的动作
+/// @param args 批量转码参数 TranscodeArgs
+pub fn create_transcode_action(args: &serde_json::Value) -> Result<Box<Action>> {
+    let args: TranscodeArgs = serde_json::from_value(args.clone())?;
+    let preset = transcode_preset::find(&args.preset)
+        .ok_or_else(|| anyhow!("Unknown transcode preset: {}", args.preset))?;
+    let mut action = action![TranscodeFiles { preset, file_info_hashes: args.file_info_hashes }];
+    action.set_name("transcode");
+    action.set_class(JobClass::Transcode);
+    Ok(action)
+}
+
+/// 重命名或移动的路径
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenamedPath {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// 增量更新参数，路径都相对于媒体目录
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateArgs {
+    /// 新增、修改或删除的文件或目录
+    pub paths: Vec<PathBuf>,
+    /// 重命名或移动的文件或目录
+    pub renames: Vec<RenamedPath>,
+    /// 封面图片或编码设置变化的目录，目录下的文件没有变化也重新生成文件信息
+    #[serde(default)]
+    pub refresh: Vec<PathBuf>,
+}
+
+/// 增量更新的范围
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateScope {
+    /// 目录
+    pub path: PathBuf,
+    /// 是否包含子目录
+    pub recursive: bool,
+}
+
+impl UpdateScope {
+    /// 根据变化的路径确定需要重新扫描的范围
+    /// 目录包含所有子目录，文件只包含所在目录下的文件（cuesheet 和整轨文件通常在同一目录），
+    /// 已经删除的路径只用于删除文件信息
+    /// @param path 相对于媒体目录的路径
+    pub fn of(path: &Path) -> UpdateScope {
+        let full_path = PathBuf::from(app_config::AUDIO_PATH).join(path);
+        match path.parent() {
+            Some(parent) if full_path.is_file() => UpdateScope { path: parent.to_path_buf(), recursive: false },
+            _ => UpdateScope { path: path.to_path_buf(), recursive: true },
         }
+    }
+
+    /// 文件是否在范围内
+    /// @param path 相对于媒体目录的文件路径
+    pub fn contains(&self, path: &Path) -> bool {
+        if self.recursive {
+            path.starts_with(&self.path)
+        } else {
+            path.parent() == Some(self.path.as_path())
+        }
+    }
+}
+
+/// 扫描变化的路径所在的目录和需要刷新的目录
+pub struct ListChangedFiles {
+    paths: Vec<PathBuf>,
+    refresh: Vec<PathBuf>,
+}
+impl Command for ListChangedFiles {
+    fn outputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<SimpleFileList>(), ContextKey::of::<UpdateScopes>(), ContextKey::of::<RefreshScopes>()]
+    }
+
+    fn execute(&self, context: &mut Context, progress: &Progress, _cancel: &CancellationToken) -> Result<()> {
+        // 封面和编码设置对子目录也生效
+        let refresh: Vec<UpdateScope> = self.refresh.iter()
+            .map(|path| UpdateScope { path: path.clone(), recursive: true })
+            .collect();
+        let mut scopes: Vec<UpdateScope> = Vec::new();
+        for scope in self.paths.iter().map(|path| UpdateScope::of(path)).chain(refresh.iter().cloned()) {
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+        let mut listed: HashSet<PathBuf> = HashSet::new();
+        let audio_file_list: Vec<SimpleFileInfo> = scopes.iter()
+            .flat_map(|scope| file_utils::list_audio_file_in(&scope.path, scope.recursive))
+            .filter(|simple| listed.insert(simple.path.clone()))
+            .collect();
+        progress.set_total(audio_file_list.len() as u64);
+        progress.set_done(audio_file_list.len() as u64);
+        context.insert(SimpleFileList(audio_file_list));
+        context.insert(UpdateScopes(scopes));
+        context.insert(RefreshScopes(refresh));
         Ok(())
     }
+}
+
+/// 重命名或移动的文件大小和修改时间没有变化时，沿用原来的文件信息，不需要重新计算音频 Hash
+/// cuesheet 引用整轨文件的路径，总是重新生成
+pub struct CarryOverRenamed {
+    renames: Vec<RenamedPath>,
+}
+impl Command for CarryOverRenamed {
+    fn inputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<HashedFileList>()]
+    }
+
+    fn outputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<RenamedFileInfo>()]
+    }
+
+    fn execute(&self, context: &mut Context, _progress: &Progress, cancel: &CancellationToken) -> Result<()> {
+        let HashedFileList(simple_file_list) = context.require::<HashedFileList>()?;
+        let mut renamed_file_info_list: Vec<(FileInfo, FileInfo)> = Vec::new();
+        if !self.renames.is_empty() {
+            let old_file_infos: HashMap<PathBuf, FileInfo> = file_info::list().into_values()
+                .map(|file_info| (file_utils::list_to_path(&file_info.path), file_info))
+                .collect();
+            for simple in simple_file_list {
+                cancel.check()?;
+                let file_info_hash = match &simple.file_info_hash {
+                    Some(file_info_hash) if file_info::get(file_info_hash).is_none() => file_info_hash,
+                    _ => continue,
+                };
+                let old_file_info = self.renames.iter()
+                    .filter_map(|rename| simple.path.strip_prefix(&rename.to).ok().map(|suffix| rename.from.join(suffix)))
+                    .find_map(|old_path| old_file_infos.get(&old_path))
+                    .filter(|old| old.cue_media_path.is_none() && old.size == simple.size && old.last_modified == simple.last_modified);
+                if let Some(old_file_info) = old_file_info {
+                    let mut file_info = old_file_info.clone();
+                    file_info.path = file_utils::path_to_list(&simple.path);
+                    file_info.file_info_hash = file_info_hash.clone();
+                    file_info::rename(&old_file_info.file_info_hash, &file_info);
+                    renamed_file_info_list.push((old_file_info.clone(), file_info));
+                }
+            }
+        }
+        println!("Carried over {} renamed file infos", renamed_file_info_list.len());
+        context.insert(RenamedFileInfo(renamed_file_info_list));
+        Ok(())
+    }
+
+    fn rollback(&self, context: &mut Context) {
+        if let Some(RenamedFileInfo(renamed)) = context.get::<RenamedFileInfo>() {
+            undo_moves(renamed);
+        }
+    }
+}
+
+/// 创建增量更新的动作
+/// 只扫描变化的路径所在的目录，重命名的文件沿用原来的文件信息
+/// @param args 增量更新参数 UpdateArgs
+pub fn create_update_action(args: &serde_json::Value) -> Result<Box<Action>> {
+    let args: UpdateArgs = serde_json::from_value(args.clone())?;
+    let mut paths = args.paths;
+    for rename in &args.renames {
+        paths.push(rename.from.clone());
+        paths.push(rename.to.clone());
+    }
+    let mut action = Box::new(Action::new());
+    let list = action.add_command_after(Box::new(ListChangedFiles { paths, refresh: args.refresh }), &[]);
+    let hash = action.add_command_after(Box::new(CalcFileInfoHash), &[list]);
+    // 先沿用重命名前的文件信息，再删除原来的文件信息
+    let carry_over = action.add_command_after(Box::new(CarryOverRenamed { renames: args.renames }), &[hash]);
+    let generate = action.add_command_after(Box::new(GenerateStorage), &[carry_over]);
+    let detect = action.add_command_after(Box::new(DetectMoves), &[generate]);
+    let save = action.add_command_after(Box::new(SaveStorage), &[detect]);
+    let clean = action.add_command_after(Box::new(CleanStorage), &[detect]);
+    action.add_command_after(Box::new(SummarizeScan), &[save, clean]);
+    action.set_name("update-files");
+    action.set_class(JobClass::Scan);
+    Ok(action)
 }
\ No newline at end of file
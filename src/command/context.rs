@@ -0,0 +1,168 @@
+use std::{any::{Any, TypeId}, collections::HashMap, fmt, sync::{Arc, Weak}};
+
+use thiserror::Error;
+
+/// 上下文数据的键，由数据类型决定
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContextKey {
+    type_id: TypeId,
+    name: &'static str,
+}
+
+impl ContextKey {
+    /// 获取数据类型对应的键
+    pub fn of<T: Any>() -> ContextKey {
+        ContextKey {
+            type_id: TypeId::of::<T>(),
+            name: short_type_name::<T>(),
+        }
+    }
+
+    /// 数据类型名称
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl fmt::Display for ContextKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+/// 去掉模块路径的类型名称
+pub fn short_type_name<T: ?Sized>() -> &'static str {
+    let type_name = std::any::type_name::<T>();
+    type_name.rsplit("::").next().unwrap_or(type_name)
+}
+
+/// 上下文错误
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ContextError {
+    /// 命令需要的数据没有由依赖的命令产生
+    #[error("Command {command} requires {input}, which is not produced by any command it depends on")]
+    MissingInput { command: String, input: &'static str },
+    /// 命令依赖的命令不在它前面
+    #[error("Command {command} depends on command {dependency}, which is not added before it")]
+    InvalidDependency { command: String, dependency: usize },
+    /// 上下文中没有数据
+    #[error("Context has no {0}")]
+    Missing(&'static str),
+    /// 数据正在被并行执行的命令读取，不能取出或修改
+    #[error("Context data {0} is shared with concurrent commands")]
+    Shared(&'static str),
+}
+
+/// 任务结果，放在上下文中的结果会作为任务的执行结果
+#[derive(Debug, Clone)]
+pub struct JobResult(pub serde_json::Value);
+
+/// 动作上下文
+/// 以数据类型为键，同一个动作中的命令通过上下文共享数据
+/// 每种数据使用单独的类型（例如: `struct SimpleFileList(Vec<SimpleFileInfo>)`），新的命令不需要修改这个模块
+/// 并行执行的命令共享同一份数据，只有不再被其他命令需要的数据才能取出或修改
+#[derive(Default)]
+pub struct Context {
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+    /// fork 时共享的数据，合并时跳过没有替换的数据
+    forked: HashMap<TypeId, Weak<dyn Any + Send + Sync>>,
+}
+
+impl Context {
+    pub fn new() -> Context {
+        Context::default()
+    }
+
+    /// 放入数据，返回原来的数据
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) -> Option<T> {
+        self.values.insert(TypeId::of::<T>(), Arc::new(value))
+            .and_then(|old| old.downcast::<T>().ok())
+            .and_then(|old| Arc::try_unwrap(old).ok())
+    }
+
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.values.get(&TypeId::of::<T>()).and_then(|value| value.downcast_ref::<T>())
+    }
+
+    /// 获取可修改的数据，数据被共享时返回 None
+    pub fn get_mut<T: Any + Send + Sync>(&mut self) -> Option<&mut T> {
+        self.values.get_mut(&TypeId::of::<T>())
+            .and_then(Arc::get_mut)
+            .and_then(|value| value.downcast_mut::<T>())
+    }
+
+    /// 取出数据，数据被共享时返回 None
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<T> {
+        let type_id = TypeId::of::<T>();
+        if self.is_shared(&type_id) {
+            return None;
+        }
+        self.values.remove(&type_id)
+            .and_then(|value| value.downcast::<T>().ok())
+            .and_then(|value| Arc::try_unwrap(value).ok())
+    }
+
+    /// 是否有数据
+    pub fn contains(&self, key: &ContextKey) -> bool {
+        self.values.contains_key(&key.type_id)
+    }
+
+    fn is_shared(&self, type_id: &TypeId) -> bool {
+        self.values.get(type_id).map(|value| Arc::strong_count(value) > 1).unwrap_or(false)
+    }
+
+    /// 获取命令声明的输入数据
+    /// @return 没有数据时返回 ContextError::Missing
+    pub fn require<T: Any + Send + Sync>(&self) -> Result<&T, ContextError> {
+        self.get::<T>().ok_or(ContextError::Missing(short_type_name::<T>()))
+    }
+
+    /// 取出命令声明的输入数据
+    /// @return 没有数据时返回 ContextError::Missing，数据被共享时返回 ContextError::Shared
+    pub fn take<T: Any + Send + Sync>(&mut self) -> Result<T, ContextError> {
+        if self.is_shared(&TypeId::of::<T>()) {
+            return Err(ContextError::Shared(short_type_name::<T>()));
+        }
+        self.remove::<T>().ok_or(ContextError::Missing(short_type_name::<T>()))
+    }
+
+    /// 获取命令声明的输入数据（可修改）
+    /// @return 没有数据时返回 ContextError::Missing，数据被共享时返回 ContextError::Shared
+    pub fn require_mut<T: Any + Send + Sync>(&mut self) -> Result<&mut T, ContextError> {
+        if self.is_shared(&TypeId::of::<T>()) {
+            return Err(ContextError::Shared(short_type_name::<T>()));
+        }
+        self.get_mut::<T>().ok_or(ContextError::Missing(short_type_name::<T>()))
+    }
+
+    /// 为开始执行的命令创建上下文
+    /// 指定的数据移动到新的上下文中，其他数据与新的上下文共享
+    /// @param moved 移动的数据，其他命令不再需要
+    pub(crate) fn fork(&mut self, moved: &[ContextKey]) -> Context {
+        let mut context = Context::new();
+        for key in moved {
+            if let Some(value) = self.values.remove(&key.type_id) {
+                context.values.insert(key.type_id, value);
+            }
+        }
+        for (type_id, value) in &self.values {
+            context.values.insert(*type_id, value.clone());
+            context.forked.insert(*type_id, Arc::downgrade(value));
+        }
+        context
+    }
+
+    /// 合并执行结束的命令的上下文，命令放入或替换的数据覆盖当前数据
+    /// @param context fork 创建的上下文
+    pub(crate) fn merge(&mut self, context: Context) {
+        for (type_id, value) in context.values {
+            let unchanged = context.forked.get(&type_id)
+                .and_then(Weak::upgrade)
+                .map(|forked| Arc::ptr_eq(&forked, &value))
+                .unwrap_or(false);
+            if !unchanged {
+                self.values.insert(type_id, value);
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,460 @@
+use std::{
+    collections::BTreeMap,
+    sync::{atomic::{AtomicU64, Ordering}, Arc, Condvar, Mutex},
+    time::SystemTime,
+};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{config::app_config, infra::{cancellation::CancellationToken, time_utils}, repository::job_queue};
+
+/// 保留的已结束任务数量
+static MAX_FINISHED_JOBS: usize = 100;
+
+/// 所有任务
+static JOBS: Lazy<Mutex<BTreeMap<u64, Arc<Job>>>> = Lazy::new(|| {
+    Mutex::new(BTreeMap::new())
+});
+
+/// 线程池
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResourcePool {
+    /// 以读写文件为主的任务，线程数较少，避免机械硬盘频繁寻道
+    Io,
+    /// 以计算为主的任务，线程数与 CPU 核心数相同
+    Cpu,
+    /// 用户等待结果的短任务，与批量任务分开，不会被批量任务内部的并行计算占满
+    Interactive,
+}
+
+/// 任务类型
+/// 每种类型有单独的并发数和优先级，优先级高的类型在竞争时分到更多执行机会
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobClass {
+    /// 用户等待结果的短任务（例如生成封面缩略图）
+    #[default]
+    Interactive,
+    /// 扫描媒体库
+    Scan,
+    /// 批量转码
+    Transcode,
+    /// 后台维护（例如淘汰转码缓存、检查数据完整性）
+    Maintenance,
+    /// 边转码边播放，执行时间与播放时长相近
+    Stream,
+}
+
+impl JobClass {
+    pub const ALL: [JobClass; 5] = [JobClass::Interactive, JobClass::Scan, JobClass::Transcode, JobClass::Maintenance, JobClass::Stream];
+
+    /// 执行任务的线程池
+    pub fn pool(&self) -> ResourcePool {
+        match self {
+            JobClass::Interactive => ResourcePool::Interactive,
+            JobClass::Scan => ResourcePool::Io,
+            JobClass::Transcode => ResourcePool::Cpu,
+            JobClass::Maintenance => ResourcePool::Io,
+            JobClass::Stream => ResourcePool::Cpu,
+        }
+    }
+
+    /// 同时执行的任务数
+    pub fn max_concurrency(&self) -> usize {
+        match self {
+            JobClass::Interactive => app_config::INTERACTIVE_JOB_CONCURRENCY,
+            JobClass::Scan => app_config::SCAN_JOB_CONCURRENCY,
+            JobClass::Transcode => app_config::TRANSCODE_JOB_CONCURRENCY,
+            JobClass::Maintenance => app_config::MAINTENANCE_JOB_CONCURRENCY,
+            JobClass::Stream => app_config::STREAM_JOB_CONCURRENCY,
+        }
+    }
+
+    /// 优先级，至少为 1
+    pub fn priority(&self) -> u32 {
+        match self {
+            JobClass::Interactive => app_config::INTERACTIVE_JOB_PRIORITY,
+            JobClass::Scan => app_config::SCAN_JOB_PRIORITY,
+            JobClass::Transcode => app_config::TRANSCODE_JOB_PRIORITY,
+            JobClass::Maintenance => app_config::MAINTENANCE_JOB_PRIORITY,
+            JobClass::Stream => app_config::STREAM_JOB_PRIORITY,
+        }.max(1)
+    }
+}
+
+/// 任务状态
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobStatus {
+    /// 等待执行
+    Queued,
+    /// 正在执行
+    Running,
+    /// 执行成功
+    Succeeded,
+    /// 执行失败，回滚时也出错
+    Failed,
+    /// 执行失败，已经回滚
+    RolledBack,
+    /// 已取消，执行过的命令已经回滚
+    Cancelled,
+}
+
+impl JobStatus {
+    /// 是否已经结束
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobStatus::Succeeded | JobStatus::Failed | JobStatus::RolledBack | JobStatus::Cancelled)
+    }
+}
+
+/// 任务错误
+#[derive(Debug, Error, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum JobError {
+    /// 命令返回错误
+    #[error("Command {command} failed: {message}")]
+    CommandFailed { command: String, message: String },
+    /// 命令执行时 panic
+    #[error("Command {command} panicked: {message}")]
+    CommandPanicked { command: String, message: String },
+    /// 动作中命令的输入数据没有由前面的命令产生
+    #[error("Invalid action: {message}")]
+    InvalidAction { message: String },
+    /// 任务因重启中断，按恢复方式放弃执行
+    #[error("Interrupted by restart after {runs} runs")]
+    Interrupted { runs: u32 },
+    /// 命令执行超时
+    #[error("Command {command} timed out after {timeout}ms")]
+    CommandTimedOut { command: String, timeout: u128 },
+    /// 执行命令时任务被取消
+    #[error("Cancelled while executing command {command}")]
+    Cancelled { command: String },
+}
+
+/// 命令进度
+/// 命令执行时更新，总数未知时为 0
+#[derive(Debug, Default)]
+pub struct Progress {
+    done: AtomicU64,
+    total: AtomicU64,
+}
+
+impl Progress {
+    /// 设置总数
+    pub fn set_total(&self, total: u64) {
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    /// 增加完成数
+    pub fn inc(&self, count: u64) {
+        self.done.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// 设置完成数
+    pub fn set_done(&self, done: u64) {
+        self.done.store(done, Ordering::Relaxed);
+    }
+
+    pub fn done(&self) -> u64 {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+}
+
+/// 命令执行状态
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommandStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    RolledBack,
+}
+
+/// 命令的一次尝试
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AttemptInfo {
+    /// 第几次尝试，从 1 开始
+    pub attempt: u32,
+    /// 开始时间
+    pub started_at: u128,
+    /// 结束时间
+    pub finished_at: u128,
+    /// 错误，成功时为 None
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 命令执行信息
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandInfo {
+    /// 命令名称
+    pub name: String,
+    /// 执行状态
+    pub status: CommandStatus,
+    /// 完成数
+    pub done: u64,
+    /// 总数
+    pub total: u64,
+    /// 执行记录，重试时有多条
+    pub attempts: Vec<AttemptInfo>,
+}
+
+/// 任务信息
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct JobInfo {
+    /// 任务编号
+    pub id: u64,
+    /// 任务名称
+    pub name: String,
+    /// 任务类型
+    pub class: JobClass,
+    /// 任务状态
+    pub status: JobStatus,
+    /// 命令执行信息
+    pub commands: Vec<CommandInfo>,
+    /// 执行结果
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    /// 错误
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<JobError>,
+    /// 创建时间
+    pub created_at: u128,
+    /// 开始时间
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<u128>,
+    /// 结束时间
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u128>,
+}
+
+struct JobState {
+    info: JobInfo,
+    progress: Vec<Arc<Progress>>,
+}
+
+/// 任务
+/// 记录一个动作的执行状态
+pub struct Job {
+    state: Mutex<JobState>,
+    finished: Condvar,
+    cancel: CancellationToken,
+}
+
+fn now() -> u128 {
+    time_utils::time_to_millis(&SystemTime::now())
+}
+
+impl Job {
+    /// 创建任务并登记
+    /// @param name 任务名称
+    /// @param class 任务类型
+    /// @param command_names 命令名称
+    /// @return 任务
+    pub fn create(name: &str, class: JobClass, command_names: Vec<String>) -> Arc<Job> {
+        Job::create_with_id(job_queue::generate_id(), name, class, command_names)
+    }
+
+    /// 使用指定编号创建任务并登记，用于恢复重启前的任务
+    /// @param id 任务编号
+    /// @param name 任务名称
+    /// @param class 任务类型
+    /// @param command_names 命令名称
+    /// @return 任务
+    pub fn create_with_id(id: u64, name: &str, class: JobClass, command_names: Vec<String>) -> Arc<Job> {
+        let commands: Vec<CommandInfo> = command_names.into_iter()
+            .map(|name| CommandInfo { name, status: CommandStatus::Pending, done: 0, total: 0, attempts: Vec::new() })
+            .collect();
+        let progress = commands.iter().map(|_| Arc::new(Progress::default())).collect();
+        let job = Arc::new(Job {
+            state: Mutex::new(JobState {
+                info: JobInfo {
+                    id,
+                    name: name.to_string(),
+                    class,
+                    status: JobStatus::Queued,
+                    commands,
+                    result: None,
+                    error: None,
+                    created_at: now(),
+                    started_at: None,
+                    finished_at: None,
+                },
+                progress,
+            }),
+            finished: Condvar::new(),
+            cancel: CancellationToken::new(),
+        });
+        let mut jobs = JOBS.lock().unwrap();
+        jobs.insert(id, job.clone());
+        // 清理最早结束的任务
+        let finished_ids: Vec<u64> = jobs.iter()
+            .filter(|(_, job)| job.status().is_finished())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in finished_ids.iter().take(finished_ids.len().saturating_sub(MAX_FINISHED_JOBS)) {
+            jobs.remove(id);
+        }
+        job
+    }
+
+    pub fn id(&self) -> u64 {
+        self.state.lock().unwrap().info.id
+    }
+
+    pub fn name(&self) -> String {
+        self.state.lock().unwrap().info.name.clone()
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.state.lock().unwrap().info.status
+    }
+
+    /// 取消令牌，执行命令时传给命令
+    pub fn cancel_token(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    /// 请求取消任务
+    /// 正在执行的命令检查到取消后停止，已经执行的命令会回滚
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// 获取命令的进度
+    /// @param index 命令序号
+    pub fn progress(&self, index: usize) -> Arc<Progress> {
+        self.state.lock().unwrap().progress[index].clone()
+    }
+
+    /// 获取任务信息，包括最新的进度
+    pub fn info(&self) -> JobInfo {
+        let state = self.state.lock().unwrap();
+        let mut info = state.info.clone();
+        for (command, progress) in info.commands.iter_mut().zip(state.progress.iter()) {
+            command.done = progress.done();
+            command.total = progress.total();
+        }
+        info
+    }
+
+    /// 设置任务状态
+    pub fn set_status(&self, status: JobStatus) {
+        let mut state = self.state.lock().unwrap();
+        state.info.status = status;
+        match status {
+            JobStatus::Running => state.info.started_at = Some(now()),
+            _ if status.is_finished() => state.info.finished_at = Some(now()),
+            _ => {},
+        }
+        drop(state);
+        if status.is_finished() {
+            self.finished.notify_all();
+        }
+    }
+
+    /// 获取命令执行状态
+    /// @param index 命令序号
+    pub fn command_status(&self, index: usize) -> CommandStatus {
+        self.state.lock().unwrap().info.commands[index].status
+    }
+
+    /// 设置命令执行状态
+    /// @param index 命令序号
+    /// @param status 执行状态
+    pub fn set_command_status(&self, index: usize, status: CommandStatus) {
+        self.state.lock().unwrap().info.commands[index].status = status;
+    }
+
+    /// 记录命令的一次尝试
+    /// @param index 命令序号
+    /// @param attempt 尝试记录
+    pub fn add_attempt(&self, index: usize, attempt: AttemptInfo) {
+        self.state.lock().unwrap().info.commands[index].attempts.push(attempt);
+    }
+
+    /// 设置执行结果
+    pub fn set_result(&self, result: Option<serde_json::Value>) {
+        self.state.lock().unwrap().info.result = result;
+    }
+
+    /// 设置错误
+    pub fn set_error(&self, error: JobError) {
+        self.state.lock().unwrap().info.error = Some(error);
+    }
+
+    /// 等待任务结束
+    pub fn wait(&self) -> JobInfo {
+        let state = self.state.lock().unwrap();
+        drop(self.finished.wait_while(state, |state| !state.info.status.is_finished()).unwrap());
+        self.info()
+    }
+}
+
+/// 任务句柄
+#[derive(Clone)]
+pub struct JobHandle {
+    job: Arc<Job>,
+}
+
+impl JobHandle {
+    pub fn new(job: Arc<Job>) -> JobHandle {
+        JobHandle { job }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.job.id()
+    }
+
+    pub fn status(&self) -> JobStatus {
+        self.job.status()
+    }
+
+    pub fn info(&self) -> JobInfo {
+        self.job.info()
+    }
+
+    /// 等待任务结束
+    /// @return 任务信息
+    pub fn wait(&self) -> JobInfo {
+        self.job.wait()
+    }
+}
+
+/// 获取任务信息
+/// @param id 任务编号
+pub fn get(id: u64) -> Option<JobInfo> {
+    JOBS.lock().unwrap().get(&id).map(|job| job.info())
+}
+
+/// 取消任务
+/// @param id 任务编号
+/// @return 任务，不存在时返回 None
+pub fn cancel(id: u64) -> Option<JobHandle> {
+    let job = JOBS.lock().unwrap().get(&id).cloned()?;
+    job.cancel();
+    Some(JobHandle::new(job))
+}
+
+/// 查找没有结束的任务
+/// @param name 任务名称
+pub fn find_unfinished(name: &str) -> Option<JobHandle> {
+    JOBS.lock().unwrap().values()
+        .find(|job| !job.status().is_finished() && job.name() == name)
+        .map(|job| JobHandle::new(job.clone()))
+}
+
+/// 获取所有任务信息，按编号排序
+pub fn list() -> Vec<JobInfo> {
+    JOBS.lock().unwrap().values().map(|job| job.info()).collect()
+}
\ No newline at end of file
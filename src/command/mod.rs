@@ -1,3 +1,10 @@
 pub mod command;
 pub mod action;
 pub mod actor;
+
+pub mod job;
+pub mod context;
+pub mod registry;
+pub mod retry;
+pub mod scheduler;
+pub mod watcher;
\ No newline at end of file
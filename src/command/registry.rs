@@ -0,0 +1,41 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+
+use super::{action::Action, command};
+
+/// 根据参数创建动作
+pub type ActionFactory = fn(&serde_json::Value) -> Result<Box<Action>>;
+
+/// 可以持久化的动作，重启后根据名称和参数重新创建
+static ACTION_FACTORIES: Lazy<Mutex<HashMap<String, ActionFactory>>> = Lazy::new(|| {
+    let mut factories: HashMap<String, ActionFactory> = HashMap::new();
+    factories.insert("scan".to_string(), |_| Ok(command::create_scan_action()));
+    factories.insert("transcode".to_string(), command::create_transcode_action);
+    factories.insert("update-files".to_string(), command::create_update_action);
+    factories.insert("evict-transcode-cache".to_string(), |_| Ok(command::create_evict_transcode_cache_action()));
+    factories.insert("compact-change-log".to_string(), |_| Ok(command::create_compact_change_log_action()));
+    factories.insert("check-integrity".to_string(), |_| Ok(command::create_check_integrity_action()));
+    Mutex::new(factories)
+});
+
+/// 登记动作
+/// @param name 动作名称
+/// @param factory 根据参数创建动作
+pub fn register(name: &str, factory: ActionFactory) {
+    ACTION_FACTORIES.lock().unwrap().insert(name.to_string(), factory);
+}
+
+/// 根据名称和参数创建动作
+/// @param name 动作名称
+/// @param args 动作参数
+/// @return 动作，名称为登记的名称
+pub fn create_action(name: &str, args: &serde_json::Value) -> Result<Box<Action>> {
+    let factory = *ACTION_FACTORIES.lock().unwrap()
+        .get(name)
+        .ok_or_else(|| anyhow!("Unknown action: {}", name))?;
+    let mut action = factory(args)?;
+    action.set_name(name);
+    Ok(action)
+}
\ No newline at end of file
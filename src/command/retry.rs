@@ -0,0 +1,167 @@
+use std::{panic::{self, AssertUnwindSafe}, time::{Duration, Instant, SystemTime}};
+
+use crate::infra::{cancellation::{self, CancellationToken}, error_utils, time_utils};
+
+use super::job::AttemptInfo;
+
+/// 可以重试的错误类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// 暂时性的 I/O 错误（例如网络存储短暂不可用）
+    Io,
+    /// 命令执行超时
+    Timeout,
+    /// 除取消以外的所有错误
+    Any,
+}
+
+impl ErrorClass {
+    /// 判断错误是否属于这个类型，取消不属于任何类型
+    pub fn matches(&self, err: &anyhow::Error) -> bool {
+        if cancellation::is_cancelled(err) {
+            return false;
+        }
+        match self {
+            ErrorClass::Io => error_utils::is_transient(err),
+            ErrorClass::Timeout => cancellation::is_timed_out(err),
+            ErrorClass::Any => true,
+        }
+    }
+}
+
+/// 命令的重试策略
+/// 重试的命令需要是幂等的，失败的尝试不会回滚
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// 最多尝试次数（包括第一次）
+    pub max_attempts: u32,
+    /// 第一次重试前的等待时间，之后每次翻倍
+    pub initial_backoff: Duration,
+    /// 最长等待时间
+    pub max_backoff: Duration,
+    /// 重试的错误类型
+    pub retry_on: Vec<ErrorClass>,
+}
+
+impl RetryPolicy {
+    /// 不重试
+    pub fn none() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+            retry_on: Vec::new(),
+        }
+    }
+
+    /// 遇到暂时性的 I/O 错误时重试
+    /// @param max_attempts 最多尝试次数
+    pub fn on_io_error(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            retry_on: vec![ErrorClass::Io],
+        }
+    }
+
+    /// 第几次尝试失败后的等待时间
+    /// @param attempt 失败的尝试次数，从 1 开始
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+
+    /// 是否需要重试
+    /// @param attempt 已经尝试的次数
+    /// @param err 这次尝试的错误
+    pub fn should_retry(&self, attempt: u32, err: &anyhow::Error) -> bool {
+        attempt < self.max_attempts && self.retry_on.iter().any(|class| class.matches(err))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy::none()
+    }
+}
+
+/// 重试后仍然失败的原因
+#[derive(Debug)]
+pub enum RetryError {
+    /// 操作 panic，不重试
+    Panicked(String),
+    /// 等待重试时被取消
+    Cancelled,
+    /// 不能重试的错误，或者已经达到最多尝试次数
+    Failed(anyhow::Error),
+}
+
+/// 按重试策略执行操作，两次尝试之间等待，每次尝试都记录下来
+/// panic 不重试
+/// @param name 日志中的操作名称
+/// @param policy 重试策略
+/// @param cancel 取消令牌，等待重试时检查
+/// @param record 记录每次尝试
+/// @param operation 操作，参数为第几次尝试，从 1 开始
+/// @return 成功的结果，或者最后一次失败的原因
+pub fn run_with_retry<T>(
+    name: &str,
+    policy: &RetryPolicy,
+    cancel: &CancellationToken,
+    mut record: impl FnMut(AttemptInfo),
+    mut operation: impl FnMut(u32) -> anyhow::Result<T>,
+) -> Result<T, RetryError> {
+    let mut attempt: u32 = 0;
+    loop {
+        attempt += 1;
+        let started_at = now();
+        let err = match panic::catch_unwind(AssertUnwindSafe(|| operation(attempt))) {
+            Ok(Ok(value)) => {
+                record(AttemptInfo { attempt, started_at, finished_at: now(), error: None });
+                return Ok(value);
+            },
+            Ok(Err(err)) => err,
+            Err(payload) => {
+                let message = panic_message(payload);
+                record(AttemptInfo { attempt, started_at, finished_at: now(), error: Some(message.clone()) });
+                return Err(RetryError::Panicked(message));
+            },
+        };
+        record(AttemptInfo { attempt, started_at, finished_at: now(), error: Some(err.to_string()) });
+
+        if !policy.should_retry(attempt, &err) {
+            return Err(RetryError::Failed(err));
+        }
+        let backoff = policy.backoff(attempt);
+        println!("{} failed on attempt {}, retry in {:?}: {}", name, attempt, backoff, err);
+        if !sleep_unless_cancelled(backoff, cancel) {
+            return Err(RetryError::Cancelled);
+        }
+    }
+}
+
+/// 等待指定时间，任务被取消时提前结束
+/// @return 等待结束时没有被取消
+fn sleep_unless_cancelled(duration: Duration, cancel: &CancellationToken) -> bool {
+    let deadline = Instant::now() + duration;
+    while !cancel.is_cancelled() {
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        std::thread::sleep((deadline - now).min(Duration::from_millis(100)));
+    }
+    false
+}
+
+/// 从 panic 中取出错误信息
+pub(super) fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    payload.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Unknown panic".to_string())
+}
+
+fn now() -> u128 {
+    time_utils::time_to_millis(&SystemTime::now())
+}
\ No newline at end of file
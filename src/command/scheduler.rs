@@ -0,0 +1,181 @@
+use std::{
+    sync::{Condvar, Mutex, Once},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::{
+    config::app_config,
+    infra::{cron_utils::Schedule, time_utils},
+    model::dto::{RecoveryPolicy, ScheduleState},
+    repository::schedule,
+};
+
+use super::{actor, job::{self, JobHandle}};
+
+/// 没有需要执行的计划时最长的等待时间，避免系统时间调整后错过执行
+static MAX_SLEEP: Duration = Duration::from_secs(60);
+
+static SCHEDULER: Lazy<(Mutex<Vec<ScheduledAction>>, Condvar)> = Lazy::new(|| {
+    (Mutex::new(Vec::new()), Condvar::new())
+});
+
+static START: Once = Once::new();
+
+/// 定时执行的动作
+struct ScheduledAction {
+    action: String,
+    args: serde_json::Value,
+    schedule: Schedule,
+    state: ScheduleState,
+}
+
+/// 定时计划信息
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleInfo {
+    pub name: String,
+    pub action: String,
+    pub args: serde_json::Value,
+    pub schedule: String,
+    pub last_run: Option<u128>,
+    pub next_run: Option<u128>,
+    pub last_job_id: Option<u64>,
+    /// 上次执行的任务是否还没有结束
+    pub running: bool,
+}
+
+impl ScheduledAction {
+    fn info(&self) -> ScheduleInfo {
+        ScheduleInfo {
+            name: self.state.name.clone(),
+            action: self.action.clone(),
+            args: self.args.clone(),
+            schedule: self.state.schedule.clone(),
+            last_run: self.state.last_run,
+            next_run: self.state.next_run,
+            last_job_id: self.state.last_job_id,
+            running: self.state.last_job_id
+                .and_then(job::get)
+                .map(|info| !info.status.is_finished())
+                .unwrap_or(false),
+        }
+    }
+
+    /// 到达执行时间时执行动作，同名任务没有结束时跳过这次执行
+    /// @param now 当前时间
+    /// @return 新的任务
+    fn run_if_due(&mut self, now: u128) -> Option<JobHandle> {
+        if self.state.next_run.map(|next_run| next_run > now).unwrap_or(true) {
+            return None;
+        }
+        // 停止期间错过的多次执行只补一次
+        self.state.next_run = self.schedule.next_after(now);
+        let handle = match actor::enqueue_exclusive(&self.action, self.args.clone(), RecoveryPolicy::Discard) {
+            Ok(Ok(handle)) => {
+                println!("Run schedule {} as job {}", self.state.name, handle.id());
+                self.state.last_run = Some(now);
+                self.state.last_job_id = Some(handle.id());
+                Some(handle)
+            },
+            Ok(Err(running)) => {
+                println!("Skip schedule {}: job {} is still running", self.state.name, running.id());
+                None
+            },
+            Err(error) => {
+                println!("Failed to run schedule {}: {}", self.state.name, error);
+                None
+            },
+        };
+        schedule::set(&self.state);
+        handle
+    }
+}
+
+/// 添加定时计划，同名的计划会被替换
+/// 计划表达式没有改变时沿用重启前的执行状态，停止期间错过的执行会在启动后补上
+/// @param name 计划名称
+/// @param action 登记的动作名称
+/// @param args 动作参数
+/// @param expr 计划表达式，参考 Schedule::parse
+pub fn add(name: &str, action: &str, args: serde_json::Value, expr: &str) -> Result<()> {
+    let schedule = Schedule::parse(expr)?;
+    let now = time_utils::time_to_millis(&SystemTime::now());
+    let mut state = schedule::get(name)
+        .filter(|state| state.schedule == expr)
+        .unwrap_or_default();
+    if state.next_run.is_none() {
+        state.next_run = schedule.next_after(now);
+    }
+    state.name = name.to_string();
+    state.schedule = expr.to_string();
+    schedule::set(&state);
+
+    let (scheduled, condvar) = &*SCHEDULER;
+    let mut scheduled = scheduled.lock().unwrap();
+    scheduled.retain(|scheduled| scheduled.state.name != name);
+    scheduled.push(ScheduledAction {
+        action: action.to_string(),
+        args,
+        schedule,
+        state,
+    });
+    condvar.notify_all();
+    Ok(())
+}
+
+/// 删除定时计划
+/// @param name 计划名称
+/// @return 是否存在
+pub fn remove(name: &str) -> bool {
+    let (scheduled, condvar) = &*SCHEDULER;
+    let mut scheduled = scheduled.lock().unwrap();
+    let count = scheduled.len();
+    scheduled.retain(|scheduled| scheduled.state.name != name);
+    schedule::remove(name);
+    condvar.notify_all();
+    scheduled.len() != count
+}
+
+/// 获取所有定时计划
+pub fn list() -> Vec<ScheduleInfo> {
+    SCHEDULER.0.lock().unwrap().iter().map(ScheduledAction::info).collect()
+}
+
+/// 执行到达执行时间的计划
+/// @param now 当前时间
+/// @return 新的任务
+pub fn run_pending(now: u128) -> Vec<JobHandle> {
+    SCHEDULER.0.lock().unwrap().iter_mut()
+        .filter_map(|scheduled| scheduled.run_if_due(now))
+        .collect()
+}
+
+/// 添加配置的定时计划并在后台线程中定时执行
+/// 多次调用只启动一个线程
+pub fn start() {
+    START.call_once(|| {
+        for (name, action, expr) in app_config::SCHEDULES {
+            if let Err(error) = add(name, action, serde_json::Value::Null, expr) {
+                println!("Invalid schedule {}: {}", name, error);
+            }
+        }
+        thread::spawn(|| loop {
+            let now = time_utils::time_to_millis(&SystemTime::now());
+            run_pending(now);
+            let (scheduled, condvar) = &*SCHEDULER;
+            let scheduled = scheduled.lock().unwrap();
+            let sleep = scheduled.iter()
+                .filter_map(|scheduled| scheduled.state.next_run)
+                .min()
+                .map(|next_run| Duration::from_millis(next_run.saturating_sub(now) as u64))
+                .unwrap_or(MAX_SLEEP)
+                .min(MAX_SLEEP);
+            let _ = condvar.wait_timeout(scheduled, sleep).unwrap();
+        });
+    });
+}
\ No newline at end of file
@@ -0,0 +1,204 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use notify::{
+    event::{AccessKind, AccessMode, ModifyKind, RenameMode},
+    Event, EventKind, RecursiveMode, Watcher,
+};
+
+use crate::{
+    config::app_config,
+    infra::{cover_utils, file_utils},
+    model::dto::RecoveryPolicy,
+};
+
+use super::{actor, command::{self, RenamedPath, UpdateArgs}};
+
+/// 没有文件变化时的等待时间
+static IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// 合并一段时间内的文件变化
+/// 最后一次变化后等待 WATCH_DEBOUNCE_MILLIS，但第一次变化后最多等待 WATCH_MAX_DELAY_MILLIS
+#[derive(Default)]
+pub struct Debouncer {
+    paths: Vec<PathBuf>,
+    path_set: HashSet<PathBuf>,
+    renames: Vec<RenamedPath>,
+    /// 封面或编码设置变化的目录
+    refresh: Vec<PathBuf>,
+    refresh_set: HashSet<PathBuf>,
+    /// 监听的事件丢失，需要重新扫描整个媒体库
+    rescan: bool,
+    first_event: Option<Instant>,
+    last_event: Option<Instant>,
+}
+
+/// 合并后的文件变化
+#[derive(Debug, PartialEq, Eq)]
+pub enum Changes {
+    /// 增量更新
+    Update(UpdateArgs),
+    /// 重新扫描整个媒体库
+    Rescan,
+}
+
+/// 是否需要处理变化的路径
+/// 已经删除的路径无法判断是文件还是目录，总是需要处理
+fn is_relevant(path: &Path) -> bool {
+    if path.is_file() {
+        file_utils::is_audio_file(path) || file_utils::is_cue_file(path)
+    } else {
+        true
+    }
+}
+
+/// 封面图片、封面来源偏好或编码设置变化时，需要重新生成文件信息的目录
+/// 这些文件变化不会改变媒体文件本身，根据文件名判断，已经删除的文件也能处理
+/// @param path 变化的路径
+/// @return 不影响文件信息的路径返回 None
+fn refresh_dir(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?;
+    if name == app_config::ENCODING_OVERRIDE_FILE_NAME || name == app_config::COVER_PREFERENCE_FILE_NAME {
+        return path.parent().map(Path::to_path_buf);
+    }
+    cover_utils::cover_dir(path)
+}
+
+impl Debouncer {
+    pub fn new() -> Debouncer {
+        Debouncer::default()
+    }
+
+    fn add_path(&mut self, path: &Path) {
+        let path = file_utils::to_relative_path(path);
+        if self.path_set.insert(path.clone()) {
+            self.paths.push(path);
+        }
+    }
+
+    fn add_refresh(&mut self, dir: &Path) {
+        let dir = file_utils::to_relative_path(dir);
+        if self.refresh_set.insert(dir.clone()) {
+            self.refresh.push(dir);
+        }
+    }
+
+    fn add_changed(&mut self, path: &Path) {
+        match refresh_dir(path) {
+            Some(dir) => self.add_refresh(&dir),
+            None if is_relevant(path) => self.add_path(path),
+            None => {},
+        }
+    }
+
+    /// 记录文件变化
+    /// @param event 文件变化事件
+    /// @param now 当前时间
+    pub fn add(&mut self, event: &Event, now: Instant) {
+        if event.need_rescan() {
+            self.rescan = true;
+        } else {
+            match event.kind {
+                // 重命名的封面图片或设置文件按删除和新增处理
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both))
+                    if event.paths.len() == 2 && event.paths.iter().all(|path| refresh_dir(path).is_none()) => {
+                    self.renames.push(RenamedPath {
+                        from: file_utils::to_relative_path(&event.paths[0]),
+                        to: file_utils::to_relative_path(&event.paths[1]),
+                    });
+                },
+                // 复制完成时会关闭以写入方式打开的文件，其他访问不会改变文件
+                EventKind::Access(AccessKind::Close(AccessMode::Write))
+                | EventKind::Create(_)
+                | EventKind::Modify(_)
+                | EventKind::Remove(_)
+                | EventKind::Any => {
+                    for path in &event.paths {
+                        self.add_changed(path);
+                    }
+                },
+                _ => return,
+            }
+        }
+        self.first_event.get_or_insert(now);
+        self.last_event = Some(now);
+    }
+
+    /// 距离需要处理文件变化的时间
+    /// @param now 当前时间
+    /// @return 没有文件变化时返回 None
+    pub fn due_in(&self, now: Instant) -> Option<Duration> {
+        let first_event = self.first_event?;
+        let last_event = self.last_event?;
+        let due = (last_event + Duration::from_millis(app_config::WATCH_DEBOUNCE_MILLIS))
+            .min(first_event + Duration::from_millis(app_config::WATCH_MAX_DELAY_MILLIS));
+        Some(due.saturating_duration_since(now))
+    }
+
+    /// 取出合并后的文件变化
+    /// @return 没有需要处理的变化时返回 None
+    pub fn take(&mut self) -> Option<Changes> {
+        let debouncer = std::mem::take(self);
+        if debouncer.rescan {
+            return Some(Changes::Rescan);
+        }
+        if debouncer.paths.is_empty() && debouncer.renames.is_empty() && debouncer.refresh.is_empty() {
+            return None;
+        }
+        Some(Changes::Update(UpdateArgs {
+            paths: debouncer.paths,
+            renames: debouncer.renames,
+            refresh: debouncer.refresh,
+        }))
+    }
+}
+
+/// 处理合并后的文件变化
+fn apply(changes: Changes) {
+    match changes {
+        Changes::Update(args) => {
+            let args = serde_json::to_value(args).unwrap();
+            match actor::enqueue("update-files", args, RecoveryPolicy::Resume) {
+                Ok(job) => println!("Files changed, update index in job {}", job.id()),
+                Err(error) => println!("Failed to update index: {}", error),
+            }
+        },
+        Changes::Rescan => {
+            println!("File events were lost, rescan the library");
+            let _ = command::scan_library();
+        },
+    }
+}
+
+/// 监听媒体目录的变化，合并后通过 Actor 增量更新文件信息
+pub fn start() -> Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(Path::new(app_config::AUDIO_PATH), RecursiveMode::Recursive)?;
+    thread::spawn(move || {
+        // 线程结束前保持监听
+        let _watcher = watcher;
+        let mut debouncer = Debouncer::new();
+        loop {
+            let timeout = debouncer.due_in(Instant::now()).unwrap_or(IDLE_TIMEOUT);
+            match receiver.recv_timeout(timeout) {
+                Ok(Ok(event)) => debouncer.add(&event, Instant::now()),
+                Ok(Err(error)) => println!("Watch error: {}", error),
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if debouncer.due_in(Instant::now()) == Some(Duration::ZERO) {
+                if let Some(changes) = debouncer.take() {
+                    apply(changes);
+                }
+            }
+        }
+    });
+    Ok(())
+}
\ No newline at end of file
@@ -1,6 +1,66 @@
+use std::{env, path::PathBuf};
+
+use once_cell::sync::Lazy;
+
 pub static AUDIO_PATH: &str = "music";
-pub static FILE_INFO_STORAGE_PATH: &str = "cache/file-index";
-pub static ORIGIN_COVER_PATH: &str = "cache/cover/origin";
-pub static SMALL_COVER_PATH: &str = "cache/cover/small";
-pub static OTHER_AUDIO_QUALITY_PATH: &str = "cache/audio";
+/// 缓存目录，可以用环境变量 SHADOW_MUSIC_CACHE_PATH 指定（例如测试时使用临时目录）
+pub static CACHE_PATH: Lazy<PathBuf> = Lazy::new(|| {
+    env::var_os("SHADOW_MUSIC_CACHE_PATH").map(PathBuf::from).unwrap_or_else(|| PathBuf::from("cache"))
+});
+pub static FILE_INFO_STORAGE_PATH: Lazy<PathBuf> = Lazy::new(|| CACHE_PATH.join("file-index"));
+pub static ORIGIN_COVER_PATH: Lazy<PathBuf> = Lazy::new(|| CACHE_PATH.join("cover/origin"));
+pub static SMALL_COVER_PATH: Lazy<PathBuf> = Lazy::new(|| CACHE_PATH.join("cover/small"));
+pub static OTHER_AUDIO_QUALITY_PATH: Lazy<PathBuf> = Lazy::new(|| CACHE_PATH.join("audio"));
+pub static TRANSCODE_CACHE_STORAGE_PATH: Lazy<PathBuf> = Lazy::new(|| CACHE_PATH.join("transcode-index"));
+pub static TRANSCODE_CACHE_MAX_SIZE: u64 = 10 * 1024 * 1024 * 1024;
+pub static JOB_QUEUE_STORAGE_PATH: Lazy<PathBuf> = Lazy::new(|| CACHE_PATH.join("job-queue"));
+/// 任务因重启中断后最多恢复的次数，避免任务导致进程崩溃时反复执行
+pub static JOB_MAX_RECOVERIES: u32 = 3;
+/// I/O 线程池的线程数，机械硬盘上同时读取太多文件会频繁寻道
+pub static IO_POOL_THREADS: usize = 2;
+/// 交互线程池的线程数，批量任务占满计算线程池时用户等待的短任务仍然可以执行
+pub static INTERACTIVE_POOL_THREADS: usize = 2;
+/// 各类任务同时执行的数量和优先级
+pub static INTERACTIVE_JOB_CONCURRENCY: usize = 4;
+pub static INTERACTIVE_JOB_PRIORITY: u32 = 8;
+pub static SCAN_JOB_CONCURRENCY: usize = 1;
+pub static SCAN_JOB_PRIORITY: u32 = 2;
+pub static TRANSCODE_JOB_CONCURRENCY: usize = 1;
+pub static TRANSCODE_JOB_PRIORITY: u32 = 1;
+pub static MAINTENANCE_JOB_CONCURRENCY: usize = 1;
+pub static MAINTENANCE_JOB_PRIORITY: u32 = 1;
+/// 同时边转码边播放的数量，超过时新的播放请求等待
+pub static STREAM_JOB_CONCURRENCY: usize = 4;
+pub static STREAM_JOB_PRIORITY: u32 = 8;
+/// 命令遇到暂时性的 I/O 错误时最多尝试的次数
+pub static COMMAND_MAX_ATTEMPTS: u32 = 3;
+pub static SCHEDULE_STORAGE_PATH: Lazy<PathBuf> = Lazy::new(|| CACHE_PATH.join("schedule"));
+/// 定时执行的动作: (计划名称, 动作名称, 计划)
+/// 计划为 cron 表达式（分 时 日 月 周）、"@every <时间长度>" 或 @hourly/@daily/@weekly/@monthly
+pub static SCHEDULES: [(&str, &str, &str); 4] = [
+    ("nightly-scan", "scan", "0 3 * * *"),
+    ("nightly-compact-change-log", "compact-change-log", "0 5 * * *"),
+    ("weekly-check-integrity", "check-integrity", "0 4 * * 0"),
+    ("hourly-evict-transcode-cache", "evict-transcode-cache", "@hourly"),
+];
+/// cron 表达式使用的时区相对 UTC 的偏移（分钟）
+pub static SCHEDULE_UTC_OFFSET: i64 = 0;
+/// 是否监听媒体目录的变化
+pub static WATCH_ENABLED: bool = true;
+/// 文件变化停止后等待的时间（毫秒），复制整张专辑时合并成一次更新
+pub static WATCH_DEBOUNCE_MILLIS: u64 = 2000;
+/// 文件持续变化时最长的等待时间（毫秒）
+pub static WATCH_MAX_DELAY_MILLIS: u64 = 10000;
+/// 扫描结果中最多列出的有问题的文件数
+pub static SCAN_WARNING_REPORT_LIMIT: usize = 100;
+pub static CHANGE_LOG_MAX_ENTRIES: usize = 100000;
+pub static CHANGE_LOG_PAGE_SIZE: usize = 1000;
+pub static ENCODING_OVERRIDE_FILE_NAME: &str = ".encoding";
+pub static COVER_PREFERENCE_FILE_NAME: &str = ".cover";
+pub static DEFAULT_COVER_PREFERENCE: &str = "embedded";
+/// 专辑目录中的封面图片文件名（不含扩展名），靠前的优先
+pub static COVER_FILE_NAMES: [&str; 10] = [
+    "cover", "folder", "front", "album", "albumart",
+    "scans/front", "scans/cover", "artwork/front", "artwork/cover", "covers/front",
+];
 pub static HASH_SEED: u64 = 1145141919810;
\ No newline at end of file
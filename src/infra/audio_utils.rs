@@ -1,9 +1,10 @@
 extern crate ffmpeg_next as ffmpeg;
 
-use std::path::Path;
+use std::{fs::File, path::Path};
 
 use anyhow::{Context, Ok, Result};
 use ffmpeg::{codec, decoder, encoder, format, media, Stream};
+use lofty::{id3::v2::Id3v2Tag, mp3::Mp3File, AudioFile};
 
 /// 获取最佳音频流索引
 /// @param input_ctx 输入媒体文件上下文
@@ -125,6 +126,21 @@ pub fn get_tags_from_media_file<P: AsRef<Path>>(file_path: &P) -> Result<lofty::
     Ok(tag.clone())
 }
 
+/// 从 MP3 文件中获取 ID3v2 标签
+/// 通用标签中 TXXX 帧丢失了描述，需要时直接读取 ID3v2 标签
+/// @param file_path 媒体文件路径
+/// @return 不是 MP3 文件或者没有 ID3v2 标签时返回 None
+pub fn get_id3v2_tag_from_media_file<P: AsRef<Path>>(file_path: &P) -> Result<Option<Id3v2Tag>> {
+    let is_mp3 = file_path.as_ref().extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("mp3"));
+    if !is_mp3 {
+        return Ok(None);
+    }
+    let mp3_file = Mp3File::read_from(&mut File::open(file_path)?, false)?;
+    Ok(mp3_file.id3v2_tag().cloned())
+}
+
 /// 从媒体文件中获取属性
 /// @param file_path 媒体文件路径
 /// @return 属性
@@ -0,0 +1,108 @@
+extern crate ffmpeg_next as ffmpeg;
+
+use std::{
+    ffi::CString,
+    io::Write,
+    ops::{Deref, DerefMut},
+    os::raw::{c_int, c_void},
+    ptr, slice,
+};
+
+use anyhow::{anyhow, Result};
+use ffmpeg::{ffi, format};
+
+/// AVIO 缓冲区大小，较小的缓冲区可以让数据更早写出
+static AVIO_BUFFER_SIZE: usize = 4 * 1024;
+
+/// 写入任意 Write 的输出媒体文件上下文
+/// 通过自定义 AVIO 上下文实现，输出不可 seek，需要选择支持流式写入的封装格式
+pub struct WriterOutput<W: Write> {
+    output: Option<format::context::Output>,
+    avio: *mut ffi::AVIOContext,
+    writer: *mut W,
+}
+
+unsafe impl<W: Write + Send> Send for WriterOutput<W> {}
+
+/// AVIO 写入回调
+unsafe extern "C" fn write_packet<W: Write>(opaque: *mut c_void, buffer: *mut u8, size: c_int) -> c_int {
+    let writer = &mut *(opaque as *mut W);
+    let data = slice::from_raw_parts(buffer as *const u8, size as usize);
+    match writer.write_all(data) {
+        Ok(_) => size,
+        // 例如客户端已断开连接，让 ffmpeg 停止写入
+        Err(_) => ffi::AVERROR_EXIT,
+    }
+}
+
+impl<W: Write> WriterOutput<W> {
+    /// 创建输出上下文
+    /// @param format_name 封装格式名称（例如: "ogg", "mp3", "adts"）
+    /// @param writer 输出目标
+    pub fn new(format_name: &str, writer: W) -> Result<WriterOutput<W>> {
+        let format_name = CString::new(format_name)?;
+        unsafe {
+            let mut ctx: *mut ffi::AVFormatContext = ptr::null_mut();
+            let ret = ffi::avformat_alloc_output_context2(&mut ctx, ptr::null_mut(), format_name.as_ptr(), ptr::null());
+            if ret < 0 || ctx.is_null() {
+                return Err(anyhow!("Failed to find {:?} muxer", format_name));
+            }
+
+            let writer = Box::into_raw(Box::new(writer));
+            let buffer = ffi::av_malloc(AVIO_BUFFER_SIZE) as *mut u8;
+            let avio = ffi::avio_alloc_context(
+                buffer,
+                AVIO_BUFFER_SIZE as c_int,
+                1,
+                writer as *mut c_void,
+                None,
+                Some(write_packet::<W>),
+                None,
+            );
+            if avio.is_null() {
+                ffi::av_free(buffer as *mut c_void);
+                ffi::avformat_free_context(ctx);
+                drop(Box::from_raw(writer));
+                return Err(anyhow!("Failed to allocate AVIO context"));
+            }
+            (*ctx).pb = avio;
+            (*ctx).flags |= ffi::AVFMT_FLAG_CUSTOM_IO as c_int;
+
+            Ok(WriterOutput {
+                output: Some(format::context::Output::wrap(ctx)),
+                avio,
+                writer,
+            })
+        }
+    }
+}
+
+impl<W: Write> Deref for WriterOutput<W> {
+    type Target = format::context::Output;
+
+    fn deref(&self) -> &Self::Target {
+        self.output.as_ref().unwrap()
+    }
+}
+
+impl<W: Write> DerefMut for WriterOutput<W> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.output.as_mut().unwrap()
+    }
+}
+
+impl<W: Write> Drop for WriterOutput<W> {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(mut output) = self.output.take() {
+                ffi::avio_flush(self.avio);
+                // Output 释放时会对 pb 调用 avio_close，自定义 AVIO 上下文需要自己释放
+                (*output.as_mut_ptr()).pb = ptr::null_mut();
+            }
+            // 缓冲区可能已被 ffmpeg 重新分配，以上下文中记录的为准
+            ffi::av_freep(&mut (*self.avio).buffer as *mut *mut u8 as *mut c_void);
+            ffi::avio_context_free(&mut self.avio);
+            drop(Box::from_raw(self.writer));
+        }
+    }
+}
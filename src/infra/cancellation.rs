@@ -0,0 +1,78 @@
+use std::{sync::{atomic::{AtomicBool, Ordering}, Arc}, time::{Duration, Instant}};
+
+use thiserror::Error;
+
+/// 操作已取消
+/// 耗时操作检查到取消时返回这个错误，调用者可以通过 downcast 区分取消和其他错误
+#[derive(Debug, Error)]
+#[error("Operation cancelled")]
+pub struct Cancelled;
+
+/// 操作超时
+/// 超过取消令牌的截止时间时返回这个错误
+#[derive(Debug, Error)]
+#[error("Operation timed out")]
+pub struct TimedOut;
+
+/// 取消令牌
+/// 用于协作式取消，克隆的令牌共享取消状态
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    /// 截止时间，超过后视为取消
+    deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// 创建带超时的令牌
+    /// 新令牌与原令牌共享取消状态，同时在超时后停止
+    /// @param timeout 超时时间
+    pub fn with_timeout(&self, timeout: Duration) -> CancellationToken {
+        let deadline = Instant::now() + timeout;
+        CancellationToken {
+            cancelled: self.cancelled.clone(),
+            deadline: Some(self.deadline.map_or(deadline, |current| current.min(deadline))),
+        }
+    }
+
+    /// 请求取消
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// 是否需要停止（已经请求取消或者已经超时）
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst) || self.is_timed_out()
+    }
+
+    /// 是否已经超时
+    pub fn is_timed_out(&self) -> bool {
+        self.deadline.map(|deadline| Instant::now() >= deadline).unwrap_or(false)
+    }
+
+    /// 检查是否需要停止
+    /// @return 已经请求取消时返回 Cancelled 错误，已经超时时返回 TimedOut 错误
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return Err(Cancelled.into());
+        }
+        if self.is_timed_out() {
+            return Err(TimedOut.into());
+        }
+        Ok(())
+    }
+}
+
+/// 判断错误是否由取消引起
+pub fn is_cancelled(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<Cancelled>().is_some()
+}
+
+/// 判断错误是否由超时引起
+pub fn is_timed_out(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<TimedOut>().is_some()
+}
\ No newline at end of file
@@ -0,0 +1,241 @@
+use std::{collections::HashMap, fs, io, path::{Path, PathBuf}, process, sync::{atomic::{AtomicU64, Ordering}, Mutex}};
+
+use once_cell::sync::Lazy;
+use radix_fmt::radix;
+use walkdir::WalkDir;
+
+use crate::config::app_config;
+
+use super::{file_utils, hash_utils};
+
+/// 封面图片扩展名
+static COVER_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "webp", "bmp"];
+
+/// 扫描期间的目录封面缓存，同一目录中的媒体文件只查找一次封面
+#[derive(Default)]
+struct CoverCache {
+    /// 进行中的扫描数量，没有扫描时不缓存，目录中的封面可能已经变化
+    scopes: usize,
+    /// 目录中的封面图片
+    dirs: HashMap<PathBuf, Option<PathBuf>>,
+    /// 已保存的封面图片的 Hash
+    hashes: HashMap<PathBuf, String>,
+}
+
+static COVER_CACHE: Lazy<Mutex<CoverCache>> = Lazy::new(|| Mutex::new(CoverCache::default()));
+
+/// 临时文件序号，同时保存相同的封面时不会写入同一个临时文件
+static TEMP_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// 目录封面缓存的有效范围，释放后清空缓存
+pub struct CoverCacheScope;
+
+impl Drop for CoverCacheScope {
+    fn drop(&mut self) {
+        let mut cache = COVER_CACHE.lock().unwrap();
+        cache.scopes -= 1;
+        if cache.scopes == 0 {
+            cache.dirs.clear();
+            cache.hashes.clear();
+        }
+    }
+}
+
+/// 开始缓存目录封面，扫描开始时调用，返回值释放前一直有效
+pub fn cache_scope() -> CoverCacheScope {
+    COVER_CACHE.lock().unwrap().scopes += 1;
+    CoverCacheScope
+}
+
+/// 封面来源偏好
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverPreference {
+    /// 优先使用内嵌封面，没有时使用目录中的封面图片
+    Embedded,
+    /// 优先使用目录中的封面图片，没有时使用内嵌封面
+    Sidecar,
+}
+
+impl CoverPreference {
+    /// 根据名称解析封面来源偏好
+    /// @param name 名称 embedded/sidecar
+    pub fn from_name(name: &str) -> Option<CoverPreference> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "embedded" => Some(CoverPreference::Embedded),
+            "sidecar" => Some(CoverPreference::Sidecar),
+            _ => None,
+        }
+    }
+}
+
+/// 保存封面图片，相同内容只保存一次
+/// @param data 图片数据
+/// @return 封面 Hash
+pub fn save_cover_data(data: &[u8]) -> io::Result<String> {
+    let cover_hash = radix(hash_utils::hash_data(data), 36).to_string();
+    let cover_path = app_config::ORIGIN_COVER_PATH.join(&cover_hash);
+    if !cover_path.exists() {
+        fs::create_dir_all(&*app_config::ORIGIN_COVER_PATH)?;
+        // 先写入临时文件再重命名，中断时不会留下不完整的封面
+        let temp_path = cover_path.with_extension(format!("{}-{}.tmp", process::id(), TEMP_SEQ.fetch_add(1, Ordering::Relaxed)));
+        if let Err(err) = fs::write(&temp_path, data).and_then(|_| fs::rename(&temp_path, &cover_path)) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(err);
+        }
+    }
+    Ok(cover_hash)
+}
+
+/// 查找目录指定的封面来源偏好
+/// 从文件所在目录向上查找偏好文件，直到媒体目录为止，没有时使用默认偏好
+/// 偏好文件的内容为 embedded 或 sidecar，对所在目录及其子目录生效
+/// @param file_path 媒体文件路径（包含媒体目录）
+/// @return 封面来源偏好
+pub fn find_cover_preference(file_path: &Path) -> CoverPreference {
+    let root = PathBuf::from(app_config::AUDIO_PATH);
+    let mut dir = file_path.parent();
+    while let Some(current) = dir {
+        let preference_file = current.join(app_config::COVER_PREFERENCE_FILE_NAME);
+        if let Ok(name) = fs::read_to_string(&preference_file) {
+            match CoverPreference::from_name(&name) {
+                Some(preference) => return preference,
+                None => println!("{} has unknown cover preference: {}", preference_file.display(), name.trim()),
+            }
+        }
+        if current == root {
+            break;
+        }
+        dir = current.parent();
+    }
+    CoverPreference::from_name(app_config::DEFAULT_COVER_PREFERENCE).unwrap_or(CoverPreference::Embedded)
+}
+
+/// 在目录中查找封面图片，扫描期间使用缓存
+/// @param dir 目录
+/// @return 封面图片路径
+fn find_cover_in_dir(dir: &Path) -> Option<PathBuf> {
+    if let Some(cover_path) = COVER_CACHE.lock().unwrap().dirs.get(dir) {
+        return cover_path.clone();
+    }
+    let cover_path = walk_cover_in_dir(dir);
+    let mut cache = COVER_CACHE.lock().unwrap();
+    if cache.scopes > 0 {
+        cache.dirs.insert(dir.to_path_buf(), cover_path.clone());
+    }
+    cover_path
+}
+
+/// 在目录中查找封面图片
+/// 按配置的文件名优先级查找，文件名不区分大小写
+/// @param dir 目录
+/// @return 封面图片路径
+fn walk_cover_in_dir(dir: &Path) -> Option<PathBuf> {
+    let max_depth = app_config::COVER_FILE_NAMES.iter()
+        .map(|name| name.split('/').count())
+        .max()
+        .unwrap_or(1);
+    let mut best: Option<(usize, PathBuf)> = None;
+    for entry in WalkDir::new(dir).min_depth(1).max_depth(max_depth).into_iter().filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if !is_image(path) || !entry.file_type().is_file() {
+            continue;
+        }
+        // 相对路径去掉扩展名（例如: "Scans/front"）
+        let name = match path.strip_prefix(dir) {
+            Ok(name) => name.with_extension(""),
+            Err(_) => continue,
+        };
+        let name = name.components()
+            .map(|component| component.as_os_str().to_string_lossy().to_lowercase())
+            .collect::<Vec<String>>()
+            .join("/");
+        let priority = app_config::COVER_FILE_NAMES.iter()
+            .position(|cover_name| cover_name.eq_ignore_ascii_case(&name));
+        if let Some(priority) = priority {
+            if best.as_ref().map(|(best_priority, _)| priority < *best_priority).unwrap_or(true) {
+                best = Some((priority, path.to_path_buf()));
+            }
+        }
+    }
+    best.map(|(_, path)| path)
+}
+
+/// 判断是否为封面图片支持的格式
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| COVER_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// 获取封面图片所属的专辑目录
+/// 文件名（包括子目录，例如: "Scans/front"）与配置的封面文件名匹配时为封面图片
+/// 匹配多个封面文件名时返回最上层的目录
+/// @param path 图片路径
+/// @return 不是封面图片时返回 None
+pub fn cover_dir(path: &Path) -> Option<PathBuf> {
+    if !is_image(path) {
+        return None;
+    }
+    let stem = path.with_extension("");
+    app_config::COVER_FILE_NAMES.iter().filter_map(|cover_name| {
+        let mut dir = stem.as_path();
+        for name in cover_name.rsplit('/') {
+            if !dir.file_name()?.to_string_lossy().eq_ignore_ascii_case(name) {
+                return None;
+            }
+            dir = dir.parent()?;
+        }
+        Some(dir.to_path_buf())
+    }).min_by_key(|dir| dir.components().count())
+}
+
+/// 查找媒体文件所在专辑目录中的封面图片
+/// 分碟目录中没有封面时，查找上级目录
+/// @param file_path 媒体文件路径（包含媒体目录）
+/// @return 封面图片路径
+pub fn find_sidecar_cover(file_path: &Path) -> Option<PathBuf> {
+    let dir = file_path.parent()?;
+    find_cover_in_dir(dir).or_else(|| {
+        if dir.file_name().map(|name| file_utils::is_disc_dir_name(&name.to_string_lossy())).unwrap_or(false) {
+            find_cover_in_dir(dir.parent()?)
+        } else {
+            None
+        }
+    })
+}
+
+/// 保存媒体文件所在专辑目录中的封面图片
+/// @param file_path 媒体文件路径（包含媒体目录）
+/// @return 封面 Hash
+pub fn save_sidecar_cover(file_path: &Path) -> Option<String> {
+    let cover_path = find_sidecar_cover(file_path)?;
+    if let Some(cover_hash) = COVER_CACHE.lock().unwrap().hashes.get(&cover_path) {
+        return Some(cover_hash.clone());
+    }
+    let result = fs::read(&cover_path).and_then(|data| save_cover_data(&data));
+    match result {
+        Ok(cover_hash) => {
+            let mut cache = COVER_CACHE.lock().unwrap();
+            if cache.scopes > 0 {
+                cache.hashes.insert(cover_path, cover_hash.clone());
+            }
+            Some(cover_hash)
+        },
+        Err(err) => {
+            println!("Failed to save cover {}: {}", cover_path.display(), err);
+            None
+        },
+    }
+}
+
+/// 根据封面来源偏好选择封面
+/// @param file_path 媒体文件路径（包含媒体目录）
+/// @param embedded 保存内嵌封面，返回封面 Hash
+/// @return 封面 Hash
+pub fn select_cover<F: FnOnce() -> Option<String>>(file_path: &Path, embedded: F) -> Option<String> {
+    match find_cover_preference(file_path) {
+        CoverPreference::Embedded => embedded().or_else(|| save_sidecar_cover(file_path)),
+        CoverPreference::Sidecar => save_sidecar_cover(file_path).or_else(embedded),
+    }
+}
\ No newline at end of file
@@ -0,0 +1,191 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::config::app_config;
+
+/// 最多向后查找的天数，超过时认为表达式不会匹配（例如: 2 月 30 日）
+static MAX_SEARCH_DAYS: i64 = 366 * 8;
+
+/// cron 表达式: 分 时 日 月 周
+/// 支持 *、数字、范围（1-5）、步长（*/15、0-30/10）和列表（1,3,5），周日为 0 或 7
+/// 日和周都指定时满足其中一个即可，与 crontab 相同，以 * 开头的字段（例如: */2）视为没有指定
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// 定时计划
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// 按 cron 表达式执行
+    Cron(CronExpr),
+    /// 每隔固定时间执行
+    Interval(Duration),
+}
+
+/// 解析 cron 表达式的一个字段
+/// @return 匹配的值的位图
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|step| *step > 0)
+                .ok_or_else(|| anyhow!("Invalid step: {}", part))?),
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (start.parse()?, end.parse()?)
+        } else {
+            let start = range.parse()?;
+            // 只有起始值和步长时到最大值结束（例如: 5/15）
+            (start, if step > 1 { max } else { start })
+        };
+        if start < min || end > max || start > end {
+            bail!("Value out of range {}-{}: {}", min, max, part);
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+/// 从 1970-01-01 开始的天数转换为月和日
+/// 参考: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn month_day_from_days(days: i64) -> (u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (month as u32, day as u32)
+}
+
+impl CronExpr {
+    /// 解析 cron 表达式
+    /// @param expr cron 表达式（例如: "0 3 * * *"）
+    pub fn parse(expr: &str) -> Result<CronExpr> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            bail!("Cron expression must have 5 fields: {}", expr);
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // 7 也表示周日
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        let cron = CronExpr {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*'),
+        };
+        if cron.next_after(0, 0).is_none() {
+            bail!("Cron expression never matches: {}", expr);
+        }
+        Ok(cron)
+    }
+
+    fn matches_day(&self, days: i64) -> bool {
+        let (month, day) = month_day_from_days(days);
+        let weekday = (days + 4).rem_euclid(7);
+        let day_matched = self.days & (1 << day) != 0;
+        let weekday_matched = self.weekdays & (1 << weekday) != 0;
+        let matched = match (self.any_day, self.any_weekday) {
+            (false, false) => day_matched || weekday_matched,
+            _ => day_matched && weekday_matched,
+        };
+        self.months & (1 << month) != 0 && matched
+    }
+
+    /// 计算下次执行时间
+    /// @param millis 从这个时间之后开始查找（Unix 毫秒）
+    /// @param utc_offset 时区相对 UTC 的偏移（分钟）
+    /// @return 下次执行时间（Unix 毫秒），不会匹配时返回 None
+    pub fn next_after(&self, millis: u128, utc_offset: i64) -> Option<u128> {
+        let start = (millis / 60000) as i64 + 1 + utc_offset;
+        let mut minute = start;
+        while minute < start + MAX_SEARCH_DAYS * 1440 {
+            let days = minute.div_euclid(1440);
+            if !self.matches_day(days) {
+                minute = (days + 1) * 1440;
+                continue;
+            }
+            let hour = minute.rem_euclid(1440) / 60;
+            if self.hours & (1 << hour) == 0 {
+                minute = days * 1440 + (hour + 1) * 60;
+                continue;
+            }
+            if self.minutes & (1 << minute.rem_euclid(60)) == 0 {
+                minute += 1;
+                continue;
+            }
+            return u128::try_from((minute - utc_offset) * 60000).ok();
+        }
+        None
+    }
+}
+
+/// 解析时间长度
+/// @param value 数字和单位 s/m/h/d（例如: "90s"、"1h"）
+fn parse_duration(value: &str) -> Result<Duration> {
+    let unit_index = value.find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| anyhow!("Missing unit: {}", value))?;
+    let (number, unit) = value.split_at(unit_index);
+    let number: u64 = number.parse()?;
+    let seconds = match unit {
+        "s" => number,
+        "m" => number * 60,
+        "h" => number * 60 * 60,
+        "d" => number * 24 * 60 * 60,
+        _ => bail!("Unknown unit: {}", value),
+    };
+    if seconds == 0 {
+        bail!("Interval must be positive: {}", value);
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+impl Schedule {
+    /// 解析定时计划
+    /// @param expr cron 表达式、"@every <时间长度>" 或 @hourly/@daily/@weekly/@monthly
+    pub fn parse(expr: &str) -> Result<Schedule> {
+        let expr = expr.trim();
+        if let Some(interval) = expr.strip_prefix("@every ") {
+            return Ok(Schedule::Interval(parse_duration(interval.trim())?));
+        }
+        let expr = match expr {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            _ => expr,
+        };
+        Ok(Schedule::Cron(CronExpr::parse(expr)?))
+    }
+
+    /// 计算下次执行时间，cron 表达式按配置的时区计算
+    /// @param millis 上次执行或者开始计划的时间（Unix 毫秒）
+    /// @return 下次执行时间（Unix 毫秒），不会匹配时返回 None
+    pub fn next_after(&self, millis: u128) -> Option<u128> {
+        match self {
+            Schedule::Cron(cron) => cron.next_after(millis, app_config::SCHEDULE_UTC_OFFSET),
+            Schedule::Interval(interval) => Some(millis + interval.as_millis()),
+        }
+    }
+}
\ No newline at end of file
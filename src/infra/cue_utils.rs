@@ -0,0 +1,81 @@
+use std::{fs, path::{Path, PathBuf}, time::Duration};
+
+use anyhow::{anyhow, Result};
+use cuna::{CueSheet, time::TimeStamp, track::Track};
+use encoding_rs::Encoding;
+
+use super::{file_utils, encoding_utils};
+
+/// 读取 cuesheet 文件
+/// 会自动检测文件编码，目录指定了编码时使用指定的编码
+/// @param file_path cuesheet 文件路径
+/// @return cuesheet，文件编码
+pub fn read_cue_sheet<P: AsRef<Path>>(file_path: &P) -> Result<(CueSheet, &'static Encoding)> {
+    let data = fs::read(file_path)?;
+    let override_encoding = encoding_utils::find_override_encoding(file_path.as_ref());
+    let decoded = encoding_utils::decode(&data, override_encoding);
+    let sheet = CueSheet::new(&decoded.text)
+        .map_err(|err| anyhow!("Failed to parse cuesheet: {}", err))?;
+    Ok((sheet, decoded.encoding))
+}
+
+/// 获取 cuesheet 关联的媒体文件路径
+/// 只支持整轨（只包含一个 FILE）的 cuesheet
+/// 如果文件不存在，会尝试同名但扩展名不同的音频文件（例如 cuesheet 中写的是 wav，实际是 flac）
+/// @param cue_path cuesheet 文件路径
+/// @param sheet cuesheet
+/// @return 媒体文件路径
+pub fn get_cue_media_path(cue_path: &Path, sheet: &CueSheet) -> Option<PathBuf> {
+    if sheet.files().len() != 1 {
+        return None;
+    }
+    let parent = cue_path.parent()?;
+    // 部分 cuesheet 使用 Windows 路径分隔符
+    let name = sheet.files()[0].name.replace('\\', "/");
+    let media_path = parent.join(&name);
+    if media_path.is_file() {
+        return Some(media_path);
+    }
+    let stem = media_path.file_stem()?.to_os_string();
+    fs::read_dir(media_path.parent()?).ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.is_file()
+                && path.file_stem() == Some(stem.as_os_str())
+                && file_utils::is_audio_file(path)
+        })
+}
+
+/// 获取音轨的起始位置（毫秒）
+/// 优先使用 INDEX 01，没有时使用第一个 INDEX
+/// @param track 音轨
+/// @return 起始位置（毫秒）
+pub fn get_track_index_time(track: &Track) -> Option<u128> {
+    track.index.iter()
+        .find(|index| index.id() == 1)
+        .or_else(|| track.index.first())
+        .map(|index| timestamp_to_millis(index.begin_time()))
+}
+
+/// 获取 cuesheet 注释中的值（例如 REM DATE 2000）
+/// @param sheet cuesheet
+/// @param key 注释名称
+/// @return 注释值
+pub fn get_comment<'a>(sheet: &'a CueSheet, key: &str) -> Option<&'a str> {
+    sheet.comments().0.iter().find_map(|comment| {
+        let (name, value) = comment.trim().split_once(' ')?;
+        if name.eq_ignore_ascii_case(key) {
+            Some(value.trim().trim_matches('"'))
+        } else {
+            None
+        }
+    })
+}
+
+/// cuesheet 时间转毫秒
+/// @param timestamp cuesheet 时间（分:秒:帧，每秒 75 帧）
+/// @return 毫秒
+pub fn timestamp_to_millis(timestamp: &TimeStamp) -> u128 {
+    Duration::from(timestamp).as_millis()
+}
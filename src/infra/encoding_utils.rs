@@ -0,0 +1,171 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use encoding_rs::{Encoding, BIG5, GBK, SHIFT_JIS, UTF_8, WINDOWS_1252};
+
+use crate::config::app_config;
+
+/// 候选编码，评分相同时靠前的优先
+static CANDIDATE_ENCODINGS: [&Encoding; 5] = [UTF_8, GBK, BIG5, SHIFT_JIS, WINDOWS_1252];
+
+/// 简体中文常用字
+static COMMON_SIMPLIFIED: &str = "的一是不了人我在有他这中大来上国个到说们为子和你地出道也时年得就那要下以生会自着去之过家学对可她里后小么心多天而能好都然没日于起还发成事只作当想看文无开手十用主行方又如前所本见经头面公同三已老从动两长知民样现分将外但身些与高意进把法此实回二理美点月明其种声全工己话儿者向情部正名定女问力机给等几很业最间新什打便位因重被走电四第门相次东政海口使教西再平真听世气信北少关并内加化由却代军产入先山五太水万市眼体别处总才场师书比住员九笑性通目华报立马命张活难神数件安表原车白应路期叫死常提感金何更反合放做系计或司利受光王果亲界及今京务制解各任至清物台象记边共风战干接它许八特觉望直服毛林题建南度统色字请交爱让认算论百吃义科怎元社术结六功指思非流每青管夫连远资队跟带花快条院变联言权往展该领传近留红治决周保达办运武半候七必城父强步完革深区即求品士转量空甚众技轻程告江语英基派满式李息写呢识极令黄德收脸钱党倒未持取设始版双历越史商千片容研像找友孩站广改议形委早房音火际则首单据导影失拿网香似斯专石若兵弟谁校读志飞观争究包组造落视济喜离虽坏兴切爸恋歌曲";
+
+/// 繁体中文常用字
+static COMMON_TRADITIONAL: &str = "的一是不了人我在有他這中大來上國個到說們為子和你地出道也時年得就那要下以生會自著去之過家學對可她裡後小麼心多天而能好都然沒日於起還發成事只作當想看文無開手十用主行方又如前所本見經頭面公同三已老從動兩長知民樣現分將外但身些與高意進把法此實回二理美點月明其種聲全工己話兒者向情部正名定女問力機給等幾很業最間新什打便位因重被走電四第門相次東政海口使教西再平真聽世氣信北少關並內加化由卻代軍產入先山五太水萬市眼體別處總才場師書比住員九笑性通目華報立馬命張活難神數件安表原車白應路期叫死常提感金何更反合放做系計或司利受光王果親界及今京務制解各任至清物台象記邊共風戰乾接它許八特覺望直服毛林題建南度統色字請交愛讓認算論百吃義科怎元社術結六功指思非流每青管夫連遠資隊跟帶花快條院變聯言權往展該領傳近留紅治決周保達辦運武半候七必城父強步完革深區即求品士轉量空甚眾技輕程告江語英基派滿式李息寫呢識極令黃德收臉錢黨倒未持取設始版雙歷越史商千片容研像找友孩站廣改議形委早房音火際則首單據導影失拿網香似斯專石若兵弟誰校讀志飛觀爭究包組造落視濟喜離雖壞興切爸戀歌曲";
+
+/// 解码结果
+pub struct DecodedText {
+    /// 文本
+    pub text: String,
+    /// 使用的编码
+    pub encoding: &'static Encoding,
+}
+
+/// 根据编码名称查找编码（例如: "GBK", "Shift_JIS", "Big5"）
+/// @param label 编码名称
+/// @return 编码
+pub fn find_encoding(label: &str) -> Option<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+}
+
+/// 查找目录指定的编码
+/// 从文件所在目录向上查找编码覆盖文件，直到媒体目录为止
+/// 编码覆盖文件的内容为编码名称，对所在目录及其子目录生效
+/// @param file_path 文件路径（包含媒体目录）
+/// @return 指定的编码
+pub fn find_override_encoding(file_path: &Path) -> Option<&'static Encoding> {
+    let root = PathBuf::from(app_config::AUDIO_PATH);
+    let mut dir = file_path.parent();
+    while let Some(current) = dir {
+        let override_file = current.join(app_config::ENCODING_OVERRIDE_FILE_NAME);
+        if let Ok(label) = fs::read_to_string(&override_file) {
+            match find_encoding(&label) {
+                Some(encoding) => return Some(encoding),
+                None => println!("{} has unknown encoding: {}", override_file.display(), label.trim()),
+            }
+        }
+        if current == root {
+            break;
+        }
+        dir = current.parent();
+    }
+    None
+}
+
+/// 检测编码并解码
+/// 带 BOM 时使用 BOM 指定的编码，否则在候选编码中选择评分最高的
+/// @param data 原始数据
+/// @param override_encoding 指定的编码
+/// @return 解码结果
+pub fn decode(data: &[u8], override_encoding: Option<&'static Encoding>) -> DecodedText {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(data) {
+        return decode_with(&data[bom_length..], encoding);
+    }
+    if let Some(encoding) = override_encoding {
+        return decode_with(data, encoding);
+    }
+    let encoding = detect(data).unwrap_or(WINDOWS_1252);
+    decode_with(data, encoding)
+}
+
+/// 修复按 Latin-1 错误解码的文本（常见于 ID3v1 和 ID3v2.3 标签）
+/// 只有全部字符都在 Latin-1 范围内且包含非 ASCII 字符的文本才会处理
+/// @param text 文本
+/// @param override_encoding 指定的编码
+/// @return 修复后的文本，不需要修复时返回 None
+pub fn repair(text: &str, override_encoding: Option<&'static Encoding>) -> Option<DecodedText> {
+    if text.is_ascii() || text.chars().any(|c| c as u32 > 0xFF) {
+        return None;
+    }
+    let data: Vec<u8> = text.chars().map(|c| c as u8).collect();
+    let encoding = match override_encoding {
+        Some(encoding) => encoding,
+        None => {
+            let encoding = detect(&data)?;
+            if encoding == WINDOWS_1252 {
+                return None;
+            }
+            // 合法的 UTF-8 直接采用，其他编码只有比原来的解码结果更合理时才替换
+            if encoding != UTF_8 && score(text) >= score(&decode_with(&data, encoding).text) {
+                return None;
+            }
+            encoding
+        },
+    };
+    let decoded = decode_with(&data, encoding);
+    if decoded.text == text {
+        None
+    } else {
+        Some(decoded)
+    }
+}
+
+/// 在候选编码中选择评分最高的编码
+/// 无法无损解码的编码不参与评分
+/// @param data 原始数据
+/// @return 编码
+pub fn detect(data: &[u8]) -> Option<&'static Encoding> {
+    if data.is_ascii() {
+        return Some(UTF_8);
+    }
+    // 合法的 UTF-8 文本基本不会是其他编码
+    if std::str::from_utf8(data).is_ok() {
+        return Some(UTF_8);
+    }
+    let mut best: Option<(&'static Encoding, i64)> = None;
+    for encoding in CANDIDATE_ENCODINGS.iter() {
+        if let Some(text) = encoding.decode_without_bom_handling_and_without_replacement(data) {
+            let text_score = score(&text);
+            if best.map(|(_, best_score)| text_score > best_score).unwrap_or(true) {
+                best = Some((encoding, text_score));
+            }
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// 使用指定编码解码，无法解码的字符会被替换
+fn decode_with(data: &[u8], encoding: &'static Encoding) -> DecodedText {
+    let (text, _) = encoding.decode_without_bom_handling(data);
+    DecodedText { text: text.into_owned(), encoding }
+}
+
+/// 文本合理程度评分
+/// 常用汉字和假名加分，罕见字符、半角片假名和控制字符减分
+/// 西文中带音调的字母通常夹在 ASCII 字母之间，连续出现时多半是乱码
+fn score(text: &str) -> i64 {
+    let chars: Vec<char> = text.chars().collect();
+    chars.iter().enumerate().map(|(i, &c)| match c as u32 {
+        // ASCII 在各个编码中都一样
+        0x20..=0x7E | 0x09 | 0x0A | 0x0D => 0,
+        // 控制字符
+        0x00..=0x1F | 0x7F..=0x9F => -10,
+        // Latin-1 字母
+        0xC0..=0xFF if c != '×' && c != '÷' => {
+            let prev_is_letter = i > 0 && chars[i - 1].is_ascii_alphabetic();
+            let next_is_letter = chars.get(i + 1).map(char::is_ascii_alphabetic).unwrap_or(false);
+            if prev_is_letter || next_is_letter {
+                1
+            } else {
+                -1
+            }
+        },
+        // 平假名、片假名
+        0x3040..=0x30FF => 3,
+        // 中日韩统一表意文字
+        0x4E00..=0x9FFF => {
+            if COMMON_SIMPLIFIED.contains(c) || COMMON_TRADITIONAL.contains(c) {
+                3
+            } else {
+                1
+            }
+        },
+        // 中日韩标点和全角字符
+        0x3000..=0x303F | 0xFF01..=0xFF5E => 1,
+        // 半角片假名
+        0xFF61..=0xFF9F => -2,
+        // 私用区
+        0xE000..=0xF8FF => -10,
+        _ => 0,
+    }).sum()
+}
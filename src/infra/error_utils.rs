@@ -0,0 +1,30 @@
+extern crate ffmpeg_next as ffmpeg;
+
+use std::io;
+
+/// 暂时性的错误码
+fn is_transient_errno(errno: i32) -> bool {
+    [ffmpeg::error::EIO, ffmpeg::error::EAGAIN, ffmpeg::error::EINTR, ffmpeg::error::ETIMEDOUT].contains(&errno)
+}
+
+/// 是否为暂时性的 I/O 错误
+fn is_transient_io_error(err: &io::Error) -> bool {
+    matches!(err.kind(),
+        io::ErrorKind::TimedOut
+        | io::ErrorKind::Interrupted
+        | io::ErrorKind::WouldBlock
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted)
+        || err.raw_os_error().map(is_transient_errno).unwrap_or(false)
+}
+
+/// 判断错误是否为暂时性的 I/O 错误（例如网络存储短暂不可用），重试可能成功
+/// 会检查错误链中的所有错误，ffmpeg 读取文件的错误按错误码判断
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(io_error) = cause.downcast_ref::<io::Error>() {
+            return is_transient_io_error(io_error);
+        }
+        matches!(cause.downcast_ref::<ffmpeg::Error>(), Some(ffmpeg::Error::Other { errno }) if is_transient_errno(*errno))
+    })
+}
\ No newline at end of file
@@ -1,10 +1,10 @@
-use std::{collections::HashSet, path::PathBuf};
+use std::{collections::{HashSet, HashMap}, path::{Path, PathBuf, Component}, fs, io};
 use once_cell::sync::Lazy;
 use walkdir::{WalkDir, DirEntry};
 
 use crate::{config::app_config, model::dto::SimpleFileInfo};
 
-use super::time_utils;
+use super::{time_utils, cue_utils};
 
 /// Supported audio file extensions.
 static AUDIO_EXTENSIONS : Lazy<HashSet<&'static str>> = Lazy::new(|| {
@@ -14,27 +14,152 @@ static AUDIO_EXTENSIONS : Lazy<HashSet<&'static str>> = Lazy::new(|| {
         .collect()
 });
 
+/// MIME types of supported audio files.
+static AUDIO_MIME_TYPES: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
+    [
+        ("wav", "audio/wav"),
+        ("mp3", "audio/mpeg"),
+        ("flac", "audio/flac"),
+        ("ogg", "audio/ogg"),
+        ("m4a", "audio/mp4"),
+        ("aac", "audio/aac"),
+        ("wma", "audio/x-ms-wma"),
+        ("opus", "audio/ogg"),
+    ]
+    .iter()
+    .cloned()
+    .collect()
+});
+
+/// Cuesheet file extension.
+static CUE_EXTENSION: &str = "cue";
+
 /// 获取媒体目录下的所有音频文件信息
+/// 包含 cuesheet 文件，被 cuesheet 引用的整轨文件不会单独列出
 /// @returns 音频文件信息列表
 pub fn list_audio_file() -> Vec<SimpleFileInfo> {
+    list_audio_file_in(Path::new(""), true)
+}
+
+/// 获取媒体目录下指定目录中的音频文件信息，规则与 list_audio_file 相同
+/// @param dir 相对于媒体目录的路径，不存在时返回空列表
+/// @param recursive 是否包含子目录
+/// @returns 音频文件信息列表
+pub fn list_audio_file_in(dir: &Path, recursive: bool) -> Vec<SimpleFileInfo> {
     let mut audio_file_list: Vec<SimpleFileInfo> = Vec::new();
-    let dir_map = WalkDir::new(app_config::AUDIO_PATH)
+    let mut cue_file_list: Vec<SimpleFileInfo> = Vec::new();
+    // cuesheet 引用的整轨文件
+    let mut cue_media_set: HashSet<PathBuf> = HashSet::new();
+    let max_depth = if recursive { usize::MAX } else { 1 };
+    let dir_map = WalkDir::new(PathBuf::from(app_config::AUDIO_PATH).join(dir))
+        .max_depth(max_depth)
         .follow_links(true)
         .into_iter()
         .filter_map(|e| e.ok());
     for entry in dir_map {
-        if entry.file_type().is_file() {
-            if let Some(ext) = entry.path().extension() {
-                let ext: &str = &ext.to_str().unwrap().to_lowercase();
-                if AUDIO_EXTENSIONS.contains(ext) {
-                    audio_file_list.push(dir_entry_to_simple_file_info(&entry));
-                }
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        if is_audio_file(entry.path()) {
+            audio_file_list.push(dir_entry_to_simple_file_info(&entry));
+        } else if is_cue_file(entry.path()) {
+            let media_path = cue_utils::read_cue_sheet(&entry.path())
+                .ok()
+                .and_then(|(sheet, _)| cue_utils::get_cue_media_path(entry.path(), &sheet));
+            // 无法关联到整轨文件的 cuesheet 不作处理
+            if let Some(media_path) = media_path {
+                cue_media_set.insert(to_relative_path(&media_path));
+                cue_file_list.push(dir_entry_to_simple_file_info(&entry));
             }
         }
     }
+    audio_file_list.retain(|simple_file_info| !cue_media_set.contains(&simple_file_info.path));
+    audio_file_list.extend(cue_file_list);
     audio_file_list
 }
 
+/// 判断是否为支持的音频文件
+/// @param path 文件路径
+/// @returns 是否为音频文件
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| AUDIO_EXTENSIONS.contains(ext.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// 判断是否为 cuesheet 文件
+/// @param path 文件路径
+/// @returns 是否为 cuesheet 文件
+pub fn is_cue_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.eq_ignore_ascii_case(CUE_EXTENSION))
+        .unwrap_or(false)
+}
+
+/// 判断是否为分碟目录（例如: "CD1", "CD 2", "Disc_3", "Disk-04"）
+/// 专辑分组和查找封面时都把分碟目录视为专辑目录的一部分
+/// @param name 目录名
+/// @returns 是否为分碟目录
+pub fn is_disc_dir_name(name: &str) -> bool {
+    let name = name.trim().to_ascii_lowercase();
+    ["cd", "disc", "disk"].iter().any(|prefix| {
+        name.strip_prefix(prefix)
+            .map(|number| number.trim_start_matches([' ', '_', '-', '.']))
+            .map(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+            .unwrap_or(false)
+    })
+}
+
+/// 获取相对于媒体目录的路径
+/// @param path 媒体目录下的文件路径
+/// @returns 相对路径
+pub fn to_relative_path(path: &Path) -> PathBuf {
+    path.strip_prefix(app_config::AUDIO_PATH).unwrap_or(path).to_path_buf()
+}
+
+/// 获取音频文件的 MIME 类型
+/// @param path 文件路径
+/// @returns MIME 类型
+pub fn get_audio_mime_type(path: &Path) -> &'static str {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| AUDIO_MIME_TYPES.get(ext.to_lowercase().as_str()))
+        .copied()
+        .unwrap_or("application/octet-stream")
+}
+
+/// 将文件信息中的路径映射到媒体目录下
+/// 路径中包含上级目录、根目录或盘符时拒绝访问
+/// 扫描时会跟随符号链接，所以这里不检查符号链接的目标
+/// @param components 相对于媒体目录的路径
+/// @returns 文件路径
+pub fn resolve_media_path(components: &[String]) -> io::Result<PathBuf> {
+    let relative_path = list_to_path(components);
+    // 每一段都必须是普通的文件名，不能包含路径分隔符
+    let is_plain = !components.is_empty()
+        && relative_path.components().count() == components.len()
+        && relative_path.components().all(|component| matches!(component, Component::Normal(_)));
+    if !is_plain {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Path escapes the library root"));
+    }
+    let path = PathBuf::from(app_config::AUDIO_PATH).join(relative_path);
+    if !path.is_file() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "Media file not found"));
+    }
+    Ok(path)
+}
+
+/// 根据相对于媒体目录的路径读取文件信息
+/// @param path 相对路径
+/// @returns 文件信息结构体
+pub fn path_to_simple_file_info(path: &Path) -> std::io::Result<SimpleFileInfo> {
+    let metadata = fs::metadata(PathBuf::from(app_config::AUDIO_PATH).join(path))?;
+    let last_modified = time_utils::time_to_millis(&metadata.modified()?);
+    Ok(SimpleFileInfo::new(path, metadata.len(), last_modified))
+}
+
 /// 将 DirEntry 转换成文件信息结构体
 /// @param entry DirEntry
 /// @returns 文件信息结构体
@@ -57,7 +182,7 @@ pub fn path_to_list(path: &PathBuf) -> Vec<String> {
 /// 字符串列表转路径
 /// @param list 字符串列表
 /// @returns 路径
-pub fn list_to_path(list: &Vec<String>) -> PathBuf {
+pub fn list_to_path(list: &[String]) -> PathBuf {
     list.iter().fold(PathBuf::new(), |mut path, e| {
         path.push(e);
         path
@@ -9,7 +9,7 @@ use crate::{
     config::app_config::HASH_SEED
 };
 
-use super::audio_utils::get_best_audio_stream_index;
+use super::{audio_utils::get_best_audio_stream_index, cancellation::CancellationToken};
 
 /// 计算 Hash 值
 fn hash(f: &dyn Fn(&mut Xxh3)) -> u128 {
@@ -23,7 +23,8 @@ fn hash(f: &dyn Fn(&mut Xxh3)) -> u128 {
 /// @return Hash 值
 pub fn hash_media_file_info(file_info: &SimpleFileInfo) -> u128 {
     // 简单处理不同平台的文件路径差异
-    let origin_file_path = file_info.path.as_os_str().to_str().unwrap();
+    // 不是 UTF-8 的路径替换无法解码的字符，不会 panic
+    let origin_file_path = file_info.path.to_string_lossy();
     let unify_file_path = origin_file_path.replace("\\", "/");
     hash(&|hasher| {
         hasher.write(unify_file_path.as_bytes());
@@ -36,10 +37,19 @@ pub fn hash_media_file_info(file_info: &SimpleFileInfo) -> u128 {
 /// @param file_path 媒体文件路径
 /// @return 音频数据 Hash 值
 pub fn hash_audio_data(file_path: &PathBuf) -> Result<u128> {
+    hash_audio_data_with_cancel(file_path, &CancellationToken::new())
+}
+
+/// 计算媒体文件音频数据的 Hash 值，可以中途取消
+/// @param file_path 媒体文件路径
+/// @param cancel 取消令牌，取消时返回 Cancelled 错误
+/// @return 音频数据 Hash 值
+pub fn hash_audio_data_with_cancel(file_path: &PathBuf, cancel: &CancellationToken) -> Result<u128> {
     let mut hasher = Xxh3::with_seed(HASH_SEED);
     let mut input_ctx = format::input(file_path)?;
     if let Some(audio_stream_index) = get_best_audio_stream_index(&input_ctx) {
         for (stream, packet) in input_ctx.packets() {
+            cancel.check()?;
             if stream.index() == audio_stream_index {
                 match packet.data() {
                     Some(data) => {
@@ -79,4 +89,29 @@ pub fn hash_data(data: &[u8]) -> u128 {
     let mut hasher = Xxh3::with_seed(HASH_SEED);
     hasher.write(data);
     hasher.digest128()
+}
+
+/// 计算 cuesheet 音轨的音频数据 Hash 值
+/// 由整轨文件的音频数据 Hash 和音轨起始位置组成，与文件路径无关
+/// @param audio_hash 整轨文件音频数据 Hash 值
+/// @param index_time 音轨起始位置（毫秒）
+/// @return Hash 值
+pub fn hash_cue_track(audio_hash: u128, index_time: u128) -> u128 {
+    hash(&|hasher| {
+        hasher.write_u128(audio_hash);
+        hasher.write_u128(index_time);
+    })
+}
+
+/// 计算标识的 Hash 值
+/// 每一部分都写入长度，避免 ("ab", "c") 与 ("a", "bc") 相同
+/// @param parts 组成标识的各部分
+/// @return Hash 值
+pub fn hash_identity(parts: &[&str]) -> u128 {
+    hash(&|hasher| {
+        for part in parts {
+            hasher.write_usize(part.len());
+            hasher.write(part.as_bytes());
+        }
+    })
 }
\ No newline at end of file
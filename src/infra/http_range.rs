@@ -0,0 +1,76 @@
+/// 最多支持的范围数量，超过时按完整请求处理
+static MAX_RANGES: usize = 32;
+
+/// 字节范围，包含结尾
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    /// 范围长度
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Content-Range 响应头的值
+    /// @param total 文件大小
+    pub fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+/// 解析 Range 请求头
+/// 重叠或相邻的范围会被合并
+/// @param header Range 请求头的值（例如: "bytes=0-499,-500"）
+/// @param total 文件大小
+/// @return 格式错误时返回 None（按完整请求处理），没有可满足的范围时返回空列表
+pub fn parse_range(header: &str, total: u64) -> Option<Vec<ByteRange>> {
+    let specs = header.trim().strip_prefix("bytes=")?;
+    let mut ranges: Vec<ByteRange> = Vec::new();
+    let mut count = 0;
+    for spec in specs.split(',').map(str::trim).filter(|spec| !spec.is_empty()) {
+        count += 1;
+        if count > MAX_RANGES {
+            return None;
+        }
+        let (start, end) = spec.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+        let range = if start.is_empty() {
+            // 后缀范围: 最后 n 个字节
+            let suffix_length: u64 = end.parse().ok()?;
+            if suffix_length == 0 || total == 0 {
+                continue;
+            }
+            ByteRange { start: total.saturating_sub(suffix_length), end: total - 1 }
+        } else {
+            let start: u64 = start.parse().ok()?;
+            let end: u64 = if end.is_empty() { u64::MAX } else { end.parse().ok()? };
+            if end < start {
+                return None;
+            }
+            if start >= total {
+                continue;
+            }
+            ByteRange { start, end: end.min(total - 1) }
+        };
+        ranges.push(range);
+    }
+    if count == 0 {
+        return None;
+    }
+
+    // 合并重叠或相邻的范围
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end);
+            },
+            _ => merged.push(range),
+        }
+    }
+    Some(merged)
+}
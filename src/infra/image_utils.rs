@@ -1,19 +1,49 @@
-use std::{path::Path, cmp, fs};
+use std::{path::Path, cmp, fs, sync::atomic::{AtomicU64, Ordering}};
 
 use anyhow::Result;
-use image::{imageops::FilterType, ImageOutputFormat};
+use image::{imageops::FilterType, DynamicImage, ImageFormat, ImageOutputFormat};
+
+/// 临时文件序号
+static TEMP_FILE_SEQ: AtomicU64 = AtomicU64::new(0);
 
 /// 生成缩略图
+/// 先写入临时文件再重命名，避免同时请求时读到不完整的文件
 /// @param input 输入文件
 /// @param output 输出文件
-pub fn convert_to_thumbnail<P: AsRef<Path>>(input: &P, output: &P) -> Result<()> {
-    let img = image::open(input)?;
-    let new_width = cmp::min(img.width(), 512);
-    let new_heigth = cmp::min(img.height(), 512);
-    
+/// @param size 最大宽高，小于这个尺寸的图片不放大
+pub fn convert_to_thumbnail<P: AsRef<Path>>(input: &P, output: &P, size: u32) -> Result<()> {
+    // 原图保存时没有扩展名，根据文件内容判断格式
+    let img = image::io::Reader::open(input)?.with_guessed_format()?.decode()?;
+    let new_width = cmp::min(img.width(), size);
+    let new_heigth = cmp::min(img.height(), size);
+
     let scaled = img.resize(new_width, new_heigth, FilterType::Triangle);
-    fs::create_dir_all(output.as_ref().parent().unwrap())?;
-    let mut output_file = fs::File::create(output)?;
-    scaled.write_to(&mut output_file, ImageOutputFormat::Jpeg(80))?;
+    // JPEG 不支持透明通道
+    let scaled = DynamicImage::ImageRgb8(scaled.to_rgb8());
+    let output = output.as_ref();
+    fs::create_dir_all(output.parent().unwrap())?;
+    let seq = TEMP_FILE_SEQ.fetch_add(1, Ordering::Relaxed);
+    let temp_output = output.with_extension(format!("{}-{}.part", std::process::id(), seq));
+    let mut output_file = fs::File::create(&temp_output)?;
+    if let Err(err) = scaled.write_to(&mut output_file, ImageOutputFormat::Jpeg(80)) {
+        let _ = fs::remove_file(&temp_output);
+        return Err(err.into());
+    }
+    fs::rename(&temp_output, output)?;
     Ok(())
+}
+
+/// 根据文件内容判断图片的 MIME 类型
+/// @param data 图片数据
+/// @return MIME 类型，无法识别时返回 None
+pub fn sniff_mime_type(data: &[u8]) -> Option<&'static str> {
+    match image::guess_format(data).ok()? {
+        ImageFormat::Jpeg => Some("image/jpeg"),
+        ImageFormat::Png => Some("image/png"),
+        ImageFormat::Gif => Some("image/gif"),
+        ImageFormat::WebP => Some("image/webp"),
+        ImageFormat::Bmp => Some("image/bmp"),
+        ImageFormat::Tiff => Some("image/tiff"),
+        _ => None,
+    }
 }
\ No newline at end of file
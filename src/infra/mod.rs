@@ -4,4 +4,15 @@ pub mod time_utils;
 pub mod audio_utils;
 pub mod transcoder;
 pub mod audio_filter;
-pub mod image_utils;
\ No newline at end of file
+pub mod image_utils;
+pub mod cue_utils;
+pub mod encoding_utils;
+pub mod http_range;
+pub mod avio;
+pub mod transcode_cache;
+pub mod transcode_preset;
+pub mod cover_utils;
+pub mod cancellation;
+pub mod error_utils;
+pub mod cron_utils;
+pub mod tag_utils;
\ No newline at end of file
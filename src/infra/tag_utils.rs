@@ -0,0 +1,75 @@
+/// 序号类标签的值
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TagNumber {
+    /// 序号
+    pub number: u32,
+    /// 总数，"3/12" 形式时才有
+    pub total: Option<u32>,
+    /// 黑胶唱片的面，A 面为 1，"A1" 形式时才有
+    pub side: Option<u32>,
+}
+
+/// 解析序号类的标签值
+/// 支持 "3"、"3/12"、黑胶唱片的 "A1"、"B2" 和罗马数字 "IV"
+/// @param text 标签值
+/// @return 无法解析时返回 None
+pub fn parse_number(text: &str) -> Option<TagNumber> {
+    let (number, total) = match text.split_once('/') {
+        Some((number, total)) => (number.trim(), total.trim().parse().ok().filter(|total| *total > 0)),
+        None => (text.trim(), None),
+    };
+    if let Ok(number) = number.parse() {
+        return Some(TagNumber { number, total, side: None });
+    }
+    if let Some(number) = parse_roman(number) {
+        return Some(TagNumber { number, total, side: None });
+    }
+    let mut chars = number.chars();
+    let side = chars.next().filter(char::is_ascii_alphabetic)?;
+    let number = chars.as_str().parse().ok()?;
+    Some(TagNumber {
+        number,
+        total,
+        side: Some(side.to_ascii_uppercase() as u32 - 'A' as u32 + 1),
+    })
+}
+
+/// 罗马数字的各个位，从大到小
+static ROMAN_DIGITS: [(u32, &str); 9] = [
+    (100, "C"), (90, "XC"), (50, "L"), (40, "XL"), (10, "X"), (9, "IX"), (5, "V"), (4, "IV"), (1, "I"),
+];
+
+/// 解析罗马数字，古典音乐的乐章常用罗马数字编号
+/// 只支持 I、V、X、L、C，避免把其他字母当成数字，只接受规范的写法（例如不接受 "IIII"、"VX"、"IC"）
+/// 单独的 "C"、"L" 更可能是黑胶唱片的面，不作为罗马数字
+fn parse_roman(text: &str) -> Option<u32> {
+    let text = text.to_ascii_uppercase();
+    if text == "C" || text == "L" {
+        return None;
+    }
+    let values: Vec<u32> = text.chars()
+        .map(|c| ROMAN_DIGITS.iter().find(|(_, digit)| digit.len() == 1 && digit.starts_with(c)).map(|(value, _)| *value))
+        .collect::<Option<Vec<u32>>>()?;
+    let mut number: i64 = 0;
+    for (i, value) in values.iter().enumerate() {
+        // 较小的数字在较大的数字左边时表示减去
+        match values.get(i + 1) {
+            Some(next) if next > value => number -= *value as i64,
+            _ => number += *value as i64,
+        }
+    }
+    let number = u32::try_from(number).ok().filter(|number| *number > 0)?;
+    (to_roman(number) == text).then_some(number)
+}
+
+/// 转换成规范的罗马数字
+fn to_roman(mut number: u32) -> String {
+    let mut text = String::new();
+    for (value, digit) in ROMAN_DIGITS {
+        while number >= value {
+            text.push_str(digit);
+            number -= value;
+        }
+    }
+    text
+}
\ No newline at end of file
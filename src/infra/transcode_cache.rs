@@ -0,0 +1,209 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Condvar, Mutex},
+    time::SystemTime,
+};
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+
+use crate::{
+    config::app_config::{OTHER_AUDIO_QUALITY_PATH, TRANSCODE_CACHE_MAX_SIZE},
+    model::dto::TranscodeCacheEntry,
+    repository::{file_info, transcode_cache},
+};
+
+use super::time_utils;
+
+/// 正在转码的缓存，同一个缓存只允许一个转码任务
+static ENCODING: Lazy<(Mutex<HashSet<String>>, Condvar)> = Lazy::new(|| {
+    (Mutex::new(HashSet::new()), Condvar::new())
+});
+
+/// 转码任务凭证
+/// 持有期间其他请求相同缓存的任务会等待，释放时清理未提交的临时文件
+pub struct EncodeTicket {
+    audio_hash: String,
+    variant: String,
+    extension: &'static str,
+}
+
+impl EncodeTicket {
+    /// 缓存文件路径（相对于转码缓存目录）
+    fn relative_path(&self) -> String {
+        format!("{}/{}.{}", self.variant, self.audio_hash, self.extension)
+    }
+
+    /// 转码输出的临时文件路径
+    pub fn temp_path(&self) -> PathBuf {
+        let mut path = cache_file_path(&self.relative_path()).into_os_string();
+        path.push(".part");
+        PathBuf::from(path)
+    }
+
+    /// 转码完成后提交缓存，超出容量时淘汰最久没有访问的缓存
+    /// @return 缓存文件路径
+    pub fn commit(self) -> Result<PathBuf> {
+        let relative_path = self.relative_path();
+        let path = cache_file_path(&relative_path);
+        fs::rename(self.temp_path(), &path)?;
+        transcode_cache::set(&TranscodeCacheEntry {
+            audio_hash: self.audio_hash.clone(),
+            variant: self.variant.clone(),
+            path: relative_path,
+            size: fs::metadata(&path)?.len(),
+            last_access: now(),
+        });
+        evict(TRANSCODE_CACHE_MAX_SIZE);
+        Ok(path)
+    }
+}
+
+impl Drop for EncodeTicket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(self.temp_path());
+        let (encoding, condvar) = &*ENCODING;
+        encoding.lock().unwrap().remove(&to_key(&self.audio_hash, &self.variant));
+        condvar.notify_all();
+    }
+}
+
+fn to_key(audio_hash: &str, variant: &str) -> String {
+    format!("{}/{}", audio_hash, variant)
+}
+
+fn cache_file_path(relative_path: &str) -> PathBuf {
+    OTHER_AUDIO_QUALITY_PATH.join(relative_path)
+}
+
+fn now() -> u128 {
+    time_utils::time_to_millis(&SystemTime::now())
+}
+
+/// 获取缓存文件，同时更新访问时间
+/// @param audio_hash 源音频数据 Hash
+/// @param variant 转码规格
+/// @return 缓存文件路径
+pub fn get(audio_hash: &str, variant: &str) -> Option<PathBuf> {
+    let entry = transcode_cache::get(audio_hash, variant)?;
+    let path = cache_file_path(&entry.path);
+    if !path.is_file() {
+        transcode_cache::remove(audio_hash, variant);
+        return None;
+    }
+    transcode_cache::touch(audio_hash, variant, now());
+    Some(path)
+}
+
+/// 开始转码
+/// @param audio_hash 源音频数据 Hash
+/// @param variant 转码规格
+/// @param extension 缓存文件扩展名
+/// @return 转码任务凭证，已经有相同的转码任务时返回 None
+pub fn begin(audio_hash: &str, variant: &str, extension: &'static str) -> Option<EncodeTicket> {
+    let (encoding, _) = &*ENCODING;
+    if !encoding.lock().unwrap().insert(to_key(audio_hash, variant)) {
+        return None;
+    }
+    let ticket = EncodeTicket {
+        audio_hash: audio_hash.to_string(),
+        variant: variant.to_string(),
+        extension,
+    };
+    if let Some(parent) = ticket.temp_path().parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    Some(ticket)
+}
+
+/// 等待相同的转码任务结束
+/// @param audio_hash 源音频数据 Hash
+/// @param variant 转码规格
+pub fn wait(audio_hash: &str, variant: &str) {
+    let (encoding, condvar) = &*ENCODING;
+    let key = to_key(audio_hash, variant);
+    let _guard = condvar
+        .wait_while(encoding.lock().unwrap(), |encoding| encoding.contains(&key))
+        .unwrap();
+}
+
+/// 获取缓存文件，没有缓存时转码
+/// 同时请求相同缓存时只会转码一次
+/// @param audio_hash 源音频数据 Hash
+/// @param variant 转码规格
+/// @param extension 缓存文件扩展名
+/// @param transcode 转码到指定路径
+/// @return 缓存文件路径
+pub fn get_or_transcode<F: FnOnce(&Path) -> Result<()>>(
+    audio_hash: &str,
+    variant: &str,
+    extension: &'static str,
+    transcode: F
+) -> Result<PathBuf> {
+    loop {
+        if let Some(path) = get(audio_hash, variant) {
+            return Ok(path);
+        }
+        match begin(audio_hash, variant, extension) {
+            Some(ticket) => {
+                transcode(&ticket.temp_path())?;
+                return ticket.commit();
+            },
+            None => wait(audio_hash, variant),
+        }
+    }
+}
+
+/// 淘汰最久没有访问的缓存，直到总大小不超过容量
+/// 最近访问的一个缓存总是保留，避免单个文件超出容量时刚转码就被删除
+/// @param max_size 缓存容量
+pub fn evict(max_size: u64) {
+    let mut entries = transcode_cache::list();
+    let mut total_size: u64 = entries.iter().map(|entry| entry.size).sum();
+    if total_size <= max_size {
+        return;
+    }
+    entries.sort_by_key(|entry| entry.last_access);
+    let count = entries.len().saturating_sub(1);
+    for entry in entries.iter().take(count) {
+        if total_size <= max_size {
+            break;
+        }
+        remove(entry);
+        total_size -= entry.size;
+    }
+}
+
+/// 删除源音频已经不在媒体库中的缓存
+/// @param audio_hashs 媒体库中的音频数据 Hash
+pub fn prune(audio_hashs: &HashSet<String>) {
+    transcode_cache::list().iter()
+        .filter(|entry| !audio_hashs.contains(&entry.audio_hash))
+        .for_each(remove);
+}
+
+/// 根据当前的文件信息删除失效的缓存
+pub fn prune_orphans() {
+    let audio_hashs: HashSet<String> = file_info::list().into_values()
+        .flat_map(|file_info| file_info.medias)
+        .map(|media| media.audio_hash)
+        .collect();
+    prune(&audio_hashs);
+}
+
+/// 删除缓存文件已经不存在的记录
+/// @return 删除的记录数
+pub fn remove_missing() -> usize {
+    let missing: Vec<TranscodeCacheEntry> = transcode_cache::list().into_iter()
+        .filter(|entry| !cache_file_path(&entry.path).is_file())
+        .collect();
+    missing.iter().for_each(remove);
+    missing.len()
+}
+
+fn remove(entry: &TranscodeCacheEntry) {
+    let _ = fs::remove_file(cache_file_path(&entry.path));
+    transcode_cache::remove(&entry.audio_hash, &entry.variant);
+}
\ No newline at end of file
@@ -0,0 +1,146 @@
+extern crate ffmpeg_next as ffmpeg;
+
+use std::ffi::CString;
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+
+use super::{audio_utils, cancellation::CancellationToken, transcoder::{self, StreamFormat, Transcoder}};
+
+/// 转码预设
+#[derive(Clone)]
+pub struct Preset {
+    /// 预设名称，同时用作转码缓存的规格名称
+    pub name: String,
+    /// 输出格式（封装格式和编码）
+    pub stream_format: &'static StreamFormat,
+    /// 通道数
+    pub channels: i32,
+    /// 采样率，不指定时使用编码器要求的采样率或源文件的采样率
+    pub sample_rate: Option<i32>,
+    /// 码率，无损编码不指定
+    pub bit_rate: Option<usize>,
+    /// 音频滤镜
+    pub filter_spec: Option<&'static str>,
+}
+
+impl Preset {
+    /// 根据格式和码率创建临时预设
+    /// 与内置预设的格式和码率相同时使用内置预设，转码缓存不会重复
+    /// @param stream_format 输出格式
+    /// @param bit_rate 码率
+    /// @return 预设，名称为格式和码率（例如: "opus-96k"）
+    pub fn custom(stream_format: &'static StreamFormat, bit_rate: usize) -> Preset {
+        let builtin = PRESETS.iter().find(|preset| {
+            preset.stream_format.name == stream_format.name && preset.bit_rate == Some(bit_rate) && preset.filter_spec.is_none()
+        });
+        if let Some(preset) = builtin {
+            return preset.clone();
+        }
+        Preset {
+            name: format!("{}-{}k", stream_format.name, bit_rate / 1000),
+            stream_format,
+            channels: 2,
+            sample_rate: None,
+            bit_rate: Some(bit_rate),
+            filter_spec: None,
+        }
+    }
+
+    /// 创建转码器
+    /// @param cancel 调用者的取消令牌，取消后转码中止
+    pub fn transcoder(&self, cancel: &CancellationToken) -> Transcoder {
+        Transcoder {
+            output_filter_spec: self.filter_spec.map(str::to_string),
+            codec: Some(self.stream_format.codec.to_string()),
+            channels: Some(self.channels),
+            sample_rate: self.sample_rate.or(self.stream_format.sample_rate),
+            bit_rate: self.bit_rate,
+            max_bit_rate: self.bit_rate,
+            cancel: cancel.clone(),
+        }
+    }
+
+    /// 估计的码率，无损编码按 16bit PCM 计算，用于比较音质
+    pub fn estimated_bit_rate(&self) -> usize {
+        self.bit_rate.unwrap_or_else(|| {
+            self.sample_rate.or(self.stream_format.sample_rate).unwrap_or(44100) as usize * self.channels as usize * 16
+        })
+    }
+
+    /// 检查链接的 ffmpeg 是否支持预设的编码和封装格式
+    pub fn validate(&self) -> Result<()> {
+        audio_utils::create_codec_by_name(self.stream_format.codec)?;
+        let format_name = CString::new(self.stream_format.format_name)?;
+        let muxer = unsafe {
+            ffmpeg::ffi::av_guess_format(format_name.as_ptr(), std::ptr::null(), std::ptr::null())
+        };
+        if muxer.is_null() {
+            return Err(anyhow!("Failed to find {} muxer", self.stream_format.format_name));
+        }
+        Ok(())
+    }
+}
+
+/// 内置的转码预设
+static PRESETS: Lazy<Vec<Preset>> = Lazy::new(|| {
+    let preset = |name: &str, format_name: &str, sample_rate: i32, bit_rate: Option<usize>, filter_spec: Option<&'static str>| Preset {
+        name: name.to_string(),
+        stream_format: transcoder::find_stream_format(format_name).unwrap(),
+        channels: 2,
+        sample_rate: Some(sample_rate),
+        bit_rate,
+        filter_spec,
+    };
+    vec![
+        preset("opus-64", "opus", 48000, Some(64000), None),
+        preset("opus-128", "opus", 48000, Some(128000), None),
+        preset("aac-256", "aac", 44100, Some(256000), None),
+        preset("mp3-320", "mp3", 44100, Some(320000), None),
+        // 高解析度音频降为 16bit 44.1kHz，降低位深时加入抖动
+        preset("flac-16-44", "flac", 44100, None, Some("aresample=44100:dither_method=triangular")),
+    ]
+});
+
+/// 获取内置的转码预设
+pub fn presets() -> &'static [Preset] {
+    &PRESETS
+}
+
+/// 检查链接的 ffmpeg 是否支持所有内置的转码预设，启动时调用
+/// @return 不支持的预设和原因
+pub fn validate_all() -> Result<()> {
+    let errors: Vec<String> = PRESETS.iter()
+        .filter_map(|preset| preset.validate().err().map(|err| format!("{}: {}", preset.name, err)))
+        .collect();
+    if !errors.is_empty() {
+        return Err(anyhow!("Unsupported transcode presets: {}", errors.join("; ")));
+    }
+    Ok(())
+}
+
+/// 根据名称查找转码预设
+/// @param name 预设名称（例如: "opus-128"）
+/// @return 预设
+pub fn find(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|preset| preset.name.eq_ignore_ascii_case(name))
+}
+
+/// 为客户端选择最合适的转码预设
+/// 在客户端支持的格式中选择码率不超过上限且音质最高的预设，都超过上限时选择码率最低的预设
+/// @param max_bit_rate 客户端声明的最大码率，不限制时为 None
+/// @param formats 客户端支持的格式名称（例如: ["opus", "aac"]），为空时不限制
+/// @return 预设
+pub fn select(max_bit_rate: Option<usize>, formats: &[String]) -> Option<&'static Preset> {
+    let candidates: Vec<&'static Preset> = PRESETS.iter()
+        .filter(|preset| {
+            formats.is_empty() || formats.iter().any(|format| format.eq_ignore_ascii_case(preset.stream_format.name))
+        })
+        .collect();
+    // 音质相同时靠前的预设优先
+    let best = candidates.iter()
+        .filter(|preset| max_bit_rate.map(|max| preset.estimated_bit_rate() <= max).unwrap_or(true))
+        .rev()
+        .max_by_key(|preset| preset.estimated_bit_rate());
+    best.or_else(|| candidates.iter().min_by_key(|preset| preset.estimated_bit_rate())).copied()
+}
\ No newline at end of file
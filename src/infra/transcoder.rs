@@ -1,11 +1,58 @@
 extern crate ffmpeg_next as ffmpeg;
 
-use std::path::Path;
+use std::{io::Write, path::Path};
 
 use anyhow::{Ok, Result};
-use ffmpeg::{format, frame, Packet, decoder, encoder, filter};
+use ffmpeg::{codec, format, frame, Packet, decoder, encoder, filter};
+
+use super::{audio_utils, audio_filter, avio::WriterOutput, cancellation::CancellationToken};
+
+/// 边转码边输出的格式
+pub struct StreamFormat {
+    /// 格式名称（请求参数）
+    pub name: &'static str,
+    /// 封装格式名称
+    pub format_name: &'static str,
+    /// 音频编码库名称
+    pub codec: &'static str,
+    /// MIME 类型
+    pub mime_type: &'static str,
+    /// 缓存文件扩展名
+    pub extension: &'static str,
+    /// 编码器要求的采样率
+    pub sample_rate: Option<i32>,
+}
+
+/// 支持的边转码边输出格式，封装格式需要支持不可 seek 的输出
+static STREAM_FORMATS: [StreamFormat; 5] = [
+    StreamFormat { name: "opus", format_name: "ogg", codec: "libopus", mime_type: "audio/ogg", extension: "opus", sample_rate: Some(48000) },
+    StreamFormat { name: "vorbis", format_name: "ogg", codec: "libvorbis", mime_type: "audio/ogg", extension: "ogg", sample_rate: None },
+    StreamFormat { name: "mp3", format_name: "mp3", codec: "libmp3lame", mime_type: "audio/mpeg", extension: "mp3", sample_rate: None },
+    StreamFormat { name: "aac", format_name: "adts", codec: "aac", mime_type: "audio/aac", extension: "aac", sample_rate: None },
+    StreamFormat { name: "flac", format_name: "flac", codec: "flac", mime_type: "audio/flac", extension: "flac", sample_rate: None },
+];
+
+/// 根据名称查找边转码边输出的格式
+/// @param name 格式名称（例如: "opus"）
+/// @return 格式
+pub fn find_stream_format(name: &str) -> Option<&'static StreamFormat> {
+    STREAM_FORMATS.iter().find(|format| format.name.eq_ignore_ascii_case(name))
+}
 
-use super::{audio_utils, audio_filter};
+/// 解析码率，按 kbit/s 取整
+/// @param text 码率（例如: "96k", "320000"）
+/// @return 码率（bit/s）
+pub fn parse_bit_rate(text: &str) -> Option<usize> {
+    let text = text.trim().to_ascii_lowercase();
+    let (number, unit) = match text.strip_suffix('k') {
+        Some(number) => (number, 1000),
+        None => (text.as_str(), 1),
+    };
+    number.parse::<usize>().ok()
+        .and_then(|number| number.checked_mul(unit))
+        .map(|bit_rate| bit_rate / 1000 * 1000)
+        .filter(|bit_rate| *bit_rate > 0)
+}
 
 pub struct Transcoder {
     pub output_filter_spec: Option<String>,
@@ -14,6 +61,8 @@ pub struct Transcoder {
     pub sample_rate: Option<i32>,
     pub bit_rate: Option<usize>,
     pub max_bit_rate: Option<usize>,
+    /// 取消令牌，取消时停止转码并返回 Cancelled 错误
+    pub cancel: CancellationToken,
 }
 
 impl Transcoder {
@@ -70,10 +119,39 @@ impl Transcoder {
         Ok(())
     }
 
+    /// 转码到文件
+    /// @param input 输入文件路径
+    /// @param output 输出文件路径，没有指定编码时根据扩展名选择
     pub fn transcode<P: AsRef<Path>>(&self, input: &P, output: &P) -> Result<()> {
-        // 输入输出上下文
-        let mut input_ctx = format::input(&input)?;
         let mut output_ctx = format::output(&output)?;
+        let codec = match self.codec.as_deref() {
+            Some(name) => audio_utils::create_codec_by_name(name)?,
+            None => audio_utils::guess_codec_by_path(output, &output_ctx)?,
+        };
+        self.transcode_to_ctx(input, &mut output_ctx, codec)
+    }
+
+    /// 转码并写入任意输出（例如 HTTP 响应），编码的同时输出数据
+    /// @param input 输入文件路径
+    /// @param format_name 封装格式名称（例如: "ogg"），没有指定编码时使用封装格式的默认编码
+    /// @param writer 输出目标，写入失败时停止转码
+    pub fn transcode_to_writer<P: AsRef<Path>, W: Write>(&self, input: &P, format_name: &str, writer: W) -> Result<()> {
+        let mut output_ctx = WriterOutput::new(format_name, writer)?;
+        let codec = match self.codec.as_deref() {
+            Some(name) => audio_utils::create_codec_by_name(name)?,
+            None => audio_utils::guess_codec_by_path(&format_name, &output_ctx)?,
+        };
+        self.transcode_to_ctx(input, &mut output_ctx, codec)
+    }
+
+    fn transcode_to_ctx<P: AsRef<Path>>(
+        &self,
+        input: &P,
+        output_ctx: &mut format::context::Output,
+        codec: codec::Audio
+    ) -> Result<()> {
+        // 输入上下文
+        let mut input_ctx = format::input(&input)?;
 
         // 创建解码器
         let audio_stream = audio_utils::find_best_stream(&input_ctx)?;
@@ -81,10 +159,6 @@ impl Transcoder {
         let mut decoder = audio_utils::create_decoder_by_stream(audio_stream)?;
 
         // 编码器参数
-        let codec = self.codec
-            .as_deref()
-            .map(|name| {audio_utils::create_codec_by_name(name)})
-            .unwrap_or(audio_utils::guess_codec_by_path(output, &output_ctx))?;
         let channels = self.channels.unwrap_or(decoder.channel_layout().channels());
         let sample_rate = self.sample_rate.unwrap_or(decoder.rate() as i32);
         let bit_rate = self.bit_rate.unwrap_or(decoder.bit_rate());
@@ -92,7 +166,7 @@ impl Transcoder {
 
         // 创建编码器并配置输出上下文
         let (mut encoder, output_time_base) = audio_utils::create_encoder_with_output_ctx(
-            codec, &mut output_ctx, channels, decoder.rate() as i32,
+            codec, output_ctx, channels, decoder.rate() as i32,
             sample_rate, bit_rate, max_bit_rate)?;
 
         // 写文件头
@@ -104,26 +178,27 @@ impl Transcoder {
 
         // 开始转码
         for (stream, mut packet) in input_ctx.packets() {
+            self.cancel.check()?;
             // 取出容器内的音频数据
             if stream.index() == audio_stream_index {
                 // 转换时间基
                 packet.rescale_ts(stream.time_base(), decoder.time_base());
                 decoder.send_packet(&packet)?;
-                Transcoder::receive_and_process_decoded_frame(&mut decoder, &mut filter, &mut encoder, output_time_base, &mut output_ctx)?;
+                Transcoder::receive_and_process_decoded_frame(&mut decoder, &mut filter, &mut encoder, output_time_base, output_ctx)?;
             }
         }
 
         // 解码结束
         decoder.send_eof()?;
-        Transcoder::receive_and_process_decoded_frame(&mut decoder, &mut filter, &mut encoder, output_time_base, &mut output_ctx)?;
+        Transcoder::receive_and_process_decoded_frame(&mut decoder, &mut filter, &mut encoder, output_time_base, output_ctx)?;
 
         // flush filter
         filter.get("in").unwrap().source().flush()?;
-        Transcoder::process_filtered_frames(&mut filter, &mut decoder, &mut encoder, output_time_base, &mut output_ctx)?;
+        Transcoder::process_filtered_frames(&mut filter, &mut decoder, &mut encoder, output_time_base, output_ctx)?;
 
         // 编码结束
         encoder.send_eof()?;
-        Transcoder::receive_and_process_encoded_packet(&mut decoder, &mut encoder, output_time_base, &mut output_ctx)?;
+        Transcoder::receive_and_process_encoded_packet(&mut decoder, &mut encoder, output_time_base, output_ctx)?;
 
         // 写文件尾
         output_ctx.write_trailer()?;
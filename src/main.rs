@@ -1,13 +1,56 @@
 use actix_web::{App, HttpServer, middleware};
-use shadow_music_cloud::{service::*};
+use shadow_music_cloud::{command::{actor, command, scheduler, watcher}, config::app_config, infra::transcode_preset, service::*};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    // 检查转码预设使用的编码器是否可用，不可用时无法启动
+    if let Err(error) = transcode_preset::validate_all() {
+        return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, error.to_string()));
+    }
+    let presets: Vec<&str> = transcode_preset::presets().iter()
+        .map(|preset| preset.name.as_str())
+        .collect();
+    println!("Transcode presets: {}", presets.join(", "));
+
+    // 恢复重启前没有结束的任务
+    let recovered = actor::recover();
+    println!("Recovered {} jobs", recovered.len());
+
+    // 启动时扫描媒体库，扫描结束后会清理失效的转码缓存
+    let _ = command::scan_library();
+
+    // 定时扫描媒体库、淘汰转码缓存
+    scheduler::start();
+
+    // 监听媒体目录，新增、修改、删除或重命名的文件几秒内更新到文件信息中
+    if app_config::WATCH_ENABLED {
+        if let Err(error) = watcher::start() {
+            println!("Failed to watch {}: {}", app_config::AUDIO_PATH, error);
+        }
+    }
+
     HttpServer::new(|| {
         App::new()
         .wrap(middleware::Compress::default())
         .service(media::list)
         .service(media::list_diff)
+        .service(media::changes)
+        .service(media::ids)
+        .service(library::track)
+        .service(library::albums)
+        .service(library::album)
+        .service(library::artists)
+        .service(library::artist)
+        .service(library::genres)
+        .service(library::genre)
+        .service(stream::stream)
+        .service(cover::cover)
+        .service(admin::scan)
+        .service(admin::transcode)
+        .service(admin::schedules)
+        .service(job::list)
+        .service(job::get)
+        .service(job::cancel)
     })
     .bind(("127.0.0.1", 8080))?
     .run()
@@ -1,15 +1,16 @@
-use std::{
-    path::{PathBuf, Path}, ffi::OsStr, fs::{File, self}, io::Write
-};
+use std::{fmt::{Debug, Display}, path::{PathBuf, Path}};
 
-use lofty::{TagItem, ItemKey, ItemValue, Accessor};
+use anyhow::Result;
+use cuna::{track::Track, CueSheet};
+use encoding_rs::Encoding;
+use lofty::{id3::v2::{EncodedTextFrame, FrameValue, Id3v2Tag}, Tag, TagItem, ItemKey, ItemValue, Accessor};
 use radix_fmt::radix;
 use serde::{Deserialize, Serialize};
 
-use crate::{infra::{hash_utils, audio_utils}, config};
+use crate::{infra::{cancellation::{self, CancellationToken}, error_utils, hash_utils, audio_utils, cover_utils, cue_utils, file_utils, encoding_utils, tag_utils::{self, TagNumber}}, config};
 
 /// 媒体信息
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct MediaInfo {
     /// 序号
@@ -19,12 +20,60 @@ pub struct MediaInfo {
     /// 标题
     #[serde(skip_serializing_if = "Option::is_none")]
     pub title: Option<String>,
-    /// 歌手
+    /// 歌手，显示用的原始文本
     #[serde(skip_serializing_if = "Option::is_none")]
     pub artist: Option<String>,
+    /// 歌手，多个歌手分开记录
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artists: Vec<String>,
     /// 专辑
     #[serde(skip_serializing_if = "Option::is_none")]
     pub album: Option<String>,
+    /// 专辑歌手
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album_artist: Option<String>,
+    /// 流派
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub genres: Vec<String>,
+    /// 日期，保留标签中的原始格式
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    /// 年份
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<u32>,
+    /// 作曲
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub composer: Option<String>,
+    /// 指挥
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conductor: Option<String>,
+    /// 音轨总数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_tracks: Option<u32>,
+    /// 光碟总数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_discs: Option<u32>,
+    /// 注释
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// 唱片公司
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// 唱片编号
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub catalog_number: Option<String>,
+    /// 国际标准录音编码
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub isrc: Option<String>,
+    /// 是否为合辑
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub compilation: bool,
+    /// MusicBrainz 录音 ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub musicbrainz_recording_id: Option<String>,
+    /// MusicBrainz 发行 ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub musicbrainz_release_id: Option<String>,
     /// 音频数据 Hash
     pub audio_hash: String,
     /// 起始位置（毫秒）
@@ -36,7 +85,7 @@ pub struct MediaInfo {
 }
 
 /// 文件信息
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct FileInfo {
     /// 文件路径
@@ -57,8 +106,353 @@ pub struct FileInfo {
     pub cue_media_file_info_hash: Option<String>,
     /// 专辑封面 Hash
     pub cover_hash: Option<String>,
+    /// 文本编码，标签或 cuesheet 经过编码修复时记录使用的编码
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_encoding: Option<String>,
     /// 媒体文件信息
     pub medias: Vec<MediaInfo>,
+    /// 生成文件信息时遇到的问题
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<FileWarning>,
+}
+
+/// 生成文件信息时遇到的问题种类
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum FileWarningKind {
+    /// 无法读取音频属性
+    Properties,
+    /// 无法读取标签
+    Tags,
+    /// 标签值无法解析，使用了默认值
+    TagValue,
+    /// 无法保存封面
+    Cover,
+    /// 无法计算音频数据 Hash
+    AudioHash,
+    /// 无法读取 cuesheet
+    CueSheet,
+    /// 无法读取 cuesheet 关联的媒体文件
+    CueMedia,
+}
+
+/// 生成文件信息时遇到的问题，文件信息仍然可以使用，但可能不完整
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FileWarning {
+    /// 种类
+    pub kind: FileWarningKind,
+    /// 说明
+    pub message: String,
+}
+
+/// 文件列表差异
+/// 客户端提交已有的文件信息 Hash，与服务端的文件信息比较
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FileInfoDiff {
+    /// 客户端没有的文件信息
+    pub added: Vec<FileInfo>,
+    /// 服务端已删除的文件信息 Hash
+    pub removed: Vec<String>,
+    /// 未变化的文件信息 Hash
+    pub unchanged: Vec<String>,
+    /// 客户端的文件已经移动或重命名
+    pub moved: Vec<FileInfoMove>,
+}
+
+/// 文件移动或重命名
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileInfoMove {
+    /// 客户端持有的文件信息 Hash
+    pub from: String,
+    /// 移动后的文件信息
+    pub file_info: FileInfo,
+}
+
+/// 文件信息变更类型
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeType {
+    /// 添加或更新
+    Insert,
+    /// 删除
+    Remove,
+    /// 移动或重命名，音频数据没有变化，沿用原来的文件信息
+    Moved,
+}
+
+/// 文件信息变更记录
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeLogEntry {
+    /// 序号，单调递增
+    pub seq: u64,
+    /// 变更类型
+    pub change_type: ChangeType,
+    /// 文件信息 Hash
+    pub file_info_hash: String,
+    /// 移动前的文件信息 Hash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_file_info_hash: Option<String>,
+    /// 添加或移动后的文件信息，仅在返回给客户端时填充
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_info: Option<FileInfo>,
+}
+
+/// 变更记录查询结果
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeLogPage {
+    /// 最新的序号，客户端下次从这里继续查询
+    pub latest_seq: u64,
+    /// 变更记录
+    pub changes: Vec<ChangeLogEntry>,
+    /// 是否还有更多变更记录
+    pub has_more: bool,
+    /// 客户端的序号早于已压缩的记录时，返回当前的全部文件信息
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<Vec<FileInfo>>,
+}
+
+/// 音轨的稳定标识
+/// 由音频数据 Hash 和标签生成，文件移动、重命名或修改标签后保持不变
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackIdentity {
+    /// 音轨 ID
+    pub id: String,
+    /// 所在文件的文件信息 Hash
+    pub file_info_hash: String,
+    /// 在文件中的序号，对应 FileInfo.medias
+    pub media_index: usize,
+    /// 音频数据 Hash
+    pub audio_hash: String,
+    /// 专辑 ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album_id: Option<String>,
+    /// 歌手 ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist_id: Option<String>,
+}
+
+/// 专辑的稳定标识，由专辑歌手和专辑名或所在目录生成
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumIdentity {
+    /// 专辑 ID
+    pub id: String,
+    /// 专辑名
+    pub title: String,
+    /// 专辑歌手
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+}
+
+/// 文件包含的音轨、专辑和歌手 ID
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FileIdentity {
+    /// 音轨 ID，顺序与 FileInfo.medias 相同
+    pub tracks: Vec<String>,
+    /// 专辑 ID，不重复
+    pub albums: Vec<String>,
+    /// 歌手 ID，不重复
+    pub artists: Vec<String>,
+}
+
+/// 专辑中的音轨
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumTrack {
+    /// 音轨 ID
+    pub id: String,
+    /// 所在文件的文件信息 Hash
+    pub file_info_hash: String,
+    /// 在文件中的序号，对应 FileInfo.medias
+    pub media_index: usize,
+    /// 序号
+    pub track: u32,
+    /// 标题
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// 歌手
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    /// 歌手，多个歌手分开记录
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artists: Vec<String>,
+    /// 时长（毫秒）
+    pub duration: u128,
+}
+
+/// 专辑中的一张光碟
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumDisc {
+    /// 光碟序号
+    pub disc: u32,
+    /// 总时长（毫秒）
+    pub duration: u128,
+    /// 音轨，按序号排序
+    pub tracks: Vec<AlbumTrack>,
+}
+
+/// 专辑概要
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumSummary {
+    /// 专辑 ID
+    pub id: String,
+    /// 专辑名，没有专辑标签时为目录名
+    pub title: String,
+    /// 专辑歌手，没有专辑歌手标签时为所有音轨相同的歌手
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    /// 专辑歌手 ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist_id: Option<String>,
+    /// 年份
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<u32>,
+    /// 流派，按音轨数从多到少排序
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub genres: Vec<String>,
+    /// 是否为合辑
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub compilation: bool,
+    /// 专辑封面 Hash
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cover_hash: Option<String>,
+    /// 光碟数
+    pub disc_count: usize,
+    /// 音轨数
+    pub track_count: usize,
+    /// 总时长（毫秒）
+    pub duration: u128,
+}
+
+/// 专辑
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Album {
+    #[serde(flatten)]
+    pub summary: AlbumSummary,
+    /// 光碟，按序号排序
+    pub discs: Vec<AlbumDisc>,
+}
+
+/// 歌手概要
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ArtistSummary {
+    /// 歌手 ID
+    pub id: String,
+    /// 歌手名
+    pub name: String,
+    /// 专辑数，包括只参与了部分音轨的专辑
+    pub album_count: usize,
+    /// 音轨数
+    pub track_count: usize,
+    /// 总时长（毫秒）
+    pub duration: u128,
+}
+
+/// 歌手
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Artist {
+    #[serde(flatten)]
+    pub summary: ArtistSummary,
+    /// 专辑，按年份排序
+    pub albums: Vec<AlbumSummary>,
+}
+
+/// 流派概要
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GenreSummary {
+    /// 流派 ID
+    pub id: String,
+    /// 流派名
+    pub name: String,
+    /// 专辑数，包括只有部分音轨属于这个流派的专辑
+    pub album_count: usize,
+    /// 音轨数
+    pub track_count: usize,
+    /// 总时长（毫秒）
+    pub duration: u128,
+}
+
+/// 流派
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Genre {
+    #[serde(flatten)]
+    pub summary: GenreSummary,
+    /// 专辑，按专辑歌手和专辑名排序
+    pub albums: Vec<AlbumSummary>,
+}
+
+/// 转码缓存记录
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscodeCacheEntry {
+    /// 源音频数据 Hash
+    pub audio_hash: String,
+    /// 转码规格（例如: "opus-96k"）
+    pub variant: String,
+    /// 缓存文件路径（相对于转码缓存目录）
+    pub path: String,
+    /// 缓存文件大小
+    pub size: u64,
+    /// 最后访问时间
+    pub last_access: u128,
+}
+
+/// 任务因重启中断后的恢复方式
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RecoveryPolicy {
+    /// 重新执行，命令需要跳过已经完成的工作（例如已经有缓存的转码）
+    Resume,
+    /// 放弃执行，任务标记为失败
+    Discard,
+}
+
+/// 持久化的任务，重启后根据恢复方式继续执行
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedJob {
+    /// 任务编号
+    pub id: u64,
+    /// 动作名称，用于重新创建动作
+    pub name: String,
+    /// 动作参数
+    pub args: serde_json::Value,
+    /// 恢复方式
+    pub recovery: RecoveryPolicy,
+    /// 已经开始执行的次数
+    pub runs: u32,
+    /// 创建时间
+    pub created_at: u128,
+}
+
+/// 持久化的定时计划执行状态
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleState {
+    /// 计划名称
+    pub name: String,
+    /// 计划表达式，表达式改变后重新计算下次执行时间
+    pub schedule: String,
+    /// 上次执行时间
+    pub last_run: Option<u128>,
+    /// 下次执行时间
+    pub next_run: Option<u128>,
+    /// 上次执行的任务编号
+    pub last_job_id: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -75,101 +469,524 @@ impl SimpleFileInfo {
     pub fn new(path: &Path, size: u64, last_modified: u128) -> SimpleFileInfo {
         SimpleFileInfo {
             path: path.to_path_buf(),
-            size: size,
-            last_modified: last_modified,
+            size,
+            last_modified,
             file_info_hash: None,
         }
     }
 }
 
+impl MediaInfo {
+    /// 从音频文件的标签读取媒体信息，无法解析的标签值记录为问题
+    /// @param tag 标签
+    /// @param simple 简略文件信息
+    /// @param override_encoding 目录指定的编码
+    /// @param text_encoding 发生修复时记录使用的编码
+    /// @param warnings 生成文件信息时遇到的问题
+    pub fn read_tag(
+        &mut self,
+        tag: &Tag,
+        simple: &SimpleFileInfo,
+        override_encoding: Option<&'static Encoding>,
+        text_encoding: &mut Option<String>,
+        warnings: &mut Vec<FileWarning>,
+    ) {
+        self.title = get_tag_text(tag.title(), override_encoding, text_encoding);
+        self.artist = get_tag_text(tag.artist(), override_encoding, text_encoding);
+        self.album = get_tag_text(tag.album(), override_encoding, text_encoding);
+        read_album_tags(tag, self, override_encoding, text_encoding);
+        read_track_tags(tag, self, override_encoding, text_encoding);
+        let track = get_number_item(tag, &ItemKey::TrackNumber, simple, warnings);
+        let disc = get_number_item(tag, &ItemKey::DiscNumber, simple, warnings);
+        self.track = track.map_or(1, |track| track.number);
+        self.total_tracks = self.total_tracks.or(track.and_then(|track| track.total));
+        // 黑胶唱片的每一面作为一张光碟
+        self.disc = disc.map(|disc| disc.number)
+            .or(track.and_then(|track| track.side))
+            .unwrap_or(1);
+        self.total_discs = self.total_discs.or(disc.and_then(|disc| disc.total));
+    }
+
+    /// 从 ID3v2 标签读取 MusicBrainz ID
+    /// 录音 ID 在 UFID 帧中，发行 ID 在描述为 "MusicBrainz Album Id" 的 TXXX 帧中
+    /// @param tag ID3v2 标签
+    pub fn read_id3v2_tag(&mut self, tag: &Id3v2Tag) {
+        for frame in tag.iter() {
+            match (frame.id_str(), frame.content()) {
+                ("UFID", FrameValue::Binary(data)) => {
+                    // 所有者和 ID 之间用 \0 分隔
+                    if let Some((owner, id)) = split_once_null(data) {
+                        if owner == MUSICBRAINZ_UFID_OWNER.as_bytes() {
+                            self.musicbrainz_recording_id = non_empty_text(&String::from_utf8_lossy(id));
+                        }
+                    }
+                },
+                ("TXXX", FrameValue::UserText(EncodedTextFrame { description, content, .. })) => {
+                    if description.eq_ignore_ascii_case(MUSICBRAINZ_RELEASE_ID_DESCRIPTION) {
+                        self.musicbrainz_release_id = non_empty_text(content);
+                    } else if description.eq_ignore_ascii_case(MUSICBRAINZ_TRACK_ID_DESCRIPTION) && self.musicbrainz_recording_id.is_none() {
+                        self.musicbrainz_recording_id = non_empty_text(content);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// 从 cuesheet 的注释读取整张专辑共有的信息（REM DATE/GENRE/COMMENT/TOTALDISCS）和 CATALOG
+    /// cuesheet 中没有的保留原来的值
+    /// @param sheet cuesheet
+    pub fn read_cue_comments(&mut self, sheet: &CueSheet) {
+        if let Some(date) = cue_utils::get_comment(sheet, "DATE") {
+            self.year = parse_year(date);
+            self.date = Some(date.to_string());
+        }
+        let genres = cue_utils::get_comment(sheet, "GENRE").map(split_texts);
+        if let Some(genres) = genres.filter(|genres| !genres.is_empty()) {
+            self.genres = genres;
+        }
+        if let Some(comment) = cue_utils::get_comment(sheet, "COMMENT") {
+            self.comment = Some(comment.to_string());
+        }
+        if let Some(total_discs) = cue_utils::get_comment(sheet, "TOTALDISCS").and_then(|total| total.trim().parse().ok()) {
+            self.total_discs = Some(total_discs);
+        }
+        if let Some(catalog) = sheet.catalog() {
+            // CATALOG 为 13 位的 UPC/EAN
+            self.catalog_number = Some(format!("{:013}", catalog));
+        }
+    }
+}
+
 impl FileInfo {
     /// 从简略文件信息生成媒体文件信息
     /// 会将专辑封面保存到文件
-    pub fn from_simple(simple: &SimpleFileInfo) -> FileInfo {
-        // 注：目前不支持 cuesheet 文件
+    /// 读取文件时遇到暂时性的 I/O 错误会返回错误，其他错误只记录日志
+    pub fn from_simple(simple: &SimpleFileInfo) -> Result<FileInfo> {
+        FileInfo::from_simple_with_cancel(simple, &CancellationToken::new())
+    }
+
+    /// 从简略文件信息生成媒体文件信息，计算音频数据 Hash 时可以中途取消
+    /// 取消、超时或者遇到暂时性的 I/O 错误时返回错误，调用者可以重试
+    /// @param simple 简略文件信息
+    /// @param cancel 取消令牌
+    pub fn from_simple_with_cancel(simple: &SimpleFileInfo, cancel: &CancellationToken) -> Result<FileInfo> {
+        if file_utils::is_cue_file(&simple.path) {
+            FileInfo::from_cue_simple(simple, cancel)
+        } else {
+            FileInfo::from_audio_simple(simple, cancel)
+        }
+    }
+
+    /// 从简略文件信息生成音频文件信息
+    fn from_audio_simple(simple: &SimpleFileInfo, cancel: &CancellationToken) -> Result<FileInfo> {
         let mut media_info = MediaInfo::default();
-        let mut cover_hash: Option<String> = None;
+        let mut embedded_tag: Option<Tag> = None;
+        let mut text_encoding: Option<String> = None;
+        let mut warnings: Vec<FileWarning> = Vec::new();
 
         let media_file_path = PathBuf::from(config::app_config::AUDIO_PATH).join(&simple.path);
+        let override_encoding = encoding_utils::find_override_encoding(&media_file_path);
         // 获取音频属性
         match audio_utils::get_properties_from_media_file(&media_file_path) {
             Ok(properties) => {
                 media_info.bitrate = properties.audio_bitrate().unwrap_or(0);
-                media_info.duration = properties.duration().as_micros();
+                media_info.duration = properties.duration().as_millis();
             },
-            Err(err) => println!("{}", err),
+            Err(err) => warn(&mut warnings, simple, FileWarningKind::Properties, err),
         }
         // 获取音频标签
         match audio_utils::get_tags_from_media_file(&media_file_path) {
             Ok(tag) => {
                 // 如果文件有标签
-                media_info.title = tag.title().map(|s| s.to_string());
-                media_info.artist = tag.artist().map(|s| s.to_string());
-                media_info.album = tag.album().map(|s| s.to_string());
-                if let Some(track) = tag.get_item_ref(&ItemKey::TrackNumber).map(TagItem::value) {
-                    match track {
-                        ItemValue::Text(text) => media_info.track = text.parse().unwrap_or(1),
-                        ItemValue::Locator(text) => media_info.track = text.parse().unwrap_or(1),
-                        ItemValue::Binary(binary) => {
-                            panic!("{} has binary track number: {:?}", simple.path.display(), binary)
-                        },
-                    }
-                } else {
-                    media_info.track = 1;
-                }
-                if let Some(disc) = tag.get_item_ref(&ItemKey::DiscNumber).map(TagItem::value) {
-                    match disc {
-                        ItemValue::Text(text) => media_info.disc = text.parse().unwrap_or(1),
-                        ItemValue::Locator(text) => media_info.disc = text.parse().unwrap_or(1),
-                        ItemValue::Binary(binary) => {
-                            panic!("{} has binary disc number: {:?}", simple.path.display(), binary)
-                        },
-                    }
-                } else {
-                    media_info.disc = 1;
-                }
-                // 提取专辑封面
-                if tag.picture_count() > 0 {
-                    let first_picture = tag.pictures().first().unwrap();
-                    let cover_picture = tag.get_picture_type(lofty::PictureType::CoverFront)
-                        .unwrap_or(first_picture);
-                    let picture_data_hash = radix(hash_utils::hash_data(cover_picture.data()), 36).to_string();
-                    let cover_path = PathBuf::from(config::app_config::ORIGIN_COVER_PATH).join(&picture_data_hash);
-                    cover_hash = Some(picture_data_hash);
-                    // 保存专辑封面到文件
-                    if !cover_path.exists() {
-                        fs::create_dir_all(&cover_path.parent().unwrap()).unwrap();
-                        let mut cover_file = File::create(&cover_path).unwrap();
-                        cover_file.write_all(cover_picture.data()).unwrap();
-                    }
-                }
+                media_info.read_tag(&tag, simple, override_encoding, &mut text_encoding, &mut warnings);
+                embedded_tag = Some(tag);
             },
-            Err(err) => println!("{}", err),
+            Err(err) => warn(&mut warnings, simple, FileWarningKind::Tags, err),
+        }
+        match audio_utils::get_id3v2_tag_from_media_file(&media_file_path) {
+            Ok(Some(tag)) => media_info.read_id3v2_tag(&tag),
+            Ok(None) => {},
+            Err(err) => warn(&mut warnings, simple, FileWarningKind::Tags, err),
         }
+        // 内嵌封面或专辑目录中的封面图片
+        let cover_hash = cover_utils::select_cover(&media_file_path, || {
+            embedded_tag.as_ref().and_then(|tag| save_cover(tag, simple, &mut warnings))
+        });
         // 计算音频数据 Hash
-        match hash_utils::hash_audio_data(&media_file_path) {
+        match hash_utils::hash_audio_data_with_cancel(&media_file_path, cancel) {
             Ok(hash) => media_info.audio_hash = radix(hash, 36).to_string(),
-            Err(err) => println!("{}", err),
+            Err(err) if should_retry(&err) => return Err(err),
+            Err(err) => warn(&mut warnings, simple, FileWarningKind::AudioHash, err),
         }
 
-        FileInfo {
-            path: simple.path.components()
-                .map(|x| x.as_os_str().to_string_lossy().into_owned())
-                .collect(),
-            file_type: if simple.path.extension() == Some(OsStr::new("cue")) {
-                "cuesheet".to_string()
-            } else {
-                "audio".to_string()
-            },
+        Ok(FileInfo {
+            path: path_to_components(&simple.path),
+            file_type: "audio".to_string(),
             size: simple.size,
             last_modified: simple.last_modified,
-            file_info_hash: simple.file_info_hash.as_ref().unwrap_or({
-                &radix(hash_utils::hash_media_file_info(simple), 36).to_string()
-            }).to_string(),
+            file_info_hash: get_file_info_hash(simple),
             cue_media_path: None,
             cue_media_file_info_hash: None,
-            cover_hash: cover_hash,
+            cover_hash,
+            text_encoding,
             medias: vec![media_info],
+            warnings,
+        })
+    }
+
+    /// 从简略文件信息生成 cuesheet 文件信息
+    /// 每个 TRACK 对应一个媒体信息
+    fn from_cue_simple(simple: &SimpleFileInfo, cancel: &CancellationToken) -> Result<FileInfo> {
+        let mut file_info = FileInfo {
+            path: path_to_components(&simple.path),
+            file_type: "cuesheet".to_string(),
+            size: simple.size,
+            last_modified: simple.last_modified,
+            file_info_hash: get_file_info_hash(simple),
+            cue_media_path: None,
+            cue_media_file_info_hash: None,
+            cover_hash: None,
+            text_encoding: None,
+            medias: Vec::new(),
+            warnings: Vec::new(),
+        };
+
+        let cue_file_path = PathBuf::from(config::app_config::AUDIO_PATH).join(&simple.path);
+        let sheet = match cue_utils::read_cue_sheet(&cue_file_path) {
+            Ok((sheet, encoding)) => {
+                file_info.text_encoding = Some(encoding.name().to_string());
+                sheet
+            },
+            Err(err) => {
+                warn(&mut file_info.warnings, simple, FileWarningKind::CueSheet, err);
+                return Ok(file_info);
+            },
+        };
+        let media_file_path = match cue_utils::get_cue_media_path(&cue_file_path, &sheet) {
+            Some(path) => path,
+            None => {
+                warn(&mut file_info.warnings, simple, FileWarningKind::CueMedia, "No media file");
+                return Ok(file_info);
+            },
+        };
+        let media_relative_path = file_utils::to_relative_path(&media_file_path);
+        file_info.cue_media_path = Some(path_to_components(&media_relative_path).join("/"));
+        match file_utils::path_to_simple_file_info(&media_relative_path) {
+            Ok(media_simple) => file_info.cue_media_file_info_hash = Some(get_file_info_hash(&media_simple)),
+            Err(err) => warn(&mut file_info.warnings, simple, FileWarningKind::CueMedia, err),
         }
+
+        // 整轨文件的属性
+        let mut total_duration: u128 = 0;
+        let mut bitrate: u32 = 0;
+        match audio_utils::get_properties_from_media_file(&media_file_path) {
+            Ok(properties) => {
+                bitrate = properties.audio_bitrate().unwrap_or(0);
+                total_duration = properties.duration().as_millis();
+            },
+            Err(err) => warn(&mut file_info.warnings, simple, FileWarningKind::Properties, err),
+        }
+        // 整轨文件的标签，用于补充 cuesheet 中缺少的信息
+        let tag = match audio_utils::get_tags_from_media_file(&media_file_path) {
+            Ok(tag) => Some(tag),
+            Err(err) => {
+                warn(&mut file_info.warnings, simple, FileWarningKind::Tags, err);
+                None
+            },
+        };
+        let media_audio_hash = match hash_utils::hash_audio_data_with_cancel(&media_file_path, cancel) {
+            Ok(hash) => Some(hash),
+            Err(err) if should_retry(&err) => return Err(err),
+            Err(err) => {
+                warn(&mut file_info.warnings, simple, FileWarningKind::AudioHash, err);
+                None
+            },
+        };
+        file_info.cover_hash = cover_utils::select_cover(&media_file_path, || {
+            tag.as_ref().and_then(|tag| save_cover(tag, simple, &mut file_info.warnings))
+        });
+
+        let override_encoding = encoding_utils::find_override_encoding(&media_file_path);
+        // 以 cuesheet 的编码为准，不记录整轨文件标签的编码
+        let mut tag_encoding: Option<String> = None;
+        let album = sheet.title().first().cloned()
+            .or_else(|| tag.as_ref().and_then(|tag| get_tag_text(tag.album(), override_encoding, &mut tag_encoding)));
+        // 整张专辑共有的信息，cuesheet 中有的以 cuesheet 为准
+        let mut album_info = MediaInfo::default();
+        if let Some(tag) = &tag {
+            read_album_tags(tag, &mut album_info, override_encoding, &mut tag_encoding);
+        }
+        if let Some(performer) = sheet.performer().first() {
+            album_info.album_artist = Some(performer.clone());
+        }
+        let artist = album_info.album_artist.clone()
+            .or_else(|| tag.as_ref().and_then(|tag| get_tag_text(tag.artist(), override_encoding, &mut tag_encoding)));
+        album_info.read_cue_comments(&sheet);
+        let disc = match cue_utils::get_comment(&sheet, "DISCNUMBER") {
+            Some(disc) => parse_number_text(disc, "DISCNUMBER", simple, &mut file_info.warnings),
+            None => tag.as_ref().and_then(|tag| get_number_item(tag, &ItemKey::DiscNumber, simple, &mut file_info.warnings)),
+        };
+        let disc = disc.map_or(1, |disc| disc.number);
+
+        let tracks: Vec<&Track> = sheet.tracks().collect();
+        for (i, track) in tracks.iter().enumerate() {
+            let index_time = match cue_utils::get_track_index_time(track) {
+                Some(index_time) => index_time,
+                None => continue,
+            };
+            // 音轨结束位置为下一个音轨的起始位置，最后一个音轨持续到文件结尾
+            let end_time = tracks.get(i + 1)
+                .and_then(|next| cue_utils::get_track_index_time(next))
+                .unwrap_or(total_duration);
+            let artist = track.performer().first().cloned().or_else(|| artist.clone());
+            file_info.medias.push(MediaInfo {
+                track: track.id() as u32,
+                disc,
+                title: track.title().first().cloned(),
+                artists: artist.as_deref().map(split_texts).unwrap_or_default(),
+                artist,
+                album: album.clone(),
+                composer: track.songwriter().first().cloned(),
+                total_tracks: Some(tracks.len() as u32),
+                isrc: track.isrc().map(str::to_string),
+                audio_hash: media_audio_hash
+                    .map(|hash| radix(hash_utils::hash_cue_track(hash, index_time), 36).to_string())
+                    .unwrap_or_default(),
+                index_time,
+                duration: end_time.saturating_sub(index_time),
+                bitrate,
+                ..album_info.clone()
+            });
+        }
+        Ok(file_info)
+    }
+}
+
+/// 是否需要中止生成文件信息，由调用者处理或重试
+/// 取消、超时和暂时性的 I/O 错误生成的文件信息不完整
+fn should_retry(err: &anyhow::Error) -> bool {
+    cancellation::is_cancelled(err) || cancellation::is_timed_out(err) || error_utils::is_transient(err)
+}
+
+/// 路径转字符串列表
+fn path_to_components(path: &Path) -> Vec<String> {
+    path.components()
+        .map(|x| x.as_os_str().to_string_lossy().into_owned())
+        .collect()
+}
+
+/// 获取文件信息 Hash，没有预先计算时现场计算
+fn get_file_info_hash(simple: &SimpleFileInfo) -> String {
+    simple.file_info_hash.clone().unwrap_or_else(|| {
+        radix(hash_utils::hash_media_file_info(simple), 36).to_string()
+    })
+}
+
+/// 读取标签文本，修复按 Latin-1 错误解码的文本
+/// @param text 标签文本
+/// @param override_encoding 目录指定的编码
+/// @param text_encoding 发生修复时记录使用的编码
+/// @return 标签文本
+fn get_tag_text(
+    text: Option<&str>,
+    override_encoding: Option<&'static Encoding>,
+    text_encoding: &mut Option<String>,
+) -> Option<String> {
+    let text = text?;
+    match encoding_utils::repair(text, override_encoding) {
+        Some(decoded) => {
+            *text_encoding = Some(decoded.encoding.name().to_string());
+            Some(decoded.text)
+        },
+        None => Some(text.to_string()),
+    }
+}
+
+/// 读取整张专辑共有的标签
+/// @param tag 标签
+/// @param media_info 媒体信息
+/// @param override_encoding 目录指定的编码
+/// @param text_encoding 发生修复时记录使用的编码
+fn read_album_tags(
+    tag: &Tag,
+    media_info: &mut MediaInfo,
+    override_encoding: Option<&'static Encoding>,
+    text_encoding: &mut Option<String>,
+) {
+    media_info.album_artist = get_tag_text(tag.get_string(&ItemKey::AlbumArtist), override_encoding, text_encoding);
+    media_info.genres = get_tag_texts(tag, &ItemKey::Genre, override_encoding, text_encoding);
+    // 没有录音日期时使用年份
+    media_info.date = tag.get_string(&ItemKey::RecordingDate)
+        .or_else(|| tag.get_string(&ItemKey::Year))
+        .map(str::trim)
+        .filter(|date| !date.is_empty())
+        .map(str::to_string);
+    media_info.year = media_info.date.as_deref().and_then(parse_year);
+    media_info.total_discs = get_total_item(tag, &ItemKey::DiscTotal);
+    media_info.comment = get_tag_text(tag.get_string(&ItemKey::Comment), override_encoding, text_encoding);
+    media_info.label = get_tag_text(tag.get_string(&ItemKey::Label), override_encoding, text_encoding);
+    media_info.catalog_number = get_tag_text(tag.get_string(&ItemKey::CatalogNumber), override_encoding, text_encoding);
+    media_info.compilation = get_flag_item(tag, &ItemKey::FlagCompilation);
+    media_info.musicbrainz_release_id = get_unknown_item(tag, &MUSICBRAINZ_RELEASE_ID_KEYS);
+}
+
+/// 读取单个音轨的标签
+/// @param tag 标签
+/// @param media_info 媒体信息
+/// @param override_encoding 目录指定的编码
+/// @param text_encoding 发生修复时记录使用的编码
+fn read_track_tags(
+    tag: &Tag,
+    media_info: &mut MediaInfo,
+    override_encoding: Option<&'static Encoding>,
+    text_encoding: &mut Option<String>,
+) {
+    media_info.artists = get_tag_texts(tag, &ItemKey::TrackArtist, override_encoding, text_encoding);
+    media_info.composer = get_tag_text(tag.get_string(&ItemKey::Composer), override_encoding, text_encoding);
+    media_info.conductor = get_tag_text(tag.get_string(&ItemKey::Conductor), override_encoding, text_encoding);
+    media_info.total_tracks = get_total_item(tag, &ItemKey::TrackTotal);
+    media_info.isrc = tag.get_string(&ItemKey::ISRC).map(str::trim).filter(|isrc| !isrc.is_empty()).map(str::to_string);
+    media_info.musicbrainz_recording_id = get_unknown_item(tag, &MUSICBRAINZ_RECORDING_ID_KEYS);
+}
+
+/// MusicBrainz 录音 ID 在各种标签中的键: Vorbis Comments 和 APE、MP4
+/// ID3v2 的 TXXX 帧转换为通用标签时丢失了描述，无法区分，由 read_id3v2_tag 读取
+static MUSICBRAINZ_RECORDING_ID_KEYS: [&str; 2] = ["MUSICBRAINZ_TRACKID", "----:com.apple.iTunes:MusicBrainz Track Id"];
+
+/// MusicBrainz 发行 ID 在各种标签中的键
+static MUSICBRAINZ_RELEASE_ID_KEYS: [&str; 2] = ["MUSICBRAINZ_ALBUMID", "----:com.apple.iTunes:MusicBrainz Album Id"];
+
+/// ID3v2 UFID 帧中 MusicBrainz 录音 ID 的所有者
+static MUSICBRAINZ_UFID_OWNER: &str = "http://musicbrainz.org";
+
+/// ID3v2 TXXX 帧中 MusicBrainz ID 的描述，旧的标签软件把录音 ID 写在 TXXX 帧中
+static MUSICBRAINZ_RELEASE_ID_DESCRIPTION: &str = "MusicBrainz Album Id";
+static MUSICBRAINZ_TRACK_ID_DESCRIPTION: &str = "MusicBrainz Track Id";
+
+/// 读取可能有多个值的标签文本
+/// 每个值可能是单独的标签项，也可能在同一个标签项中用 \0（ID3v2.4）分隔
+/// 分号可能是名称的一部分，不作为分隔符
+/// @return 去掉重复后的文本，保持原来的顺序
+fn get_tag_texts(
+    tag: &Tag,
+    key: &ItemKey,
+    override_encoding: Option<&'static Encoding>,
+    text_encoding: &mut Option<String>,
+) -> Vec<String> {
+    let mut texts: Vec<String> = Vec::new();
+    for value in tag.get_texts(key).flat_map(split_texts) {
+        if let Some(text) = get_tag_text(Some(&value), override_encoding, text_encoding) {
+            if !texts.contains(&text) {
+                texts.push(text);
+            }
+        }
+    }
+    texts
+}
+
+/// 分开用 \0 分隔的多个值，去掉空白
+fn split_texts(text: &str) -> Vec<String> {
+    text.split('\0')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// 读取标签库不认识的标签，键不区分大小写
+fn get_unknown_item(tag: &Tag, keys: &[&str]) -> Option<String> {
+    tag.items().iter()
+        .find(|item| matches!(item.key(), ItemKey::Unknown(name) if keys.iter().any(|key| key.eq_ignore_ascii_case(name))))
+        .and_then(|item| item.value().text())
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(str::to_string)
+}
+
+/// 去掉空白，空白的文本视为没有
+fn non_empty_text(text: &str) -> Option<String> {
+    Some(text.trim()).filter(|text| !text.is_empty()).map(str::to_string)
+}
+
+/// 在第一个 \0 处分开二进制数据
+fn split_once_null(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let index = data.iter().position(|byte| *byte == 0)?;
+    Some((&data[..index], &data[index + 1..]))
+}
+
+/// 读取开关类的标签，1、true、yes 视为打开
+fn get_flag_item(tag: &Tag, key: &ItemKey) -> bool {
+    tag.get_string(key)
+        .map(|text| matches!(text.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+/// 读取总数类的标签，没有或者无法解析时为 None
+fn get_total_item(tag: &Tag, key: &ItemKey) -> Option<u32> {
+    tag.get_string(key)
+        .and_then(|text| text.trim().parse().ok())
+        .filter(|total| *total > 0)
+}
+
+/// 从日期中解析年份，日期格式可能是 2001、2001-05-21 或 2001/05/21
+fn parse_year(date: &str) -> Option<u32> {
+    let digits: String = date.trim().chars().take_while(char::is_ascii_digit).collect();
+    match digits.len() {
+        4 => digits.parse().ok(),
+        _ => None,
+    }
+}
+
+/// 获取序号类的标签值
+/// 没有标签或者无法解析时返回 None，由调用者使用默认值，无法解析时记录问题
+/// @param tag 标签
+/// @param key 标签键
+/// @param simple 简略文件信息
+/// @param warnings 生成文件信息时遇到的问题
+fn get_number_item(tag: &Tag, key: &ItemKey, simple: &SimpleFileInfo, warnings: &mut Vec<FileWarning>) -> Option<TagNumber> {
+    match tag.get_item_ref(key).map(TagItem::value) {
+        Some(ItemValue::Text(text)) | Some(ItemValue::Locator(text)) => parse_number_text(text, key, simple, warnings),
+        Some(ItemValue::Binary(binary)) => {
+            warn(warnings, simple, FileWarningKind::TagValue, format!("{:?} has binary value of {} bytes", key, binary.len()));
+            None
+        },
+        None => None,
+    }
+}
+
+/// 解析序号类的文本，无法解析时记录问题
+fn parse_number_text(text: &str, key: impl Debug, simple: &SimpleFileInfo, warnings: &mut Vec<FileWarning>) -> Option<TagNumber> {
+    let number = tag_utils::parse_number(text);
+    if number.is_none() {
+        warn(warnings, simple, FileWarningKind::TagValue, format!("{:?} has invalid number: {}", key, text));
+    }
+    number
+}
+
+/// 记录生成文件信息时遇到的问题
+/// @param warnings 生成文件信息时遇到的问题
+/// @param simple 简略文件信息
+/// @param kind 种类
+/// @param message 说明
+fn warn(warnings: &mut Vec<FileWarning>, simple: &SimpleFileInfo, kind: FileWarningKind, message: impl Display) {
+    let message = message.to_string();
+    println!("{}: {}", simple.path.display(), message);
+    warnings.push(FileWarning { kind, message });
+}
+
+/// 提取专辑封面并保存到文件，保存失败时记录问题
+/// @return 专辑封面 Hash
+fn save_cover(tag: &Tag, simple: &SimpleFileInfo, warnings: &mut Vec<FileWarning>) -> Option<String> {
+    let first_picture = tag.pictures().first()?;
+    let cover_picture = tag.get_picture_type(lofty::PictureType::CoverFront)
+        .unwrap_or(first_picture);
+    // 保存专辑封面到文件
+    match cover_utils::save_cover_data(cover_picture.data()) {
+        Ok(cover_hash) => Some(cover_hash),
+        Err(err) => {
+            warn(warnings, simple, FileWarningKind::Cover, err);
+            None
+        },
     }
 }
\ No newline at end of file
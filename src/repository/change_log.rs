@@ -0,0 +1,172 @@
+use once_cell::sync::Lazy;
+use sled::{transaction::{ConflictableTransactionResult, TransactionalTree}, Batch, IVec, Transactional, Tree};
+
+use crate::model::dto::{ChangeLogEntry, ChangeLogPage, ChangeType};
+
+use super::file_info::{self, FILE_INFO_DB};
+
+/// 变更记录，键为大端序的序号
+pub(super) static CHANGE_LOG_TREE: Lazy<Tree> = Lazy::new(|| {
+    FILE_INFO_DB.open_tree("change_log").unwrap()
+});
+
+/// 变更记录元数据
+/// 没有保存下一个序号时（旧版本的数据）从已有的最大序号之后开始，事务中不能读取其他树，打开时初始化
+pub(super) static CHANGE_LOG_META_TREE: Lazy<Tree> = Lazy::new(|| {
+    let meta = FILE_INFO_DB.open_tree("change_log_meta").unwrap();
+    if !meta.contains_key(NEXT_SEQ_KEY).unwrap() {
+        let latest_seq = match (CHANGE_LOG_TREE.last().unwrap(), meta.get(COMPACTED_SEQ_KEY).unwrap()) {
+            (Some((key, _)), _) => decode_seq(&key),
+            (None, Some(value)) => decode_seq(&value),
+            (None, None) => 0,
+        };
+        meta.insert(NEXT_SEQ_KEY, &(latest_seq + 1).to_be_bytes()).unwrap();
+    }
+    meta
+});
+
+/// 已压缩的最大序号
+static COMPACTED_SEQ_KEY: &str = "compacted_seq";
+
+/// 下一个序号
+static NEXT_SEQ_KEY: &str = "next_seq";
+
+/// 追加变更记录
+/// @param change_type 变更类型
+/// @param file_info_hash 文件信息 Hash
+/// @return 序号
+pub fn append(change_type: ChangeType, file_info_hash: &str) -> u64 {
+    (&*CHANGE_LOG_TREE, &*CHANGE_LOG_META_TREE)
+        .transaction(|(log, meta)| append_in(log, meta, change_type, file_info_hash, None))
+        .unwrap()
+}
+
+/// 在事务中追加变更记录，与文件信息的修改一起提交
+/// 序号在事务中分配，同时追加的事务冲突时重试，提交的顺序与序号一致，读取时不会先看到较大的序号
+/// @param log 变更记录
+/// @param meta 变更记录元数据
+/// @return 序号
+pub(super) fn append_in(
+    log: &TransactionalTree,
+    meta: &TransactionalTree,
+    change_type: ChangeType,
+    file_info_hash: &str,
+    previous_file_info_hash: Option<String>
+) -> ConflictableTransactionResult<u64> {
+    let seq = next_seq_in(meta)?;
+    let entry = ChangeLogEntry {
+        seq,
+        change_type,
+        file_info_hash: file_info_hash.to_string(),
+        previous_file_info_hash,
+        file_info: None,
+    };
+    log.insert(&seq.to_be_bytes(), serde_json::to_vec(&entry).unwrap())?;
+    Ok(seq)
+}
+
+/// 在事务中分配序号
+fn next_seq_in(meta: &TransactionalTree) -> ConflictableTransactionResult<u64> {
+    let seq = meta.get(NEXT_SEQ_KEY)?.map(|value| decode_seq(&value)).unwrap_or(1);
+    meta.insert(NEXT_SEQ_KEY, &(seq + 1).to_be_bytes())?;
+    Ok(seq)
+}
+
+/// 获取已压缩的最大序号，早于这个序号的变更记录已被删除
+pub fn compacted_seq() -> u64 {
+    match CHANGE_LOG_META_TREE.get(COMPACTED_SEQ_KEY) {
+        Ok(Some(value)) => decode_seq(&value),
+        _ => 0,
+    }
+}
+
+/// 获取最新的序号
+pub fn latest_seq() -> u64 {
+    match CHANGE_LOG_TREE.last() {
+        Ok(Some((key, _))) => decode_seq(&key),
+        _ => compacted_seq(),
+    }
+}
+
+/// 查询指定序号之后的变更记录
+/// 如果指定的序号早于已压缩的记录，返回当前的全部文件信息作为快照
+/// @param since 客户端持有的序号
+/// @param limit 最多返回的记录数
+/// @return 变更记录
+pub fn list_since(since: u64, limit: usize) -> ChangeLogPage {
+    if since < compacted_seq() {
+        return snapshot_page();
+    }
+
+    let mut page = ChangeLogPage {
+        latest_seq: since,
+        ..ChangeLogPage::default()
+    };
+    for item in CHANGE_LOG_TREE.range((since + 1).to_be_bytes()..) {
+        if page.changes.len() >= limit {
+            page.has_more = true;
+            break;
+        }
+        let (_, value) = item.unwrap();
+        let mut entry: ChangeLogEntry = serde_json::from_slice(&value).unwrap();
+        if entry.change_type != ChangeType::Remove {
+            entry.file_info = file_info::get(&entry.file_info_hash);
+        }
+        page.latest_seq = entry.seq;
+        page.changes.push(entry);
+    }
+    // 读取期间压缩了变更记录时，读到的记录可能不连续
+    if since < compacted_seq() {
+        return snapshot_page();
+    }
+    page
+}
+
+/// 当前的全部文件信息作为快照
+fn snapshot_page() -> ChangeLogPage {
+    ChangeLogPage {
+        latest_seq: latest_seq(),
+        changes: Vec::new(),
+        has_more: false,
+        snapshot: Some(file_info::list().into_values().collect()),
+    }
+}
+
+/// 压缩变更记录，只保留最新的若干条
+/// 序号早于保留记录的客户端需要重新获取快照
+/// @param retain 保留的记录数
+pub fn compact(retain: usize) {
+    let total = CHANGE_LOG_TREE.len();
+    if total <= retain {
+        return;
+    }
+    let keys: Vec<IVec> = CHANGE_LOG_TREE.iter().keys().take(total - retain).map(|key| key.unwrap()).collect();
+    let compacted_seq = match keys.last() {
+        Some(key) => decode_seq(key),
+        None => return,
+    };
+    // 先记录已压缩的序号再删除，读取时不会把删除了一部分的记录当成完整的记录
+    CHANGE_LOG_META_TREE.fetch_and_update(COMPACTED_SEQ_KEY, |value| {
+        let seq = value.map(decode_seq).unwrap_or(0).max(compacted_seq);
+        Some(seq.to_be_bytes().to_vec())
+    }).unwrap();
+    let mut batch = Batch::default();
+    keys.into_iter().for_each(|key| batch.remove(key));
+    CHANGE_LOG_TREE.apply_batch(batch).unwrap();
+}
+
+/// 清空变更记录，所有客户端都需要重新获取快照
+pub fn reset() {
+    CHANGE_LOG_TREE.clear().unwrap();
+    CHANGE_LOG_META_TREE.transaction(|meta| {
+        let seq = next_seq_in(meta)?;
+        meta.insert(COMPACTED_SEQ_KEY, &seq.to_be_bytes())?;
+        Ok(())
+    }).unwrap();
+}
+
+fn decode_seq(bytes: &[u8]) -> u64 {
+    let mut buffer = [0u8; 8];
+    buffer.copy_from_slice(&bytes[..8]);
+    u64::from_be_bytes(buffer)
+}
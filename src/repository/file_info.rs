@@ -1,18 +1,46 @@
 use std::collections::{HashMap, HashSet};
 
 use once_cell::sync::Lazy;
-use sled::Db;
+use sled::{Db, Transactional, Tree};
 
-use crate::{config::app_config::FILE_INFO_STORAGE_PATH, model::dto::FileInfo};
+use crate::{config::app_config::{FILE_INFO_STORAGE_PATH, CHANGE_LOG_MAX_ENTRIES}, model::dto::{FileInfo, FileInfoDiff, FileInfoMove, ChangeType}};
+
+use super::{change_log, identity};
 
 // TODO
 // 检查没有访问权限
 // 检查磁盘已满
 
-static FILE_INFO_DB: Lazy<Db> = Lazy::new(|| {
-    sled::open(FILE_INFO_STORAGE_PATH).unwrap()
+pub(super) static FILE_INFO_DB: Lazy<Db> = Lazy::new(|| {
+    sled::open(&*FILE_INFO_STORAGE_PATH).unwrap()
+});
+
+/// 移动前的文件信息 Hash 到当前的文件信息 Hash
+/// 多次移动时都指向最后一次移动后的 Hash，不需要逐个跟随
+static MOVED_TREE: Lazy<Tree> = Lazy::new(|| {
+    FILE_INFO_DB.open_tree("moved").unwrap()
+});
+
+/// 移动记录的反向索引，键为 "当前的 Hash/移动前的 Hash"
+static MOVED_FROM_TREE: Lazy<Tree> = Lazy::new(|| {
+    FILE_INFO_DB.open_tree("moved_from").unwrap()
 });
 
+fn moved_from_key(file_info_hash: &str, previous_file_info_hash: &str) -> String {
+    format!("{}/{}", file_info_hash, previous_file_info_hash)
+}
+
+/// 指向指定文件信息 Hash 的移动前的 Hash
+fn list_moved_from(file_info_hash: &str) -> Vec<String> {
+    let prefix = format!("{}/", file_info_hash);
+    MOVED_FROM_TREE.scan_prefix(&prefix)
+        .map(|item| {
+            let (key, _) = item.unwrap();
+            String::from_utf8(key[prefix.len()..].to_vec()).unwrap()
+        })
+        .collect()
+}
+
 pub fn get(file_info_hash: &String) -> Option<FileInfo> {
     match FILE_INFO_DB.get(file_info_hash) {
         Ok(Some(value)) => {
@@ -23,6 +51,25 @@ pub fn get(file_info_hash: &String) -> Option<FileInfo> {
     }
 }
 
+/// 获取文件当前的文件信息 Hash，文件移动或重命名后返回移动后的 Hash
+/// @param file_info_hash 文件信息 Hash，可以是移动前的 Hash
+/// @return 文件已经删除时返回 None
+pub fn resolve(file_info_hash: &str) -> Option<String> {
+    if FILE_INFO_DB.contains_key(file_info_hash).unwrap() {
+        return Some(file_info_hash.to_string());
+    }
+    match MOVED_TREE.get(file_info_hash) {
+        Ok(Some(value)) => Some(String::from_utf8(value.to_vec()).unwrap()),
+        _ => None,
+    }
+}
+
+/// 获取文件信息，文件移动或重命名后返回移动后的文件信息
+/// @param file_info_hash 文件信息 Hash，可以是移动前的 Hash
+pub fn find(file_info_hash: &str) -> Option<FileInfo> {
+    resolve(file_info_hash).and_then(|file_info_hash| get(&file_info_hash))
+}
+
 pub fn list() -> HashMap<String, FileInfo> {
     let mut file_infos: HashMap<String, FileInfo> = HashMap::new();
     for item in FILE_INFO_DB.iter() {
@@ -42,17 +89,114 @@ pub fn list_key() -> HashSet<String> {
     file_infos
 }
 
-pub fn set(file_info_hash: &String, file_info: &FileInfo) {
+/// 与客户端已有的文件信息比较
+/// @param client_file_info_hashs 客户端已有的文件信息 Hash
+/// @return 差异
+pub fn diff(client_file_info_hashs: &HashSet<String>) -> FileInfoDiff {
+    let mut result = FileInfoDiff::default();
+    let mut server_file_info_hashs: HashSet<String> = HashSet::new();
+    for item in FILE_INFO_DB.iter() {
+        let (key, value) = item.unwrap();
+        let file_info_hash = String::from_utf8(key.to_vec()).unwrap();
+        if client_file_info_hashs.contains(&file_info_hash) {
+            result.unchanged.push(file_info_hash.clone());
+        } else {
+            result.added.push(serde_json::from_slice(&value).unwrap());
+        }
+        server_file_info_hashs.insert(file_info_hash);
+    }
+    // 客户端的文件已经移动时返回移动后的文件信息，不作为新增的文件信息返回
+    let mut added: HashMap<String, FileInfo> = result.added.drain(..)
+        .map(|file_info| (file_info.file_info_hash.clone(), file_info))
+        .collect();
+    for file_info_hash in client_file_info_hashs.iter().filter(|file_info_hash| !server_file_info_hashs.contains(*file_info_hash)) {
+        match resolve(file_info_hash).and_then(|moved_hash| added.remove(&moved_hash)) {
+            Some(file_info) => result.moved.push(FileInfoMove {
+                from: file_info_hash.clone(),
+                file_info,
+            }),
+            None => result.removed.push(file_info_hash.clone()),
+        }
+    }
+    result.added = added.into_values().collect();
+    result
+}
+
+/// 保存文件信息，与原来的内容完全相同时不记录变更
+pub fn set(file_info_hash: &str, file_info: &FileInfo) {
     let value = serde_json::to_vec(file_info).unwrap();
-    FILE_INFO_DB.insert(file_info_hash, value).unwrap();
+    let changed = (&**FILE_INFO_DB, &*change_log::CHANGE_LOG_TREE, &*change_log::CHANGE_LOG_META_TREE)
+        .transaction(|(db, log, meta)| {
+            if db.insert(file_info_hash, value.as_slice())?.is_some_and(|previous| previous == value.as_slice()) {
+                return Ok(false);
+            }
+            change_log::append_in(log, meta, ChangeType::Insert, file_info_hash, None)?;
+            Ok(true)
+        })
+        .unwrap();
+    if changed {
+        identity::assign(file_info);
+    }
 }
 
-pub fn remove(file_info_hash: &String) {
-    FILE_INFO_DB.remove(file_info_hash).unwrap();
+/// 删除文件信息，同时删除指向这个文件的移动记录
+pub fn remove(file_info_hash: &str) {
+    let moved_from = list_moved_from(file_info_hash);
+    let removed = (&**FILE_INFO_DB, &*MOVED_TREE, &*MOVED_FROM_TREE, &*change_log::CHANGE_LOG_TREE, &*change_log::CHANGE_LOG_META_TREE)
+        .transaction(|(db, moved, moved_index, log, meta)| {
+            if db.remove(file_info_hash)?.is_none() {
+                return Ok(false);
+            }
+            for previous_file_info_hash in &moved_from {
+                moved.remove(previous_file_info_hash.as_str())?;
+                moved_index.remove(moved_from_key(file_info_hash, previous_file_info_hash).as_str())?;
+            }
+            change_log::append_in(log, meta, ChangeType::Remove, file_info_hash, None)?;
+            Ok(true)
+        })
+        .unwrap();
+    if removed {
+        identity::remove(file_info_hash);
+    }
+}
+
+/// 文件移动或重命名，沿用原来的文件信息
+/// 记录移动前的 Hash，客户端持有移动前的 Hash 时仍然可以找到文件，音轨 ID 保持不变
+/// @param old_file_info_hash 移动前的文件信息 Hash
+/// @param file_info 移动后的文件信息
+pub fn rename(old_file_info_hash: &str, file_info: &FileInfo) {
+    let value = serde_json::to_vec(file_info).unwrap();
+    let new_file_info_hash = file_info.file_info_hash.as_str();
+    // 之前移动到旧 Hash 的记录改为指向新的 Hash
+    let mut moved_from = list_moved_from(old_file_info_hash);
+    moved_from.push(old_file_info_hash.to_string());
+    (&**FILE_INFO_DB, &*MOVED_TREE, &*MOVED_FROM_TREE, &*change_log::CHANGE_LOG_TREE, &*change_log::CHANGE_LOG_META_TREE)
+        .transaction(|(db, moved, moved_index, log, meta)| {
+            db.remove(old_file_info_hash)?;
+            db.insert(new_file_info_hash, value.as_slice())?;
+            for previous_file_info_hash in &moved_from {
+                moved_index.remove(moved_from_key(old_file_info_hash, previous_file_info_hash).as_str())?;
+                // 移动回原来的位置时删除旧的移动记录
+                if previous_file_info_hash == new_file_info_hash {
+                    moved.remove(new_file_info_hash)?;
+                    continue;
+                }
+                moved.insert(previous_file_info_hash.as_str(), new_file_info_hash)?;
+                moved_index.insert(moved_from_key(new_file_info_hash, previous_file_info_hash).as_str(), &[])?;
+            }
+            change_log::append_in(log, meta, ChangeType::Moved, new_file_info_hash, Some(old_file_info_hash.to_string()))?;
+            Ok(())
+        })
+        .unwrap();
+    identity::migrate(old_file_info_hash, file_info);
 }
 
 pub fn clear() {
     FILE_INFO_DB.clear().unwrap();
+    MOVED_TREE.clear().unwrap();
+    MOVED_FROM_TREE.clear().unwrap();
+    identity::clear();
+    change_log::reset();
 }
 
 pub fn sync(data: &HashMap<String, FileInfo>) {
@@ -71,4 +215,5 @@ pub fn sync(data: &HashMap<String, FileInfo>) {
             set(file_info_hash, file_info);
         }
     }
+    change_log::compact(CHANGE_LOG_MAX_ENTRIES);
 }
\ No newline at end of file
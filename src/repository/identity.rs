@@ -0,0 +1,206 @@
+use once_cell::sync::Lazy;
+use radix_fmt::radix;
+use serde::{de::DeserializeOwned, Serialize};
+use sled::Tree;
+
+use crate::{infra::{file_utils, hash_utils}, model::dto::{AlbumIdentity, FileIdentity, FileInfo, MediaInfo, TrackIdentity}};
+
+use super::file_info::FILE_INFO_DB;
+
+/// 音轨，键为音轨 ID
+static TRACK_TREE: Lazy<Tree> = Lazy::new(|| {
+    FILE_INFO_DB.open_tree("track").unwrap()
+});
+
+/// 文件包含的 ID，键为文件信息 Hash
+static FILE_IDENTITY_TREE: Lazy<Tree> = Lazy::new(|| {
+    FILE_INFO_DB.open_tree("file_identity").unwrap()
+});
+
+fn get_value<T: DeserializeOwned>(tree: &Tree, key: &str) -> Option<T> {
+    match tree.get(key) {
+        Ok(Some(value)) => Some(serde_json::from_slice(&value).unwrap()),
+        _ => None,
+    }
+}
+
+fn set_value<T: Serialize>(tree: &Tree, key: &str, value: &T) {
+    tree.insert(key, serde_json::to_vec(value).unwrap()).unwrap();
+}
+
+fn list_values<T: DeserializeOwned>(tree: &Tree) -> Vec<T> {
+    tree.iter()
+        .map(|item| serde_json::from_slice(&item.unwrap().1).unwrap())
+        .collect()
+}
+
+/// 统一大小写和空白，标签只有这些差异时视为相同
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ").to_lowercase()
+}
+
+fn identity_id(parts: &[&str]) -> String {
+    radix(hash_utils::hash_identity(parts), 36).to_string()
+}
+
+pub fn get_track(id: &str) -> Option<TrackIdentity> {
+    get_value(&TRACK_TREE, id)
+}
+
+pub fn get_file(file_info_hash: &str) -> Option<FileIdentity> {
+    get_value(&FILE_IDENTITY_TREE, file_info_hash)
+}
+
+pub fn list_tracks() -> Vec<TrackIdentity> {
+    list_values(&TRACK_TREE)
+}
+
+/// 为文件中的音轨、专辑和歌手分配 ID
+/// 文件信息 Hash 已经有 ID 时沿用原来的音轨 ID
+/// @param file_info 文件信息
+/// @return 文件包含的 ID
+pub fn assign(file_info: &FileInfo) -> FileIdentity {
+    let previous = get_file(&file_info.file_info_hash).map(|identity| identity.tracks);
+    save(file_info, previous)
+}
+
+/// 文件移动、重命名或修改标签后沿用原来的音轨 ID
+/// 专辑和歌手 ID 根据新的标签重新生成
+/// @param old_file_info_hash 移动前的文件信息 Hash
+/// @param file_info 移动后的文件信息
+/// @return 文件包含的 ID
+pub fn migrate(old_file_info_hash: &str, file_info: &FileInfo) -> FileIdentity {
+    let previous = FILE_IDENTITY_TREE.remove(old_file_info_hash).unwrap()
+        .map(|value| serde_json::from_slice::<FileIdentity>(&value).unwrap().tracks);
+    save(file_info, previous)
+}
+
+/// 删除文件的 ID
+/// 专辑和歌手 ID 由标签生成，不需要删除，再次出现时仍然是同一个 ID
+/// @param file_info_hash 文件信息 Hash
+pub fn remove(file_info_hash: &str) {
+    if let Some(value) = FILE_IDENTITY_TREE.remove(file_info_hash).unwrap() {
+        let identity: FileIdentity = serde_json::from_slice(&value).unwrap();
+        for track_id in identity.tracks {
+            // 重复的音轨可能已经被其他文件占用
+            if get_track(&track_id).is_some_and(|track| track.file_info_hash == file_info_hash) {
+                TRACK_TREE.remove(&track_id).unwrap();
+            }
+        }
+    }
+}
+
+pub fn clear() {
+    TRACK_TREE.clear().unwrap();
+    FILE_IDENTITY_TREE.clear().unwrap();
+}
+
+fn save(file_info: &FileInfo, previous_track_ids: Option<Vec<String>>) -> FileIdentity {
+    // 媒体数量变化说明不是同一个文件，不能沿用原来的音轨 ID
+    let previous_track_ids = previous_track_ids.filter(|track_ids| track_ids.len() == file_info.medias.len());
+    let mut identity = FileIdentity::default();
+    for (media_index, media) in file_info.medias.iter().enumerate() {
+        let album_id = album_of(file_info, media).map(|album| album.id);
+        let artist_id = tag_text(&media.artist).map(artist_id);
+        let track_id = match &previous_track_ids {
+            Some(track_ids) => track_ids[media_index].clone(),
+            None => new_track_id(file_info, media_index, media),
+        };
+        let track = TrackIdentity {
+            id: track_id.clone(),
+            file_info_hash: file_info.file_info_hash.clone(),
+            media_index,
+            audio_hash: media.audio_hash.clone(),
+            album_id: album_id.clone(),
+            artist_id: artist_id.clone(),
+        };
+        set_value(&TRACK_TREE, &track_id, &track);
+        identity.tracks.push(track_id);
+        if let Some(album_id) = album_id.filter(|album_id| !identity.albums.contains(album_id)) {
+            identity.albums.push(album_id);
+        }
+        if let Some(artist_id) = artist_id.filter(|artist_id| !identity.artists.contains(artist_id)) {
+            identity.artists.push(artist_id);
+        }
+    }
+    set_value(&FILE_IDENTITY_TREE, &file_info.file_info_hash, &identity);
+    identity
+}
+
+/// 由音频数据 Hash 和标签生成音轨 ID
+/// 同一音频出现在不同专辑时是不同的音轨，音频和标签都相同的重复文件依次加上序号区分
+fn new_track_id(file_info: &FileInfo, media_index: usize, media: &MediaInfo) -> String {
+    // 没有音频数据 Hash 时只能使用文件信息 Hash
+    let audio_identity = if media.audio_hash.is_empty() {
+        format!("{}#{}", file_info.file_info_hash, media_index)
+    } else {
+        media.audio_hash.clone()
+    };
+    let title = normalize(media.title.as_deref().unwrap_or_default());
+    let artist = normalize(media.artist.as_deref().unwrap_or_default());
+    let album = normalize(media.album.as_deref().unwrap_or_default());
+    let mut duplicate = 0usize;
+    loop {
+        let duplicate_text = duplicate.to_string();
+        let track_id = identity_id(&["track", &audio_identity, &title, &artist, &album, &duplicate_text]);
+        let occupied = get_track(&track_id).is_some_and(|track| {
+            let is_self = track.file_info_hash == file_info.file_info_hash && track.media_index == media_index;
+            // 同一文件中的其他音轨还没有保存文件 ID
+            let is_sibling = track.file_info_hash == file_info.file_info_hash;
+            !is_self && (is_sibling || FILE_IDENTITY_TREE.contains_key(&track.file_info_hash).unwrap())
+        });
+        if !occupied {
+            return track_id;
+        }
+        duplicate += 1;
+    }
+}
+
+/// 专辑的分组
+/// 有专辑歌手时按专辑歌手和专辑名分组
+/// 没有专辑歌手时合辑中每首歌的歌手可能不同，按所在目录和专辑名分组
+/// 没有专辑名时按所在目录分组，专辑名为目录名
+/// 光碟目录（CD1、Disc 2）使用上一级目录，多张光碟属于同一专辑
+/// @param file_info 文件信息
+/// @param media 媒体信息
+/// @return 不在任何目录中且没有专辑名时返回 None
+pub fn album_of(file_info: &FileInfo, media: &MediaInfo) -> Option<AlbumIdentity> {
+    let directories = album_directories(&file_info.path);
+    let directory = directories.join("/");
+    let title = tag_text(&media.album);
+    let album_artist = tag_text(&media.album_artist);
+    let (id, title) = match (album_artist, title) {
+        (Some(album_artist), Some(title)) => (identity_id(&["album", &normalize(album_artist), &normalize(title)]), title),
+        (None, Some(title)) => (identity_id(&["album-directory", &directory, &normalize(title)]), title),
+        (_, None) => (identity_id(&["directory", &directory]), directories.last()?.as_str()),
+    };
+    Some(AlbumIdentity {
+        id,
+        title: title.to_string(),
+        artist: album_artist.map(str::to_string),
+    })
+}
+
+/// 歌手 ID，由歌手名生成
+pub fn artist_id(name: &str) -> String {
+    identity_id(&["artist", &normalize(name)])
+}
+
+/// 流派 ID，由流派名生成
+pub fn genre_id(name: &str) -> String {
+    identity_id(&["genre", &normalize(name)])
+}
+
+/// 去掉空白，空白的标签视为没有
+fn tag_text(text: &Option<String>) -> Option<&str> {
+    text.as_deref().map(str::trim).filter(|text| !text.is_empty())
+}
+
+/// 专辑所在的目录，去掉文件名和光碟目录
+fn album_directories(path: &[String]) -> &[String] {
+    let directories = &path[..path.len().saturating_sub(1)];
+    match directories.split_last() {
+        Some((last, parent)) if file_utils::is_disc_dir_name(last) => parent,
+        _ => directories,
+    }
+}
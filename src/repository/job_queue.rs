@@ -0,0 +1,55 @@
+use once_cell::sync::Lazy;
+use sled::Db;
+
+use crate::{config::app_config::JOB_QUEUE_STORAGE_PATH, model::dto::QueuedJob};
+
+/// 等待执行和正在执行的任务，键为大端序的任务编号
+static JOB_QUEUE_DB: Lazy<Db> = Lazy::new(|| {
+    sled::open(&*JOB_QUEUE_STORAGE_PATH).unwrap()
+});
+
+/// 生成任务编号，重启后也不会重复
+pub fn generate_id() -> u64 {
+    JOB_QUEUE_DB.generate_id().unwrap() + 1
+}
+
+pub fn get(id: u64) -> Option<QueuedJob> {
+    match JOB_QUEUE_DB.get(id.to_be_bytes()) {
+        Ok(Some(value)) => Some(serde_json::from_slice(&value).unwrap()),
+        _ => None,
+    }
+}
+
+/// 获取所有任务，按编号排序
+pub fn list() -> Vec<QueuedJob> {
+    JOB_QUEUE_DB.iter()
+        .map(|item| {
+            let (_, value) = item.unwrap();
+            serde_json::from_slice(&value).unwrap()
+        })
+        .collect()
+}
+
+/// 加入队列，立即写入磁盘，加入后重启也不会丢失
+pub fn set(job: &QueuedJob) {
+    let value = serde_json::to_vec(job).unwrap();
+    JOB_QUEUE_DB.insert(job.id.to_be_bytes(), value).unwrap();
+    JOB_QUEUE_DB.flush().unwrap();
+}
+
+/// 记录任务开始执行，立即写入磁盘，执行时崩溃的任务重启后也能计入执行次数
+/// @param id 任务编号，不是持久化的任务时不做处理
+pub fn mark_started(id: u64) {
+    let previous = JOB_QUEUE_DB.fetch_and_update(id.to_be_bytes(), |value| {
+        let mut job: QueuedJob = serde_json::from_slice(value?).unwrap();
+        job.runs += 1;
+        Some(serde_json::to_vec(&job).unwrap())
+    }).unwrap();
+    if previous.is_some() {
+        JOB_QUEUE_DB.flush().unwrap();
+    }
+}
+
+pub fn remove(id: u64) {
+    JOB_QUEUE_DB.remove(id.to_be_bytes()).unwrap();
+}
\ No newline at end of file
@@ -0,0 +1,321 @@
+use std::{collections::{BTreeMap, HashMap, HashSet}, sync::{Arc, Mutex}};
+
+use once_cell::sync::Lazy;
+
+use crate::model::dto::{Album, AlbumDisc, AlbumSummary, AlbumTrack, Artist, ArtistSummary, FileInfo, Genre, GenreSummary};
+
+use super::{change_log, file_info, identity};
+
+/// 由文件信息汇总的专辑、歌手和流派
+#[derive(Default)]
+pub struct Library {
+    /// 专辑，按专辑歌手和专辑名排序
+    pub albums: Vec<Album>,
+    /// 歌手，按歌手名排序
+    pub artists: Vec<Artist>,
+    /// 流派，按流派名排序
+    pub genres: Vec<Genre>,
+}
+
+/// 汇总结果和汇总时的文件信息
+struct CachedLibrary {
+    /// 已经应用的变更记录序号
+    seq: u64,
+    /// 文件信息，键为文件信息 Hash
+    file_infos: Arc<HashMap<String, FileInfo>>,
+    library: Arc<Library>,
+}
+
+/// 文件信息没有变化时直接使用上次的汇总结果
+static LIBRARY_CACHE: Lazy<Mutex<Option<Arc<CachedLibrary>>>> = Lazy::new(|| Mutex::new(None));
+
+/// 获取当前的专辑、歌手和流派
+/// 只在第一次读取全部文件信息，之后根据变更记录更新缓存的文件信息再重新汇总
+/// 汇总时不持有锁，其他请求继续使用上次的结果，汇总完成后替换较旧的缓存
+pub fn get() -> Arc<Library> {
+    let seq = change_log::latest_seq();
+    let previous = LIBRARY_CACHE.lock().unwrap().clone();
+    let cached = match previous {
+        Some(cached) if cached.seq == seq => return cached.library.clone(),
+        Some(cached) => apply_changes(&cached),
+        None => {
+            // 先取序号再读取文件信息，期间的变更会在下次重复应用，结果相同
+            let file_infos = file_info::list();
+            let library = Arc::new(build(&file_infos));
+            CachedLibrary { seq, file_infos: Arc::new(file_infos), library }
+        }
+    };
+    let library = cached.library.clone();
+    let mut cache = LIBRARY_CACHE.lock().unwrap();
+    if cache.as_ref().is_none_or(|current| current.seq < cached.seq) {
+        *cache = Some(Arc::new(cached));
+    }
+    library
+}
+
+/// 应用缓存的序号之后的变更记录并重新汇总
+/// 变更记录已被压缩时使用返回的快照
+fn apply_changes(cached: &CachedLibrary) -> CachedLibrary {
+    let page = change_log::list_since(cached.seq, usize::MAX);
+    let mut file_infos = match page.snapshot {
+        Some(snapshot) => snapshot.into_iter()
+            .map(|file_info| (file_info.file_info_hash.clone(), file_info))
+            .collect(),
+        None => cached.file_infos.as_ref().clone(),
+    };
+    for entry in page.changes {
+        if let Some(previous_file_info_hash) = &entry.previous_file_info_hash {
+            file_infos.remove(previous_file_info_hash);
+        }
+        // 之后又被删除的文件没有文件信息
+        match entry.file_info {
+            Some(file_info) => file_infos.insert(entry.file_info_hash, file_info),
+            None => file_infos.remove(&entry.file_info_hash),
+        };
+    }
+    let library = Arc::new(build(&file_infos));
+    CachedLibrary { seq: page.latest_seq, file_infos: Arc::new(file_infos), library }
+}
+
+pub fn list_albums() -> Vec<AlbumSummary> {
+    get().albums.iter().map(|album| album.summary.clone()).collect()
+}
+
+pub fn get_album(id: &str) -> Option<Album> {
+    get().albums.iter().find(|album| album.summary.id == id).cloned()
+}
+
+pub fn list_artists() -> Vec<ArtistSummary> {
+    get().artists.iter().map(|artist| artist.summary.clone()).collect()
+}
+
+pub fn get_artist(id: &str) -> Option<Artist> {
+    get().artists.iter().find(|artist| artist.summary.id == id).cloned()
+}
+
+pub fn list_genres() -> Vec<GenreSummary> {
+    get().genres.iter().map(|genre| genre.summary.clone()).collect()
+}
+
+pub fn get_genre(id: &str) -> Option<Genre> {
+    get().genres.iter().find(|genre| genre.summary.id == id).cloned()
+}
+
+/// 汇总中的专辑
+struct AlbumBuilder {
+    title: String,
+    album_artist: Option<String>,
+    years: Vec<u32>,
+    genres: Vec<String>,
+    compilation: bool,
+    cover_hash: Option<String>,
+    artists: Vec<Option<String>>,
+    discs: BTreeMap<u32, Vec<AlbumTrack>>,
+}
+
+/// 汇总中的流派
+struct GenreBuilder {
+    name: String,
+    track_count: usize,
+    duration: u128,
+    album_ids: HashSet<String>,
+}
+
+/// 按专辑汇总文件信息
+/// cuesheet 引用的整轨文件不重复计入
+/// @param file_infos 文件信息
+/// @return 专辑、歌手和流派
+pub fn build(file_infos: &HashMap<String, FileInfo>) -> Library {
+    let cue_media_hashes: HashSet<&String> = file_infos.values()
+        .filter_map(|file_info| file_info.cue_media_file_info_hash.as_ref())
+        .collect();
+    let mut builders: HashMap<String, AlbumBuilder> = HashMap::new();
+    let mut genres: HashMap<String, GenreBuilder> = HashMap::new();
+    for (file_info_hash, file_info) in file_infos {
+        if cue_media_hashes.contains(file_info_hash) {
+            continue;
+        }
+        let track_ids = identity::get_file(file_info_hash).map(|identity| identity.tracks).unwrap_or_default();
+        for (media_index, media) in file_info.medias.iter().enumerate() {
+            let album = match identity::album_of(file_info, media) {
+                Some(album) => album,
+                None => continue,
+            };
+            // 同一音轨中重复的流派只计入一次
+            let genre_names: HashSet<&str> = media.genres.iter().map(|genre| genre.trim()).filter(|genre| !genre.is_empty()).collect();
+            for name in genre_names {
+                let genre = genres.entry(identity::genre_id(name)).or_insert_with(|| GenreBuilder {
+                    name: name.to_string(),
+                    track_count: 0,
+                    duration: 0,
+                    album_ids: HashSet::new(),
+                });
+                genre.track_count += 1;
+                genre.duration += media.duration;
+                genre.album_ids.insert(album.id.clone());
+            }
+            let builder = builders.entry(album.id).or_insert_with(|| AlbumBuilder {
+                title: album.title,
+                album_artist: album.artist,
+                years: Vec::new(),
+                genres: Vec::new(),
+                compilation: false,
+                cover_hash: None,
+                artists: Vec::new(),
+                discs: BTreeMap::new(),
+            });
+            builder.years.extend(media.year);
+            builder.genres.extend(media.genres.iter().cloned());
+            builder.compilation |= media.compilation;
+            if builder.cover_hash.is_none() {
+                builder.cover_hash = file_info.cover_hash.clone();
+            }
+            builder.artists.push(media.artist.clone());
+            builder.discs.entry(media.disc).or_default().push(AlbumTrack {
+                id: track_ids.get(media_index).cloned().unwrap_or_default(),
+                file_info_hash: file_info_hash.clone(),
+                media_index,
+                track: media.track,
+                title: media.title.clone(),
+                artist: media.artist.clone(),
+                artists: media.artists.clone(),
+                duration: media.duration,
+            });
+        }
+    }
+
+    let mut albums: Vec<Album> = builders.into_iter().map(|(id, builder)| build_album(id, builder)).collect();
+    albums.sort_by_cached_key(|album| (
+        album.summary.artist.as_deref().unwrap_or_default().to_lowercase(),
+        album.summary.year,
+        album.summary.title.to_lowercase(),
+        album.summary.id.clone(),
+    ));
+    let artists = build_artists(&albums);
+    let genres = build_genres(genres, &albums);
+    Library { albums, artists, genres }
+}
+
+fn build_album(id: String, builder: AlbumBuilder) -> Album {
+    // 没有专辑歌手标签时，所有音轨的歌手相同才作为专辑歌手，合辑没有专辑歌手
+    let compilation = builder.compilation;
+    let artist = builder.album_artist.or_else(|| {
+        if compilation {
+            return None;
+        }
+        let first = builder.artists.first()?.clone()?;
+        builder.artists.iter().all(|artist| artist.as_ref() == Some(&first)).then_some(first)
+    });
+    let discs: Vec<AlbumDisc> = builder.discs.into_iter()
+        .map(|(disc, mut tracks)| {
+            tracks.sort_by(|a, b| a.track.cmp(&b.track).then_with(|| a.title.cmp(&b.title)));
+            AlbumDisc {
+                disc,
+                duration: tracks.iter().map(|track| track.duration).sum(),
+                tracks,
+            }
+        })
+        .collect();
+    Album {
+        summary: AlbumSummary {
+            id,
+            title: builder.title,
+            artist_id: artist.as_deref().map(identity::artist_id),
+            artist,
+            // 各音轨年份不同时使用最早的年份
+            year: builder.years.into_iter().min(),
+            genres: count_genres(builder.genres),
+            compilation,
+            cover_hash: builder.cover_hash,
+            disc_count: discs.len(),
+            track_count: discs.iter().map(|disc| disc.tracks.len()).sum(),
+            duration: discs.iter().map(|disc| disc.duration).sum(),
+        },
+        discs,
+    }
+}
+
+/// 统计流派，出现次数相同时按名称排序
+fn count_genres(genres: Vec<String>) -> Vec<String> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for genre in genres {
+        *counts.entry(genre).or_default() += 1;
+    }
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+    counts.into_iter().map(|(genre, _)| genre).collect()
+}
+
+/// 按歌手汇总专辑，专辑歌手和音轨歌手都计入
+fn build_artists(albums: &[Album]) -> Vec<Artist> {
+    let mut artists: HashMap<String, Artist> = HashMap::new();
+    for album in albums {
+        let mut album_artist_ids: HashSet<String> = HashSet::new();
+        if let (Some(artist_id), Some(name)) = (&album.summary.artist_id, &album.summary.artist) {
+            artist_entry(&mut artists, artist_id, name);
+            album_artist_ids.insert(artist_id.clone());
+        }
+        for track in album.discs.iter().flat_map(|disc| disc.tracks.iter()) {
+            // 多个歌手的音轨计入每个歌手
+            let names: Vec<&str> = match track.artists.is_empty() {
+                true => track.artist.as_deref().into_iter().collect(),
+                false => track.artists.iter().map(String::as_str).collect(),
+            };
+            for name in names.into_iter().map(str::trim).filter(|name| !name.is_empty()) {
+                let artist_id = identity::artist_id(name);
+                let artist = artist_entry(&mut artists, &artist_id, name);
+                artist.summary.track_count += 1;
+                artist.summary.duration += track.duration;
+                album_artist_ids.insert(artist_id);
+            }
+        }
+        for artist_id in album_artist_ids {
+            let artist = artists.get_mut(&artist_id).unwrap();
+            artist.albums.push(album.summary.clone());
+            artist.summary.album_count += 1;
+        }
+    }
+    let mut artists: Vec<Artist> = artists.into_values().collect();
+    for artist in artists.iter_mut() {
+        artist.albums.sort_by_cached_key(|album| (album.year, album.title.to_lowercase()));
+    }
+    artists.sort_by_cached_key(|artist| (artist.summary.name.to_lowercase(), artist.summary.id.clone()));
+    artists
+}
+
+/// 按流派汇总专辑，专辑按原来的顺序排列
+fn build_genres(builders: HashMap<String, GenreBuilder>, albums: &[Album]) -> Vec<Genre> {
+    let mut genres: Vec<Genre> = builders.into_iter()
+        .map(|(id, builder)| {
+            let albums: Vec<AlbumSummary> = albums.iter()
+                .filter(|album| builder.album_ids.contains(&album.summary.id))
+                .map(|album| album.summary.clone())
+                .collect();
+            Genre {
+                summary: GenreSummary {
+                    id,
+                    name: builder.name,
+                    album_count: albums.len(),
+                    track_count: builder.track_count,
+                    duration: builder.duration,
+                },
+                albums,
+            }
+        })
+        .collect();
+    genres.sort_by_cached_key(|genre| (genre.summary.name.to_lowercase(), genre.summary.id.clone()));
+    genres
+}
+
+fn artist_entry<'a>(artists: &'a mut HashMap<String, Artist>, artist_id: &str, name: &str) -> &'a mut Artist {
+    artists.entry(artist_id.to_string()).or_insert_with(|| Artist {
+        summary: ArtistSummary {
+            id: artist_id.to_string(),
+            name: name.to_string(),
+            album_count: 0,
+            track_count: 0,
+            duration: 0,
+        },
+        albums: Vec::new(),
+    })
+}
\ No newline at end of file
@@ -1 +1,7 @@
-pub mod file_info;
\ No newline at end of file
+pub mod file_info;
+pub mod change_log;
+pub mod transcode_cache;
+pub mod job_queue;
+pub mod schedule;
+pub mod identity;
+pub mod library;
\ No newline at end of file
@@ -0,0 +1,27 @@
+use once_cell::sync::Lazy;
+use sled::Db;
+
+use crate::{config::app_config::SCHEDULE_STORAGE_PATH, model::dto::ScheduleState};
+
+/// 定时计划的执行状态，键为计划名称
+static SCHEDULE_DB: Lazy<Db> = Lazy::new(|| {
+    sled::open(&*SCHEDULE_STORAGE_PATH).unwrap()
+});
+
+pub fn get(name: &str) -> Option<ScheduleState> {
+    match SCHEDULE_DB.get(name) {
+        Ok(Some(value)) => Some(serde_json::from_slice(&value).unwrap()),
+        _ => None,
+    }
+}
+
+pub fn set(state: &ScheduleState) {
+    let value = serde_json::to_vec(state).unwrap();
+    SCHEDULE_DB.insert(state.name.as_str(), value).unwrap();
+    SCHEDULE_DB.flush().unwrap();
+}
+
+pub fn remove(name: &str) {
+    SCHEDULE_DB.remove(name).unwrap();
+    SCHEDULE_DB.flush().unwrap();
+}
\ No newline at end of file
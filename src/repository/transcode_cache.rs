@@ -0,0 +1,55 @@
+use once_cell::sync::Lazy;
+use sled::Db;
+
+use crate::{config::app_config::TRANSCODE_CACHE_STORAGE_PATH, model::dto::TranscodeCacheEntry};
+
+/// 转码缓存记录，键为 "音频数据 Hash/转码规格"
+static TRANSCODE_CACHE_DB: Lazy<Db> = Lazy::new(|| {
+    sled::open(&*TRANSCODE_CACHE_STORAGE_PATH).unwrap()
+});
+
+fn to_key(audio_hash: &str, variant: &str) -> String {
+    format!("{}/{}", audio_hash, variant)
+}
+
+pub fn get(audio_hash: &str, variant: &str) -> Option<TranscodeCacheEntry> {
+    match TRANSCODE_CACHE_DB.get(to_key(audio_hash, variant)) {
+        Ok(Some(value)) => Some(serde_json::from_slice(&value).unwrap()),
+        _ => None,
+    }
+}
+
+pub fn list() -> Vec<TranscodeCacheEntry> {
+    TRANSCODE_CACHE_DB.iter()
+        .map(|item| {
+            let (_, value) = item.unwrap();
+            serde_json::from_slice(&value).unwrap()
+        })
+        .collect()
+}
+
+pub fn set(entry: &TranscodeCacheEntry) {
+    let value = serde_json::to_vec(entry).unwrap();
+    TRANSCODE_CACHE_DB.insert(to_key(&entry.audio_hash, &entry.variant), value).unwrap();
+}
+
+/// 更新最后访问时间
+/// @param audio_hash 源音频数据 Hash
+/// @param variant 转码规格
+/// @param last_access 访问时间
+pub fn touch(audio_hash: &str, variant: &str, last_access: u128) {
+    let _ = TRANSCODE_CACHE_DB.fetch_and_update(to_key(audio_hash, variant), |value| {
+        let mut entry: TranscodeCacheEntry = serde_json::from_slice(value?).unwrap();
+        entry.last_access = last_access;
+        Some(serde_json::to_vec(&entry).unwrap())
+    });
+}
+
+pub fn remove(audio_hash: &str, variant: &str) {
+    TRANSCODE_CACHE_DB.remove(to_key(audio_hash, variant)).unwrap();
+}
+
+/// 缓存文件的总大小
+pub fn total_size() -> u64 {
+    list().iter().map(|entry| entry.size).sum()
+}
\ No newline at end of file
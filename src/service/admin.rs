@@ -0,0 +1,29 @@
+use actix_web::{error, get, post, web, HttpResponse, Result};
+
+use crate::{command::{actor, command::{self, TranscodeArgs}, scheduler}, model::dto::RecoveryPolicy};
+
+/// 扫描媒体库
+/// 扫描在后台执行，返回扫描任务信息，正在扫描时返回 409 和正在执行的任务信息
+#[post("/admin/scan")]
+pub async fn scan() -> Result<HttpResponse> {
+    match command::scan_library() {
+        Ok(job) => Ok(HttpResponse::Accepted().json(job.info())),
+        Err(job) => Ok(HttpResponse::Conflict().json(job.info())),
+    }
+}
+
+/// 批量转码到转码缓存
+/// 转码在后台执行，重启后继续转码没有缓存的文件，返回转码任务信息
+#[post("/admin/transcode")]
+pub async fn transcode(args: web::Json<TranscodeArgs>) -> Result<HttpResponse> {
+    let args = serde_json::to_value(args.into_inner())?;
+    let job = actor::enqueue("transcode", args, RecoveryPolicy::Resume)
+        .map_err(|err| error::ErrorBadRequest(err.to_string()))?;
+    Ok(HttpResponse::Accepted().json(job.info()))
+}
+
+/// 获取定时计划和上次、下次执行时间
+#[get("/admin/schedules")]
+pub async fn schedules() -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(scheduler::list()))
+}
\ No newline at end of file
@@ -0,0 +1,89 @@
+use std::{fs, io};
+
+use actix_web::{
+    error, get,
+    http::{header, StatusCode},
+    web, HttpRequest, HttpResponse, Result,
+};
+use serde::Deserialize;
+
+use crate::{command::{actor, job::JobClass}, config::app_config, infra::image_utils};
+
+/// 封面内容由 Hash 决定，可以永久缓存
+static CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// 支持的缩略图尺寸
+static THUMBNAIL_SIZES: [u32; 3] = [128, 256, 512];
+
+#[derive(Deserialize)]
+pub struct CoverQuery {
+    /// 尺寸 128/256/512/original，默认为 original
+    size: Option<String>,
+}
+
+/// 获取封面图片
+/// @param cover_hash 封面 Hash
+/// @param size 缩略图尺寸，原图为 None
+/// @return 图片数据，MIME 类型
+fn read_cover(cover_hash: &str, size: Option<u32>) -> io::Result<(Vec<u8>, &'static str)> {
+    let origin_path = app_config::ORIGIN_COVER_PATH.join(cover_hash);
+    let size = match size {
+        Some(size) => size,
+        None => {
+            let data = fs::read(&origin_path)?;
+            let mime_type = image_utils::sniff_mime_type(&data).unwrap_or("application/octet-stream");
+            return Ok((data, mime_type));
+        },
+    };
+    // 按需生成缩略图
+    let thumbnail_path = app_config::SMALL_COVER_PATH
+        .join(size.to_string())
+        .join(format!("{}.jpg", cover_hash));
+    if !thumbnail_path.is_file() {
+        if !origin_path.is_file() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "Cover not found"));
+        }
+        image_utils::convert_to_thumbnail(&origin_path, &thumbnail_path, size)
+            .map_err(|err| io::Error::other(err.to_string()))?;
+    }
+    Ok((fs::read(&thumbnail_path)?, "image/jpeg"))
+}
+
+/// 获取专辑封面
+/// 缩略图在第一次请求时生成
+#[get("/cover/{cover_hash}")]
+pub async fn cover(request: HttpRequest, cover_hash: web::Path<String>, query: web::Query<CoverQuery>) -> Result<HttpResponse> {
+    let cover_hash = cover_hash.into_inner();
+    // 封面 Hash 是 36 进制数字，同时避免路径穿越
+    if cover_hash.is_empty() || !cover_hash.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(error::ErrorBadRequest("Invalid cover hash"));
+    }
+    let size_name = query.size.as_deref().unwrap_or("original");
+    let size = match size_name {
+        "original" => None,
+        _ => Some(size_name.parse::<u32>().ok()
+            .filter(|size| THUMBNAIL_SIZES.contains(size))
+            .ok_or_else(|| error::ErrorBadRequest(format!("Unsupported size: {}", size_name)))?),
+    };
+
+    let etag = format!("\"{}-{}\"", cover_hash, size_name);
+    let not_modified = request.headers().get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.split(',').any(|tag| tag.trim() == etag))
+        .unwrap_or(false);
+    if not_modified {
+        return Ok(HttpResponse::build(StatusCode::NOT_MODIFIED)
+            .insert_header((header::ETAG, etag))
+            .insert_header((header::CACHE_CONTROL, CACHE_CONTROL))
+            .finish());
+    }
+
+    // 与后台任务一起调度，扫描和转码时缩略图仍然能及时生成
+    let (data, mime_type) = actor::run(JobClass::Interactive, move || read_cover(&cover_hash, size)).await
+        .map_err(error::ErrorInternalServerError)??;
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, mime_type))
+        .insert_header((header::ETAG, etag))
+        .insert_header((header::CACHE_CONTROL, CACHE_CONTROL))
+        .body(data))
+}
\ No newline at end of file
@@ -0,0 +1,31 @@
+use actix_web::{delete, error, get, web, HttpResponse, Responder, Result};
+
+use crate::command::job;
+
+/// 获取所有任务信息
+#[get("/jobs")]
+pub async fn list() -> Result<impl Responder> {
+    Ok(web::Json(job::list()))
+}
+
+/// 获取任务信息，包括执行状态、每个命令的进度、结果和错误
+#[get("/jobs/{id}")]
+pub async fn get(id: web::Path<u64>) -> Result<impl Responder> {
+    let id = id.into_inner();
+    let info = job::get(id).ok_or_else(|| error::ErrorNotFound(format!("Job {} not found", id)))?;
+    Ok(web::Json(info))
+}
+
+/// 取消任务
+/// 取消是协作式的，正在执行的命令检查到取消后停止并回滚，返回 202 和当前的任务信息
+/// 任务已经结束时返回 409
+#[delete("/jobs/{id}")]
+pub async fn cancel(id: web::Path<u64>) -> Result<HttpResponse> {
+    let id = id.into_inner();
+    let job = job::cancel(id).ok_or_else(|| error::ErrorNotFound(format!("Job {} not found", id)))?;
+    let info = job.info();
+    if info.status.is_finished() {
+        return Ok(HttpResponse::Conflict().json(info));
+    }
+    Ok(HttpResponse::Accepted().json(info))
+}
\ No newline at end of file
@@ -0,0 +1,68 @@
+use actix_web::{error, get, web, Responder, Result};
+
+use crate::repository::{identity, library};
+
+/// 根据音轨 ID 获取音轨，返回音轨当前所在的文件信息 Hash
+#[get("/tracks/{id}")]
+pub async fn track(id: web::Path<String>) -> Result<impl Responder> {
+    let id = id.into_inner();
+    let track = identity::get_track(&id).ok_or_else(|| error::ErrorNotFound(format!("Track {} not found", id)))?;
+    Ok(web::Json(track))
+}
+
+/// 获取所有专辑的概要
+#[get("/albums")]
+pub async fn albums() -> Result<impl Responder> {
+    let albums = web::block(library::list_albums).await?;
+    Ok(web::Json(albums))
+}
+
+/// 获取专辑，包括每张光碟的音轨
+#[get("/albums/{id}")]
+pub async fn album(id: web::Path<String>) -> Result<impl Responder> {
+    let id = id.into_inner();
+    let album = web::block({
+        let id = id.clone();
+        move || library::get_album(&id)
+    }).await?;
+    let album = album.ok_or_else(|| error::ErrorNotFound(format!("Album {} not found", id)))?;
+    Ok(web::Json(album))
+}
+
+/// 获取所有歌手的概要
+#[get("/artists")]
+pub async fn artists() -> Result<impl Responder> {
+    let artists = web::block(library::list_artists).await?;
+    Ok(web::Json(artists))
+}
+
+/// 获取歌手，包括参与的专辑
+#[get("/artists/{id}")]
+pub async fn artist(id: web::Path<String>) -> Result<impl Responder> {
+    let id = id.into_inner();
+    let artist = web::block({
+        let id = id.clone();
+        move || library::get_artist(&id)
+    }).await?;
+    let artist = artist.ok_or_else(|| error::ErrorNotFound(format!("Artist {} not found", id)))?;
+    Ok(web::Json(artist))
+}
+
+/// 获取所有流派的概要
+#[get("/genres")]
+pub async fn genres() -> Result<impl Responder> {
+    let genres = web::block(library::list_genres).await?;
+    Ok(web::Json(genres))
+}
+
+/// 获取流派，包括属于这个流派的专辑
+#[get("/genres/{id}")]
+pub async fn genre(id: web::Path<String>) -> Result<impl Responder> {
+    let id = id.into_inner();
+    let genre = web::block({
+        let id = id.clone();
+        move || library::get_genre(&id)
+    }).await?;
+    let genre = genre.ok_or_else(|| error::ErrorNotFound(format!("Genre {} not found", id)))?;
+    Ok(web::Json(genre))
+}
\ No newline at end of file
@@ -1,6 +1,17 @@
-use actix_web::{get, post, HttpResponse, Responder, web, Result};
+use std::collections::{HashMap, HashSet};
 
-use crate::{infra::{file_utils}};
+use actix_web::{get, post, Responder, web, Result};
+use serde::Deserialize;
+
+use crate::{infra::{file_utils}, repository::{file_info, change_log, identity}, config::app_config};
+
+#[derive(Deserialize)]
+pub struct ChangesQuery {
+    /// 客户端持有的序号
+    since: Option<u64>,
+    /// 最多返回的记录数
+    limit: Option<usize>,
+}
 
 #[get("/media/list")]
 pub async fn list() -> Result<impl Responder> {
@@ -9,10 +20,34 @@ pub async fn list() -> Result<impl Responder> {
     Ok(web::Json(audio_files))
 }
 
+/// 增量同步文件列表
+/// 客户端提交已有的文件信息 Hash，返回新增的文件信息、已删除和未变化的文件信息 Hash
 #[post("/media/list-diff")]
-pub async fn list_diff(file_info_hashs: web::Json<Vec<String>>) -> impl Responder {
-    for file_info_hash in file_info_hashs.into_inner() {
-        println!("{}", file_info_hash);
-    }
-    HttpResponse::Ok().body("not implemented")
+pub async fn list_diff(file_info_hashs: web::Json<Vec<String>>) -> Result<impl Responder> {
+    let client_file_info_hashs: HashSet<String> = file_info_hashs.into_inner().into_iter().collect();
+    let diff = web::block(move || file_info::diff(&client_file_info_hashs)).await?;
+    Ok(web::Json(diff))
+}
+
+/// 获取文件包含的音轨、专辑和歌手 ID
+/// 客户端提交文件信息 Hash，没有 ID 的文件不返回
+#[post("/media/ids")]
+pub async fn ids(file_info_hashs: web::Json<Vec<String>>) -> Result<impl Responder> {
+    let file_info_hashs = file_info_hashs.into_inner();
+    let ids = web::block(move || {
+        file_info_hashs.into_iter()
+            .filter_map(|file_info_hash| identity::get_file(&file_info_hash).map(|ids| (file_info_hash, ids)))
+            .collect::<HashMap<_, _>>()
+    }).await?;
+    Ok(web::Json(ids))
+}
+
+/// 获取指定序号之后的文件信息变更记录
+/// 客户端只需要保存上次返回的序号
+#[get("/media/changes")]
+pub async fn changes(query: web::Query<ChangesQuery>) -> Result<impl Responder> {
+    let since = query.since.unwrap_or(0);
+    let limit = query.limit.unwrap_or(app_config::CHANGE_LOG_PAGE_SIZE).clamp(1, app_config::CHANGE_LOG_PAGE_SIZE);
+    let page = web::block(move || change_log::list_since(since, limit)).await?;
+    Ok(web::Json(page))
 }
\ No newline at end of file
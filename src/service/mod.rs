@@ -1 +1,6 @@
-pub mod media;
\ No newline at end of file
+pub mod media;
+pub mod stream;
+pub mod cover;
+pub mod admin;
+pub mod job;
+pub mod library;
\ No newline at end of file
@@ -0,0 +1,410 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+use actix_web::{
+    body::SizedStream,
+    error,
+    http::{header, Method, StatusCode},
+    route,
+    rt,
+    web::{self, Bytes},
+    HttpRequest, HttpResponse, Result,
+};
+use futures_util::{stream::unfold, Stream};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use crate::{
+    command::{actor, job::JobClass},
+    infra::{
+        cancellation::CancellationToken,
+        file_utils,
+        http_range::{self, ByteRange},
+        transcode_cache::{self, EncodeTicket},
+        transcode_preset::{self, Preset},
+        transcoder,
+    },
+    model::dto::FileInfo,
+    repository::file_info,
+};
+
+/// 每次读取的字节数
+static CHUNK_SIZE: u64 = 64 * 1024;
+
+/// 转码输出缓冲的数据块数量，客户端读取较慢时转码会暂停等待
+static TRANSCODE_CHANNEL_SIZE: usize = 16;
+
+/// 转码的默认码率
+static DEFAULT_BIT_RATE: usize = 128000;
+
+/// 转码允许的码率范围
+static MIN_BIT_RATE: usize = 6000;
+static MAX_BIT_RATE: usize = 512000;
+
+/// multipart/byteranges 的分隔符
+static BOUNDARY: &str = "shadow-music-cloud-byteranges";
+
+/// 响应体片段
+enum BodyPart {
+    /// 固定内容（multipart 的分段头）
+    Bytes(Bytes),
+    /// 文件中的一段
+    File(ByteRange),
+}
+
+/// 按片段顺序读取的响应体
+struct FileBody {
+    file: File,
+    parts: VecDeque<BodyPart>,
+}
+
+impl FileBody {
+    /// 读取下一块数据
+    /// @return 数据和剩余的响应体，读取完毕时返回 None
+    fn read_next(mut self) -> io::Result<Option<(Bytes, FileBody)>> {
+        match self.parts.pop_front() {
+            None => Ok(None),
+            Some(BodyPart::Bytes(bytes)) => Ok(Some((bytes, self))),
+            Some(BodyPart::File(range)) => {
+                let size = range.length().min(CHUNK_SIZE);
+                let mut buffer = vec![0u8; size as usize];
+                self.file.seek(SeekFrom::Start(range.start))?;
+                self.file.read_exact(&mut buffer)?;
+                if size < range.length() {
+                    self.parts.push_front(BodyPart::File(ByteRange {
+                        start: range.start + size,
+                        end: range.end,
+                    }));
+                }
+                Ok(Some((Bytes::from(buffer), self)))
+            },
+        }
+    }
+
+    /// 转换为异步的数据流，文件读取在线程池中进行
+    fn into_stream(self) -> impl Stream<Item = io::Result<Bytes>> {
+        unfold(Some(self), |body| async move {
+            let body = body?;
+            match web::block(move || body.read_next()).await {
+                Ok(Ok(Some((bytes, body)))) => Some((Ok(bytes), Some(body))),
+                Ok(Ok(None)) => None,
+                Ok(Err(err)) => Some((Err(err), None)),
+                Err(err) => Some((Err(io::Error::other(err.to_string())), None)),
+            }
+        })
+    }
+}
+
+/// 将转码输出的数据发送给响应体，同时写入缓存文件
+/// 客户端断开连接后继续写入缓存文件，没有缓存文件时停止转码
+/// 写入缓存文件失败时放弃缓存，继续输出给客户端
+struct TeeWriter {
+    sender: Option<mpsc::Sender<io::Result<Bytes>>>,
+    file: Option<File>,
+    ticket: Option<EncodeTicket>,
+}
+
+impl Write for TeeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(file) = &mut self.file {
+            if let Err(err) = file.write_all(buf) {
+                if let Some(ticket) = &self.ticket {
+                    println!("Failed to write transcode cache {}: {}", ticket.temp_path().display(), err);
+                }
+                // 释放凭证时删除不完整的临时文件
+                self.file = None;
+                self.ticket = None;
+            }
+        }
+        if let Some(sender) = &self.sender {
+            // 接收端关闭说明客户端已断开连接
+            if sender.blocking_send(Ok(Bytes::copy_from_slice(buf))).is_err() {
+                self.sender = None;
+            }
+        }
+        if self.sender.is_none() && self.file.is_none() {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "Client disconnected"));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(file) = &mut self.file {
+            if file.flush().is_err() {
+                self.file = None;
+                self.ticket = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 转码方式
+enum TranscodePlan {
+    /// 使用已有的缓存文件
+    Cached(PathBuf),
+    /// 转码源文件，可以缓存时带有转码任务凭证
+    Transcode(PathBuf, Option<EncodeTicket>),
+}
+
+/// 播放参数
+#[derive(Deserialize)]
+pub struct StreamQuery {
+    /// 转码预设（例如: "opus-128"）
+    preset: Option<String>,
+    /// 转码格式（例如: "opus"），不指定时播放原始文件
+    format: Option<String>,
+    /// 转码码率（例如: "96k"）
+    bitrate: Option<String>,
+    /// 客户端允许的最大码率（例如: "160k"），根据码率和支持的格式选择预设
+    max_bitrate: Option<String>,
+    /// 客户端支持的格式，逗号分隔（例如: "opus,aac"）
+    codecs: Option<String>,
+}
+
+impl StreamQuery {
+    /// 根据请求参数选择转码预设
+    /// 优先使用指定的预设，其次是指定的格式和码率，最后根据客户端的码率上限和支持的格式选择
+    /// @return 转码预设，播放原始文件时返回 None
+    fn to_preset(&self) -> Result<Option<Preset>> {
+        if let Some(name) = self.preset.as_deref() {
+            let preset = transcode_preset::find(name)
+                .ok_or_else(|| error::ErrorBadRequest(format!("Unsupported preset: {}", name)))?;
+            return Ok(Some(preset.clone()));
+        }
+        if let Some(format_name) = self.format.as_deref() {
+            let stream_format = transcoder::find_stream_format(format_name)
+                .ok_or_else(|| error::ErrorBadRequest(format!("Unsupported format: {}", format_name)))?;
+            let bit_rate = match self.bitrate.as_deref() {
+                Some(text) => parse_bit_rate_param(text)?,
+                None => DEFAULT_BIT_RATE,
+            };
+            return Ok(Some(Preset::custom(stream_format, bit_rate)));
+        }
+        if self.max_bitrate.is_none() && self.codecs.is_none() {
+            return Ok(None);
+        }
+        let max_bit_rate = self.max_bitrate.as_deref().map(parse_bit_rate_param).transpose()?;
+        let formats: Vec<String> = self.codecs.as_deref()
+            .map(|codecs| codecs.split(',').map(str::trim).filter(|codec| !codec.is_empty()).map(str::to_string).collect())
+            .unwrap_or_default();
+        let preset = transcode_preset::select(max_bit_rate, &formats)
+            .ok_or_else(|| error::ErrorBadRequest("No preset matches the client"))?;
+        Ok(Some(preset.clone()))
+    }
+}
+
+/// 解析码率参数
+fn parse_bit_rate_param(text: &str) -> Result<usize> {
+    transcoder::parse_bit_rate(text)
+        .filter(|bit_rate| (MIN_BIT_RATE..=MAX_BIT_RATE).contains(bit_rate))
+        .ok_or_else(|| error::ErrorBadRequest(format!("Invalid bitrate: {}", text)))
+}
+
+/// 根据文件信息 Hash 获取媒体文件路径
+/// cuesheet 返回关联的整轨文件
+/// @param file_info_hash 文件信息 Hash
+/// @return 文件路径
+pub fn resolve_media_file(file_info_hash: &str) -> io::Result<PathBuf> {
+    resolve_file_info(file_info_hash).map(|(_, media_path)| media_path)
+}
+
+/// 根据文件信息 Hash 获取文件信息和媒体文件路径
+/// 文件移动或重命名后，移动前的 Hash 仍然可以使用
+fn resolve_file_info(file_info_hash: &str) -> io::Result<(FileInfo, PathBuf)> {
+    let file_info = file_info::find(file_info_hash)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "File info not found"))?;
+    let components: Vec<String> = match &file_info.cue_media_path {
+        Some(cue_media_path) => cue_media_path.split('/').map(|s| s.to_string()).collect(),
+        None => file_info.path.clone(),
+    };
+    let media_path = file_utils::resolve_media_path(&components)?;
+    Ok((file_info, media_path))
+}
+
+/// 获取转码方式
+/// 已经有相同的转码任务时单独转码，不等待其完成，也不写入缓存
+/// cuesheet 的音轨 Hash 不对应整轨文件，不缓存
+fn plan_transcode(file_info_hash: &str, variant: &str, extension: &'static str) -> io::Result<TranscodePlan> {
+    let (file_info, media_path) = resolve_file_info(file_info_hash)?;
+    let audio_hash = match (&file_info.cue_media_path, file_info.medias.as_slice()) {
+        (None, [media]) if !media.audio_hash.is_empty() => media.audio_hash.clone(),
+        _ => return Ok(TranscodePlan::Transcode(media_path, None)),
+    };
+    if let Some(cache_path) = transcode_cache::get(&audio_hash, variant) {
+        return Ok(TranscodePlan::Cached(cache_path));
+    }
+    Ok(TranscodePlan::Transcode(media_path, transcode_cache::begin(&audio_hash, variant, extension)))
+}
+
+/// 播放音频文件
+/// 指定转码预设、格式或客户端能力时边转码边输出，否则播放原始文件
+#[route("/media/{file_info_hash}/stream", method = "GET", method = "HEAD")]
+pub async fn stream(
+    request: HttpRequest,
+    file_info_hash: web::Path<String>,
+    query: web::Query<StreamQuery>
+) -> Result<HttpResponse> {
+    let file_info_hash = file_info_hash.into_inner();
+    match query.to_preset()? {
+        Some(preset) => stream_transcoded(request, file_info_hash, preset).await,
+        None => stream_file(request, file_info_hash).await,
+    }
+}
+
+/// 边转码边输出
+/// 已有缓存时播放缓存文件（支持 Range 请求）
+/// 否则转码输出不可 seek，响应使用分块传输，不支持 Range 请求，同时写入缓存
+async fn stream_transcoded(
+    request: HttpRequest,
+    file_info_hash: String,
+    preset: Preset
+) -> Result<HttpResponse> {
+    let stream_format = preset.stream_format;
+    let variant = preset.name.clone();
+    let etag = format!("\"{}-{}\"", file_info_hash, variant);
+    if request.method() == Method::HEAD {
+        web::block(move || resolve_media_file(&file_info_hash)).await??;
+        return Ok(HttpResponse::build(StatusCode::OK)
+            .insert_header((header::CONTENT_TYPE, stream_format.mime_type))
+            .finish());
+    }
+
+    let plan = web::block(move || plan_transcode(&file_info_hash, &variant, stream_format.extension)).await??;
+    let (media_path, ticket) = match plan {
+        TranscodePlan::Cached(cache_path) => {
+            return serve_file(request, cache_path, stream_format.mime_type, etag).await;
+        },
+        TranscodePlan::Transcode(media_path, ticket) => (media_path, ticket),
+    };
+
+    let (sender, receiver) = mpsc::channel::<io::Result<Bytes>>(TRANSCODE_CHANNEL_SIZE);
+    // 与后台任务一起调度，同时播放的数量受并发数限制
+    rt::spawn(actor::run(JobClass::Stream, move || {
+        // 等待调度时客户端已经断开连接
+        if sender.is_closed() {
+            return;
+        }
+        // 客户端断开连接时由输出的错误中止转码
+        let transcoder = preset.transcoder(&CancellationToken::new());
+        let (file, ticket) = match ticket {
+            Some(ticket) => match File::create(ticket.temp_path()) {
+                Ok(file) => (Some(file), Some(ticket)),
+                Err(err) => {
+                    println!("Failed to create transcode cache {}: {}", ticket.temp_path().display(), err);
+                    (None, None)
+                },
+            },
+            None => (None, None),
+        };
+        let mut writer = TeeWriter { sender: Some(sender.clone()), file, ticket };
+        match transcoder.transcode_to_writer(&media_path, stream_format.format_name, &mut writer) {
+            Ok(_) => {
+                // 缓存文件写入失败时凭证已经释放
+                let ticket = writer.ticket.take().filter(|_| writer.file.take().is_some());
+                if let Some(Err(err)) = ticket.map(EncodeTicket::commit) {
+                    println!("Failed to save transcode cache of {}: {}", media_path.display(), err);
+                }
+            },
+            Err(err) => {
+                println!("Failed to transcode {}: {}", media_path.display(), err);
+                // 让响应异常结束，避免客户端把不完整的数据当作完整文件
+                let _ = sender.blocking_send(Err(io::Error::other(err.to_string())));
+            },
+        }
+    }));
+
+    let body = unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|item| (item, receiver))
+    });
+    Ok(HttpResponse::build(StatusCode::OK)
+        .insert_header((header::ACCEPT_RANGES, "none"))
+        .insert_header((header::CONTENT_TYPE, stream_format.mime_type))
+        .streaming(body))
+}
+
+/// 播放原始音频文件
+/// 支持 Range 请求（包括多个范围），用于播放器拖动进度
+async fn stream_file(request: HttpRequest, file_info_hash: String) -> Result<HttpResponse> {
+    // 文件大小和修改时间变化时文件信息 Hash 也会变化
+    let etag = format!("\"{}\"", file_info_hash);
+    let media_path = web::block(move || resolve_media_file(&file_info_hash)).await??;
+    let content_type = file_utils::get_audio_mime_type(&media_path);
+    serve_file(request, media_path, content_type, etag).await
+}
+
+/// 输出文件，支持 Range 请求
+/// @param path 文件路径
+/// @param content_type 文件的 MIME 类型
+/// @param etag 文件的 ETag，用于判断 If-Range
+pub async fn serve_file(request: HttpRequest, path: PathBuf, content_type: &str, etag: String) -> Result<HttpResponse> {
+    let (file, total) = web::block(move || -> io::Result<(File, u64)> {
+        let file = File::open(&path)?;
+        let total = file.metadata()?.len();
+        Ok((file, total))
+    }).await??;
+
+    // If-Range 与当前文件不一致时返回完整文件
+    let if_range_matches = request.headers().get(header::IF_RANGE)
+        .map(|value| value.to_str().map(|value| value == etag).unwrap_or(false))
+        .unwrap_or(true);
+    let ranges = request.headers().get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .filter(|_| if_range_matches)
+        .and_then(|value| http_range::parse_range(value, total));
+
+    let mut response = HttpResponse::build(StatusCode::OK);
+    response
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header((header::ETAG, etag));
+    let mut parts: VecDeque<BodyPart> = VecDeque::new();
+    let length = match ranges {
+        None => {
+            response.insert_header((header::CONTENT_TYPE, content_type));
+            if total > 0 {
+                parts.push_back(BodyPart::File(ByteRange { start: 0, end: total - 1 }));
+            }
+            total
+        },
+        Some(ranges) if ranges.is_empty() => {
+            return Ok(HttpResponse::build(StatusCode::RANGE_NOT_SATISFIABLE)
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{}", total)))
+                .finish());
+        },
+        Some(ranges) if ranges.len() == 1 => {
+            let range = ranges[0];
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .insert_header((header::CONTENT_TYPE, content_type))
+                .insert_header((header::CONTENT_RANGE, range.content_range(total)));
+            parts.push_back(BodyPart::File(range));
+            range.length()
+        },
+        Some(ranges) => {
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .insert_header((header::CONTENT_TYPE, format!("multipart/byteranges; boundary={}", BOUNDARY)));
+            let mut length = 0;
+            for range in ranges {
+                let part_header = format!(
+                    "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                    BOUNDARY, content_type, range.content_range(total)
+                );
+                length += part_header.len() as u64 + range.length();
+                parts.push_back(BodyPart::Bytes(Bytes::from(part_header)));
+                parts.push_back(BodyPart::File(range));
+            }
+            let end = format!("\r\n--{}--\r\n", BOUNDARY);
+            length += end.len() as u64;
+            parts.push_back(BodyPart::Bytes(Bytes::from(end)));
+            length
+        },
+    };
+
+    let body = FileBody { file, parts };
+    Ok(response.body(SizedStream::new(length, body.into_stream())))
+}
@@ -0,0 +1,119 @@
+//! 扫描和检查整个媒体库会删除不在媒体目录中的文件信息，压缩变更记录会让其他测试的序号失效，
+//! 在单独的测试进程中使用独立的缓存目录，不影响其他测试
+
+use std::{sync::Once, thread, time::Duration};
+
+use actix_web::{http::StatusCode, test::{call_service, init_service, read_body_json, TestRequest}, App};
+
+use shadow_music_cloud::{
+    command::{actor::act, command, job::{self, Job, JobClass, JobStatus}, registry},
+    config::app_config,
+    model::dto::FileInfo,
+    model::dto::ChangeType,
+    repository::{change_log, file_info},
+    service::admin,
+};
+
+/// 测试使用临时的缓存目录，每个测试开始时调用
+fn setup() {
+    static SETUP: Once = Once::new();
+    SETUP.call_once(|| {
+        let cache_path = std::env::temp_dir().join(format!("shadow-music-cloud-scan-test-{}", std::process::id()));
+        std::env::set_var("SHADOW_MUSIC_CACHE_PATH", cache_path);
+    });
+}
+
+/// 等待任务结束
+fn wait_job(id: u64) -> job::JobInfo {
+    loop {
+        let info = job::get(id).unwrap();
+        if info.status.is_finished() {
+            return info;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_scheduled_actions() {
+    setup();
+    let action = command::create_scan_action();
+    assert!(action.validate().is_ok());
+    assert_eq!(action.class(), JobClass::Scan);
+
+    // 定时执行的维护任务不占用用户等待结果的任务的并发数
+    for (_, name, _) in app_config::SCHEDULES {
+        let action = registry::create_action(name, &serde_json::Value::Null).unwrap();
+        let class = if name == "scan" { JobClass::Scan } else { JobClass::Maintenance };
+        assert_eq!(action.class(), class, "{}", name);
+    }
+}
+
+/// 测试用的文件信息，文件不在媒体目录中
+fn gone_file_info(path: &str) -> FileInfo {
+    let gone = FileInfo {
+        path: path.split('/').map(str::to_string).collect(),
+        file_type: "audio".to_string(),
+        file_info_hash: format!("Test-{}", path),
+        ..Default::default()
+    };
+    file_info::set(&gone.file_info_hash, &gone);
+    gone
+}
+
+/// 扫描和检查完整性都处理整个媒体库，在同一个测试中依次执行
+#[actix_web::test]
+async fn test_admin_scan_and_check_integrity() {
+    setup();
+    let gone = gone_file_info("test-scan/gone.flac");
+    let app = init_service(App::new().service(admin::scan)).await;
+    let scan = || TestRequest::post().uri("/admin/scan").to_request();
+
+    // 正在扫描时返回正在执行的任务
+    let running = Job::create("scan", JobClass::Scan, Vec::new());
+    let response = call_service(&app, scan()).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let body: serde_json::Value = read_body_json(response).await;
+    assert_eq!(body["id"], running.id());
+    running.set_status(JobStatus::Cancelled);
+
+    // 媒体目录中已经不存在的文件信息在扫描后删除
+    let response = call_service(&app, scan()).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let body: serde_json::Value = read_body_json(response).await;
+    assert_eq!(body["name"], "scan");
+    let info = wait_job(body["id"].as_u64().unwrap());
+    assert_eq!(info.status, JobStatus::Succeeded);
+    assert_eq!(info.result.unwrap()["removed"], 1);
+    assert!(file_info::get(&gone.file_info_hash).is_none());
+
+    // 检查完整性时增量更新已经不存在的文件
+    let gone = gone_file_info("test-integrity/gone.flac");
+    let info = act(command::create_check_integrity_action()).wait();
+    assert_eq!(info.status, JobStatus::Succeeded);
+    let result = info.result.unwrap();
+    assert_eq!((&result["checked"], &result["changedFiles"]), (&1.into(), &1.into()));
+    let update = wait_job(result["updateJob"].as_u64().unwrap());
+    assert_eq!(update.status, JobStatus::Succeeded);
+    assert!(file_info::get(&gone.file_info_hash).is_none());
+}
+
+#[test]
+fn test_compact_change_log() {
+    setup();
+    let since = change_log::latest_seq();
+    for index in 0..10 {
+        change_log::append(ChangeType::Insert, &format!("CompactTest{}", index));
+    }
+    change_log::compact(3);
+    assert!(change_log::compacted_seq() > since);
+
+    // 序号早于压缩的记录时返回快照，否则返回保留的记录
+    assert!(change_log::list_since(since, usize::MAX).snapshot.is_some());
+    // 其他测试可能同时追加记录
+    let compacted_seq = change_log::compacted_seq();
+    let page = change_log::list_since(compacted_seq, usize::MAX);
+    assert!(page.snapshot.is_none());
+    assert!(page.changes.len() >= 3);
+    assert_eq!(page.changes[0].seq, compacted_seq + 1);
+}
\ No newline at end of file
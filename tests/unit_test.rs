@@ -1,62 +1,510 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
+    sync::{atomic::{AtomicU32, Ordering}, Once},
+    time::{Duration, Instant},
 };
 
+use actix_web::{
+    body::{BodySize, MessageBody},
+    dev::ServiceResponse,
+    http::StatusCode,
+    test::{call_service, init_service, read_body, read_body_json, TestRequest},
+    web, App, HttpRequest,
+};
 use anyhow::Result;
+use lofty::{
+    id3::v2::{EncodedTextFrame, Frame, FrameFlags, FrameValue, Id3v2Tag, TextEncoding},
+    ItemKey, ItemValue, Tag, TagItem, TagType,
+};
+use notify::{event::{AccessKind, CreateKind, Flag, ModifyKind, RemoveKind, RenameMode}, Event, EventKind};
 use radix_fmt::radix;
 use rayon::prelude::*;
 
-use shadow_music_cloud::repository::file_info;
+use shadow_music_cloud::repository::{self, change_log, file_info, identity, job_queue, library};
 use shadow_music_cloud::{
     action,
-    command::actor::act,
+    command::{actor::{self, act}, registry, scheduler, watcher},
     infra::transcoder,
-    model::dto::FileInfo,
+    model::dto::{ChangeType, FileInfo, FileInfoDiff, FileWarningKind, MediaInfo, QueuedJob, RecoveryPolicy, SimpleFileInfo},
+    service::{cover, media, stream},
 };
 use shadow_music_cloud::{
     command::{
-        action::{Action, ContextData},
-        command::Command,
+        action::Action,
+        command::{self, Command, RenamedPath, UpdateArgs, UpdateScope},
+        context::{Context, ContextError, ContextKey, JobResult},
+        job::{self, CommandStatus, JobClass, JobError, JobStatus, Progress, ResourcePool},
+        retry::{self, ErrorClass, RetryError, RetryPolicy},
     },
     config::app_config,
-    infra::{file_utils, hash_utils},
+    infra::{cancellation::{CancellationToken, Cancelled}, cover_utils, cron_utils::{CronExpr, Schedule}, time_utils, cue_utils, encoding_utils, file_utils, hash_utils, image_utils, http_range::{self, ByteRange}, tag_utils::{self, TagNumber}, transcode_cache, transcode_preset::{self, Preset}},
 };
 
+/// 测试使用临时的缓存目录，不影响正式的缓存，每个测试开始时调用
+fn setup() {
+    static SETUP: Once = Once::new();
+    SETUP.call_once(|| {
+        let cache_path = std::env::temp_dir().join(format!("shadow-music-cloud-test-{}", std::process::id()));
+        std::env::set_var("SHADOW_MUSIC_CACHE_PATH", cache_path);
+    });
+}
+
+/// 测试用的文件信息，文件信息 Hash 由路径生成
+fn test_file_info(path: &str, medias: Vec<MediaInfo>) -> FileInfo {
+    FileInfo {
+        path: path.split('/').map(|s| s.to_string()).collect(),
+        file_type: "audio".to_string(),
+        size: 1000,
+        last_modified: 2000,
+        file_info_hash: format!("Test-{}", path),
+        medias,
+        ..Default::default()
+    }
+}
+
+struct TestData(String);
+
 struct WriteValueCommand;
 impl Command for WriteValueCommand {
-    fn execute(&self, context: &mut HashMap<&str, ContextData>) -> Result<()> {
+    fn outputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<TestData>()]
+    }
+
+    fn execute(&self, context: &mut Context, _progress: &Progress, _cancel: &CancellationToken) -> Result<()> {
         println!("execute TestCommand");
         std::thread::sleep(std::time::Duration::from_millis(1000));
-        context.insert(
-            "data",
-            ContextData::String("string from another command".to_string()),
-        );
+        context.insert(TestData("string from another command".to_string()));
         Ok(())
     }
 }
 
 struct ReadValueCommand;
 impl Command for ReadValueCommand {
-    fn execute(&self, context: &mut HashMap<&str, ContextData>) -> Result<()> {
-        match context.get("data") {
-            Some(ContextData::String(s)) => println!("{}", s),
-            _ => println!("no value"),
-        }
+    fn inputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<TestData>()]
+    }
+
+    fn outputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<JobResult>()]
+    }
+
+    fn execute(&self, context: &mut Context, progress: &Progress, _cancel: &CancellationToken) -> Result<()> {
+        progress.set_total(1);
+        let TestData(s) = context.require::<TestData>()?;
+        println!("{}", s);
+        let result = serde_json::json!({ "data": s });
+        context.insert(JobResult(result));
+        progress.inc(1);
         Ok(())
     }
 }
 
+/// 生成固定的文件信息，代替读取媒体文件
+struct GenerateTestFileInfo(Vec<FileInfo>);
+impl Command for GenerateTestFileInfo {
+    fn outputs(&self) -> Vec<ContextKey> {
+        vec![ContextKey::of::<command::GeneratedFileInfo>()]
+    }
+
+    fn execute(&self, context: &mut Context, _progress: &Progress, _cancel: &CancellationToken) -> Result<()> {
+        context.insert(command::GeneratedFileInfo(self.0.clone()));
+        Ok(())
+    }
+}
+
+struct FailCommand;
+impl Command for FailCommand {
+    fn execute(&self, _context: &mut Context, _progress: &Progress, _cancel: &CancellationToken) -> Result<()> {
+        Err(anyhow::anyhow!("failed on purpose"))
+    }
+}
+
 #[test]
 fn test_action() {
-    let test_action = action![WriteValueCommand, ReadValueCommand];
-    act(test_action);
-    std::thread::sleep(std::time::Duration::from_millis(1000));
+    setup();
+    let mut test_action = action![WriteValueCommand, ReadValueCommand];
+    test_action.set_name("test");
+    let handle = act(test_action);
+    let info = handle.wait();
+    assert_eq!(info.name, "test");
+    assert_eq!(info.status, JobStatus::Succeeded);
+    assert_eq!(info.result, Some(serde_json::json!({ "data": "string from another command" })));
+    assert!(info.commands.iter().all(|command| command.status == CommandStatus::Succeeded));
+    assert_eq!(info.commands[1].name, "ReadValueCommand");
+    assert_eq!((info.commands[1].done, info.commands[1].total), (1, 1));
+    assert!(job::get(handle.id()).is_some());
+
+    // 失败时回滚已经执行的命令
+    let info = act(action![WriteValueCommand, FailCommand]).wait();
+    assert_eq!(info.status, JobStatus::RolledBack);
+    assert_eq!(info.commands[0].status, CommandStatus::RolledBack);
+    assert_eq!(info.commands[1].status, CommandStatus::Failed);
+    assert!(matches!(info.error, Some(JobError::CommandFailed { ref command, .. }) if command == "FailCommand"));
+}
+
+#[test]
+fn test_action_context() {
+    setup();
+    let mut context = Context::new();
+    assert!(context.get::<TestData>().is_none());
+    assert_eq!(context.require::<TestData>().err(), Some(ContextError::Missing("TestData")));
+    assert!(context.insert(TestData("a".to_string())).is_none());
+    assert_eq!(context.insert(TestData("b".to_string())).map(|data| data.0), Some("a".to_string()));
+    context.get_mut::<TestData>().unwrap().0.push('c');
+    assert_eq!(context.get::<TestData>().unwrap().0, "bc");
+    assert_eq!(context.take::<TestData>().unwrap().0, "bc");
+    assert!(context.remove::<TestData>().is_none());
+
+    // 输入数据必须由前面的命令产生
+    let mut invalid_action = Action::new();
+    invalid_action.add_command(Box::new(ReadValueCommand));
+    invalid_action.add_command(Box::new(WriteValueCommand));
+    assert_eq!(invalid_action.validate(), Err(ContextError::MissingInput {
+        command: "ReadValueCommand".to_string(),
+        input: "TestData",
+    }));
+    let info = act(Box::new(invalid_action)).wait();
+    assert_eq!(info.status, JobStatus::Failed);
+    assert!(matches!(info.error, Some(JobError::InvalidAction { .. })));
+    assert!(std::panic::catch_unwind(|| action![ReadValueCommand]).is_err());
+}
+
+struct WaitCancelCommand;
+impl Command for WaitCancelCommand {
+    fn execute(&self, _context: &mut Context, _progress: &Progress, cancel: &CancellationToken) -> Result<()> {
+        loop {
+            cancel.check()?;
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+}
+
+#[test]
+fn test_action_dag() {
+    setup();
+    // 两个分支都读取共享的数据，都结束后汇总
+    let mut dag = Action::new();
+    let write = dag.add_command_after(Box::new(WriteValueCommand), &[]);
+    let left = dag.add_command_after(Box::new(ReadValueCommand), &[write]);
+    let right = dag.add_command_after(Box::new(ReadValueCommand), &[write]);
+    dag.add_command_after(Box::new(ReadValueCommand), &[left, right]);
+    assert!(dag.validate().is_ok());
+    let info = act(Box::new(dag)).wait();
+    assert_eq!(info.status, JobStatus::Succeeded);
+    assert!(info.commands.iter().all(|command| command.status == CommandStatus::Succeeded));
+    assert_eq!(info.result, Some(serde_json::json!({ "data": "string from another command" })));
+
+    // 一个分支失败时回滚已经执行的分支，不再执行依赖失败分支的命令
+    let mut dag = Action::new();
+    let write = dag.add_command_after(Box::new(WriteValueCommand), &[]);
+    let fail = dag.add_command_after(Box::new(FailCommand), &[write]);
+    let read = dag.add_command_after(Box::new(ReadValueCommand), &[write]);
+    dag.add_command_after(Box::new(ReadValueCommand), &[fail, read]);
+    let info = act(Box::new(dag)).wait();
+    assert_eq!(info.status, JobStatus::RolledBack);
+    let statuses: Vec<CommandStatus> = info.commands.iter().map(|command| command.status).collect();
+    assert_eq!(statuses[..2], [CommandStatus::RolledBack, CommandStatus::Failed]);
+    assert!(matches!(statuses[2], CommandStatus::RolledBack | CommandStatus::Pending));
+    assert_eq!(statuses[3], CommandStatus::Pending);
+
+    // 只能依赖已经添加的命令，输入数据必须由依赖的命令产生
+    let mut invalid = Action::new();
+    invalid.add_command_after(Box::new(ReadValueCommand), &[1]);
+    assert!(matches!(invalid.validate(), Err(ContextError::InvalidDependency { dependency: 1, .. })));
+    let mut invalid = Action::new();
+    invalid.add_command_after(Box::new(WriteValueCommand), &[]);
+    invalid.add_command_after(Box::new(ReadValueCommand), &[]);
+    assert!(matches!(invalid.validate(), Err(ContextError::MissingInput { input: "TestData", .. })));
+    assert!(command::create_scan_action().validate().is_ok());
+}
+
+#[test]
+fn test_action_cancel() {
+    setup();
+    let handle = act(action![WriteValueCommand, WaitCancelCommand, ReadValueCommand]);
+    while handle.info().commands[1].status != CommandStatus::Running {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert!(job::cancel(handle.id()).is_some());
+    let info = handle.wait();
+    assert_eq!(info.status, JobStatus::Cancelled);
+    assert_eq!(info.commands[0].status, CommandStatus::RolledBack);
+    assert_eq!(info.commands[2].status, CommandStatus::Pending);
+    assert!(matches!(info.error, Some(JobError::Cancelled { ref command }) if command == "WaitCancelCommand"));
+    assert!(job::cancel(u64::MAX).is_none());
+}
+
+/// 前几次执行返回指定的错误
+struct FlakyCommand {
+    failures: AtomicU32,
+    error_kind: std::io::ErrorKind,
+}
+impl Command for FlakyCommand {
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff: Duration::from_millis(10),
+            ..RetryPolicy::on_io_error(3)
+        }
+    }
+
+    fn execute(&self, _context: &mut Context, _progress: &Progress, _cancel: &CancellationToken) -> Result<()> {
+        if self.failures.fetch_sub(1, Ordering::SeqCst) > 0 {
+            return Err(std::io::Error::new(self.error_kind, "flaky").into());
+        }
+        Ok(())
+    }
+}
+
+struct SlowCommand;
+impl Command for SlowCommand {
+    fn timeout(&self) -> Option<Duration> {
+        Some(Duration::from_millis(50))
+    }
+
+    fn execute(&self, _context: &mut Context, _progress: &Progress, cancel: &CancellationToken) -> Result<()> {
+        loop {
+            cancel.check()?;
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+#[test]
+fn test_command_retry() {
+    setup();
+    let policy = RetryPolicy::on_io_error(5);
+    assert_eq!(policy.backoff(1), Duration::from_millis(500));
+    assert_eq!(policy.backoff(2), Duration::from_secs(1));
+    assert_eq!(policy.backoff(20), Duration::from_secs(30));
+    let timed_out: anyhow::Error = std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out").into();
+    let not_found: anyhow::Error = std::io::Error::new(std::io::ErrorKind::NotFound, "not found").into();
+    assert!(ErrorClass::Io.matches(&timed_out.context("read file")));
+    assert!(!ErrorClass::Io.matches(&not_found));
+    assert!(!ErrorClass::Any.matches(&Cancelled.into()));
+    assert!(policy.should_retry(4, &std::io::Error::from_raw_os_error(5).into()));
+    assert!(!policy.should_retry(5, &std::io::Error::from_raw_os_error(5).into()));
+
+    // 暂时性的错误重试后成功
+    let flaky = FlakyCommand { failures: AtomicU32::new(2), error_kind: std::io::ErrorKind::TimedOut };
+    let info = act(action![flaky]).wait();
+    assert_eq!(info.status, JobStatus::Succeeded);
+    let attempts = &info.commands[0].attempts;
+    assert_eq!(attempts.len(), 3);
+    assert!(attempts[0].error.is_some() && attempts[1].error.is_some() && attempts[2].error.is_none());
+    assert!(attempts[1].started_at >= attempts[0].finished_at);
+
+    // 其他错误不重试
+    let broken = FlakyCommand { failures: AtomicU32::new(1), error_kind: std::io::ErrorKind::NotFound };
+    let info = act(action![broken]).wait();
+    assert_eq!(info.status, JobStatus::RolledBack);
+    assert_eq!(info.commands[0].attempts.len(), 1);
+
+    // 超时
+    let info = act(action![WriteValueCommand, SlowCommand]).wait();
+    assert_eq!(info.status, JobStatus::RolledBack);
+    assert!(matches!(info.error, Some(JobError::CommandTimedOut { timeout: 50, .. })));
+
+    // 不在任务中的操作也记录每次尝试，panic 不重试
+    let policy = RetryPolicy { initial_backoff: Duration::ZERO, ..RetryPolicy::on_io_error(3) };
+    let mut attempts = Vec::new();
+    let result: std::result::Result<(), _> = retry::run_with_retry("Flaky", &policy, &CancellationToken::new(), |attempt| attempts.push(attempt), |attempt| {
+        if attempt < 2 {
+            return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "flaky").into());
+        }
+        panic!("broken");
+    });
+    assert!(matches!(result, Err(RetryError::Panicked(ref message)) if message == "broken"));
+    assert_eq!(attempts.iter().map(|attempt| attempt.attempt).collect::<Vec<_>>(), vec![1, 2]);
+    assert_eq!(attempts[1].error.as_deref(), Some("broken"));
+}
+
+/// 等待持久化的任务从队列中删除
+fn wait_dequeued(id: u64) -> bool {
+    for _ in 0..100 {
+        if job_queue::get(id).is_none() {
+            return true;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    false
+}
+
+#[test]
+fn test_job_queue() {
+    setup();
+    registry::register("test-durable", |_| Ok(action![WriteValueCommand, ReadValueCommand]));
+
+    // 持久化的任务结束后从队列中删除
+    let handle = actor::enqueue("test-durable", serde_json::Value::Null, RecoveryPolicy::Resume).unwrap();
+    assert_eq!(handle.wait().status, JobStatus::Succeeded);
+    assert!(wait_dequeued(handle.id()));
+    assert!(actor::enqueue("test-unknown", serde_json::Value::Null, RecoveryPolicy::Resume).is_err());
+
+    // 模拟重启前没有结束的任务
+    let queued = |name: &str, recovery: RecoveryPolicy, runs: u32| {
+        let job = QueuedJob {
+            id: job_queue::generate_id(),
+            name: name.to_string(),
+            args: serde_json::Value::Null,
+            recovery,
+            runs,
+            created_at: 0,
+        };
+        job_queue::set(&job);
+        job
+    };
+    // 开始执行时记录执行次数
+    let started = queued("test-durable", RecoveryPolicy::Resume, 0);
+    job_queue::mark_started(started.id);
+    assert_eq!(job_queue::list().into_iter().find(|job| job.id == started.id).unwrap().runs, 1);
+    job_queue::remove(started.id);
+
+    let queued_jobs = vec![
+        queued("test-durable", RecoveryPolicy::Discard, 0),
+        queued("test-durable", RecoveryPolicy::Resume, 1),
+        queued("test-durable", RecoveryPolicy::Discard, 1),
+        queued("test-durable", RecoveryPolicy::Resume, app_config::JOB_MAX_RECOVERIES + 1),
+        queued("test-unknown", RecoveryPolicy::Resume, 0),
+    ];
+    let [not_started, resumed, discarded, too_many_runs, unknown] = [0, 1, 2, 3, 4].map(|index| queued_jobs[index].id);
+
+    // 只恢复这些任务，不影响同时执行的其他测试的任务
+    let recovered = actor::recover_jobs(queued_jobs);
+    let find = |id: u64| recovered.iter().find(|handle| handle.id() == id).unwrap().wait();
+    assert_eq!(find(not_started).status, JobStatus::Succeeded);
+    assert_eq!(find(resumed).status, JobStatus::Succeeded);
+    assert!(matches!(find(discarded).error, Some(JobError::Interrupted { runs: 1 })));
+    assert_eq!(find(too_many_runs).status, JobStatus::Failed);
+    assert!(matches!(find(unknown).error, Some(JobError::InvalidAction { .. })));
+    for id in [not_started, resumed, discarded, too_many_runs, unknown] {
+        assert!(wait_dequeued(id));
+    }
+}
+
+#[test]
+fn test_actor_schedule() {
+    setup();
+    assert_eq!(JobClass::default(), JobClass::Interactive);
+    assert_eq!(JobClass::Scan.pool(), ResourcePool::Io);
+    // 用户等待的短任务不与批量任务共用线程池
+    assert_ne!(JobClass::Interactive.pool(), JobClass::Transcode.pool());
+    assert_eq!(JobClass::Transcode.max_concurrency(), app_config::TRANSCODE_JOB_CONCURRENCY);
+    assert!(JobClass::Interactive.priority() > JobClass::Transcode.priority());
+    assert_eq!(serde_json::to_value(JobClass::Transcode).unwrap(), "transcode");
+    // 边转码边播放的请求不排在批量转码后面
+    assert_eq!(JobClass::Stream.pool(), ResourcePool::Cpu);
+    assert!(JobClass::Stream.priority() > JobClass::Transcode.priority());
+
+    // 同一类型的任务不超过并发数
+    static RUNNING: AtomicU32 = AtomicU32::new(0);
+    static MAX_RUNNING: AtomicU32 = AtomicU32::new(0);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    for _ in 0..6 {
+        let sender = sender.clone();
+        actor::GLOBAL_ACTOR.spawn(JobClass::Transcode, move || {
+            let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+            MAX_RUNNING.fetch_max(running, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            RUNNING.fetch_sub(1, Ordering::SeqCst);
+            sender.send(()).unwrap();
+        });
+    }
+
+    // 转码排队时交互任务仍然可以执行
+    let interactive_sender = sender.clone();
+    actor::GLOBAL_ACTOR.spawn(JobClass::Interactive, move || interactive_sender.send(()).unwrap());
+    // panic 不影响后续任务
+    actor::GLOBAL_ACTOR.spawn(JobClass::Transcode, || panic!("test panic"));
+    for _ in 0..7 {
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+    assert!(MAX_RUNNING.load(Ordering::SeqCst) as usize <= app_config::TRANSCODE_JOB_CONCURRENCY);
+
+    // 动作按类型调度
+    let mut action = action![WriteValueCommand, ReadValueCommand];
+    action.set_class(JobClass::Scan);
+    let handle = act(action);
+    assert_eq!(handle.info().class, JobClass::Scan);
+    assert_eq!(handle.wait().status, JobStatus::Succeeded);
+}
+
+#[test]
+fn test_cron() {
+    setup();
+    let hour = 60 * 60 * 1000;
+    let day = 24 * hour;
+    let cron = |expr: &str| CronExpr::parse(expr).unwrap();
+    assert_eq!(cron("0 3 * * *").next_after(0, 0), Some(3 * hour));
+    assert_eq!(cron("0 3 * * *").next_after(3 * hour, 0), Some(27 * hour));
+    // 时区为 UTC+8 时，本地 03:00 是 UTC 19:00
+    assert_eq!(cron("0 3 * * *").next_after(0, 8 * 60), Some(19 * hour));
+    assert_eq!(cron("*/15 * * * *").next_after(0, 0), Some(15 * 60 * 1000));
+    assert_eq!(cron("0 0 29 2 *").next_after(0, 0), Some((365 + 365 + 31 + 28) * day));
+    // 1970-01-01 是周四
+    assert_eq!(cron("0 0 * * 1").next_after(0, 0), Some(4 * day));
+    assert_eq!(cron("0 0 * * 7"), cron("0 0 * * 0"));
+    // 日和周都指定时满足其中一个即可
+    assert_eq!(cron("0 0 13 * 5").next_after(0, 0), Some(day));
+    // 以 * 开头的字段视为没有指定，需要同时满足（1970-01-13 是周二）
+    assert_eq!(cron("0 0 13 * */2").next_after(0, 0), Some(12 * day));
+    for expr in ["0 0 30 2 *", "60 * * * *", "* * *", "*/0 * * * *", "5-1 * * * *"] {
+        assert!(CronExpr::parse(expr).is_err(), "{}", expr);
+    }
+
+    assert_eq!(Schedule::parse("@every 90m").unwrap(), Schedule::Interval(Duration::from_secs(90 * 60)));
+    assert_eq!(Schedule::parse("@every 90m").unwrap().next_after(1000), Some(1000 + 90 * 60 * 1000));
+    assert_eq!(Schedule::parse("@weekly").unwrap(), Schedule::parse("0 0 * * 0").unwrap());
+    assert!(Schedule::parse("@every 0s").is_err());
+    assert!(Schedule::parse("@every 5x").is_err());
+}
+
+#[test]
+fn test_scheduler() {
+    setup();
+    registry::register("test-scheduled", |_| Ok(action![WaitCancelCommand]));
+    repository::schedule::remove("test-schedule");
+    let hour = 60 * 60 * 1000;
+    scheduler::add("test-schedule", "test-scheduled", serde_json::Value::Null, "@every 1h").unwrap();
+    // 添加之后再取当前时间，下次执行时间不会晚于 now + hour
+    let now = time_utils::time_to_millis(&std::time::SystemTime::now());
+    let info = || scheduler::list().into_iter().find(|info| info.name == "test-schedule").unwrap();
+    assert!(scheduler::run_pending(now).is_empty());
+
+    // 到达执行时间时执行，并持久化执行时间
+    let handles = scheduler::run_pending(now + hour);
+    assert_eq!(handles.len(), 1);
+    assert_eq!(info().last_job_id, Some(handles[0].id()));
+    assert!(info().running);
+    let state = repository::schedule::get("test-schedule").unwrap();
+    assert_eq!(state.last_run, Some(now + hour));
+    assert_eq!(state.next_run, Some(now + 2 * hour));
+
+    // 上次的任务没有结束时跳过
+    assert!(scheduler::run_pending(now + 2 * hour).is_empty());
+    assert_eq!(info().last_run, Some(now + hour));
+    assert_eq!(info().next_run, Some(now + 3 * hour));
+    job::cancel(handles[0].id());
+    assert_eq!(handles[0].wait().status, JobStatus::Cancelled);
+    assert!(!info().running);
+
+    // 表达式不变时重新添加沿用执行状态
+    scheduler::add("test-schedule", "test-scheduled", serde_json::Value::Null, "@every 1h").unwrap();
+    assert_eq!(info().next_run, Some(now + 3 * hour));
+    let handles = scheduler::run_pending(now + 3 * hour);
+    assert_eq!(handles.len(), 1);
+    job::cancel(handles[0].id());
+    handles[0].wait();
+
+    assert!(scheduler::remove("test-schedule"));
+    assert!(repository::schedule::get("test-schedule").is_none());
+    assert!(scheduler::add("test-invalid", "test-scheduled", serde_json::Value::Null, "61 * * * *").is_err());
 }
 
 #[test]
 fn test_file_hash() {
+    setup();
     let audio_file_info_list = file_utils::list_audio_file();
     for audio_file_info in audio_file_info_list {
         let hash = hash_utils::hash_media_file_info(&audio_file_info);
@@ -67,6 +515,7 @@ fn test_file_hash() {
 
 #[test]
 fn test_audio_hash() -> Result<()> {
+    setup();
     let audio_file_info_list = file_utils::list_audio_file();
     audio_file_info_list.par_iter().for_each(|audio_file_info| {
         let mut path = PathBuf::new();
@@ -83,23 +532,16 @@ fn test_audio_hash() -> Result<()> {
 
 #[test]
 fn test_audio_transcode() -> Result<()> {
+    setup();
     ffmpeg_next::util::log::set_level(ffmpeg_next::util::log::Level::Error);
     let audio_file_info_list = file_utils::list_audio_file();
     audio_file_info_list.par_iter().for_each(|audio_file_info| {
         let path = PathBuf::from(app_config::AUDIO_PATH).join(&audio_file_info.path);
         //println!("{}", audio_file_info.path.display());
-        let transcoder = transcoder::Transcoder {
-            // output_filter_spec: Some("aresample=resampler=soxr".to_string()),
-            output_filter_spec: None,
-            codec: Some("libopus".to_string()),
-            channels: Some(2),
-            sample_rate: Some(48000),
-            bit_rate: Some(96000),
-            max_bit_rate: Some(320000),
-        };
+        let transcoder = transcode_preset::find("opus-128").unwrap().transcoder(&CancellationToken::new());
 
         let mut output_path = PathBuf::new();
-        output_path.push(Path::new(app_config::OTHER_AUDIO_QUALITY_PATH));
+        output_path.push(&*app_config::OTHER_AUDIO_QUALITY_PATH);
         let mut output_file_path = audio_file_info.path.clone();
         output_file_path.set_extension("opus");
         output_path.push(output_file_path);
@@ -114,6 +556,7 @@ fn test_audio_transcode() -> Result<()> {
 
 #[test]
 fn test_storage() {
+    setup();
     let test_data = FileInfo {
         path: ["test", "test2"]
             .into_iter()
@@ -126,18 +569,820 @@ fn test_storage() {
         cue_media_path: None,
         cue_media_file_info_hash: None,
         cover_hash: Some("TestData".to_string()),
+        text_encoding: None,
         medias: vec![],
+        warnings: vec![],
     };
 
-    file_info::set(&"TestData".to_string(), &test_data);
+    file_info::set("TestData", &test_data);
     let data_from_storage = file_info::get(&"TestData".to_string()).unwrap();
     println!("{:?}", data_from_storage);
 }
 
+#[actix_web::test]
+async fn test_list_diff() {
+    setup();
+    let kept = test_file_info("test-list-diff/kept.flac", vec![]);
+    let added = test_file_info("test-list-diff/added.flac", vec![]);
+    file_info::set(&kept.file_info_hash, &kept);
+    file_info::set(&added.file_info_hash, &added);
+
+    // 客户端已有的文件未变化，客户端没有的文件是新增的，服务端没有的文件已删除
+    let app = init_service(App::new().service(media::list_diff)).await;
+    let request = TestRequest::post()
+        .uri("/media/list-diff")
+        .set_json([kept.file_info_hash.as_str(), "TestListDiff-Removed"])
+        .to_request();
+    let response = call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let diff: FileInfoDiff = read_body_json(response).await;
+    assert_eq!(diff.unchanged, vec![kept.file_info_hash.clone()]);
+    assert_eq!(diff.removed, vec!["TestListDiff-Removed".to_string()]);
+    assert!(diff.added.iter().any(|file_info| file_info.file_info_hash == added.file_info_hash));
+    assert!(diff.added.iter().all(|file_info| file_info.file_info_hash != kept.file_info_hash));
+}
+
 #[test]
 fn test_media_info() {
+    setup();
     let audio_file_info_list = file_utils::list_audio_file();
     audio_file_info_list.par_iter().for_each(|audio_file_info| {
         println!("{:?} \n", FileInfo::from_simple(audio_file_info));
     });
+}
+
+#[test]
+fn test_cue_sheet() {
+    setup();
+    let sheet = cuna::CueSheet::new(r#"REM DATE 2004
+REM DISCNUMBER 2
+PERFORMER "Artist"
+TITLE "Album"
+FILE "image.wav" WAVE
+  TRACK 01 AUDIO
+    TITLE "First"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "Second"
+    INDEX 00 03:58:50
+    INDEX 01 04:00:00
+"#).unwrap();
+    let tracks: Vec<_> = sheet.tracks().collect();
+    assert_eq!(tracks.len(), 2);
+    assert_eq!(cue_utils::get_track_index_time(tracks[0]), Some(0));
+    assert_eq!(cue_utils::get_track_index_time(tracks[1]), Some(240_000));
+    assert_eq!(cue_utils::get_comment(&sheet, "DISCNUMBER"), Some("2"));
+    assert_eq!(cue_utils::get_comment(&sheet, "GENRE"), None);
+}
+
+#[test]
+fn test_encoding_detect() {
+    setup();
+    let samples = [
+        (encoding_rs::GBK, "周杰伦 - 七里香"),
+        (encoding_rs::BIG5, "周杰倫 - 七里香"),
+        (encoding_rs::SHIFT_JIS, "さくらんぼ - 大塚愛"),
+        (encoding_rs::UTF_8, "千本桜"),
+    ];
+    for (encoding, text) in samples {
+        let (data, _, _) = encoding.encode(text);
+        let decoded = encoding_utils::decode(&data, None);
+        assert_eq!(decoded.text, text);
+        assert_eq!(decoded.encoding, encoding);
+    }
+
+    // ID3v2.3 标签中按 Latin-1 解码的 GBK 文本
+    let (data, _, _) = encoding_rs::GBK.encode("晴天");
+    let mojibake: String = data.iter().map(|&b| b as char).collect();
+    let repaired = encoding_utils::repair(&mojibake, None).unwrap();
+    assert_eq!(repaired.text, "晴天");
+    assert!(encoding_utils::repair("Für Elise", None).is_none());
+    assert!(encoding_utils::repair("Plain ASCII", None).is_none());
+}
+
+#[test]
+fn test_save_storage_rollback() {
+    setup();
+    let kept = test_file_info("test-save/kept.flac", vec![]);
+    file_info::set(&kept.file_info_hash, &kept);
+    let updated = FileInfo { size: 3000, ..kept.clone() };
+    let added = test_file_info("test-save/added.flac", vec![]);
+
+    // 保存后失败时恢复扫描前已有的文件信息，删除新增的文件信息
+    let generate = GenerateTestFileInfo(vec![updated, added.clone()]);
+    let info = act(action![generate, command::SaveStorage, FailCommand]).wait();
+    assert_eq!(info.status, JobStatus::RolledBack);
+    assert_eq!(file_info::get(&kept.file_info_hash).unwrap().size, kept.size);
+    assert!(file_info::get(&added.file_info_hash).is_none());
+    file_info::remove(&kept.file_info_hash);
+}
+
+#[test]
+fn test_incremental_update() {
+    setup();
+    // 已经删除的路径包含子目录，存在的文件只包含所在目录
+    let scope = UpdateScope::of(Path::new("test-update/Gone"));
+    assert_eq!(scope, UpdateScope { path: PathBuf::from("test-update/Gone"), recursive: true });
+    assert!(scope.contains(Path::new("test-update/Gone/CD1/01.flac")));
+    assert!(!scope.contains(Path::new("test-update/Gone2/01.flac")));
+    let scope = UpdateScope { path: PathBuf::from("test-update"), recursive: false };
+    assert!(scope.contains(Path::new("test-update/01.flac")));
+    assert!(!scope.contains(Path::new("test-update/Gone/01.flac")));
+
+    // 只删除更新范围内已经不存在的文件信息
+    let file_info = |path: &str| test_file_info(path, vec![]);
+    let gone = file_info("test-update/Gone/01.flac");
+    let kept = file_info("test-update/Kept/01.flac");
+    file_info::set(&gone.file_info_hash, &gone);
+    file_info::set(&kept.file_info_hash, &kept);
+    // 旧的任务参数没有刷新的目录
+    let args = serde_json::json!({ "paths": ["test-update/Gone"], "renames": [] });
+    let action = command::create_update_action(&args).unwrap();
+    assert_eq!(action.class(), JobClass::Scan);
+    let info = act(action).wait();
+    assert_eq!(info.status, JobStatus::Succeeded);
+    assert_eq!(info.result.unwrap()["removed"], 1);
+    assert!(file_info::get(&gone.file_info_hash).is_none());
+    assert!(file_info::get(&kept.file_info_hash).is_some());
+    file_info::remove(&kept.file_info_hash);
+}
+
+#[test]
+fn test_watch_debounce() {
+    setup();
+    let debounce = Duration::from_millis(app_config::WATCH_DEBOUNCE_MILLIS);
+    let max_delay = Duration::from_millis(app_config::WATCH_MAX_DELAY_MILLIS);
+    let audio_path = PathBuf::from(app_config::AUDIO_PATH).join("Album").join("01.flac");
+    let event = |kind: EventKind, paths: &[PathBuf]| paths.iter().fold(Event::new(kind), |event, path| event.add_path(path.clone()));
+    let mut debouncer = watcher::Debouncer::new();
+    let now = Instant::now();
+    assert_eq!(debouncer.due_in(now), None);
+
+    // 读取文件和不是音频的文件不需要更新
+    debouncer.add(&event(EventKind::Access(AccessKind::Read), std::slice::from_ref(&audio_path)), now);
+    debouncer.add(&event(EventKind::Create(CreateKind::File), &[PathBuf::from("Cargo.toml")]), now);
+    assert_eq!(debouncer.take(), None);
+
+    // 最后一次变化后等待，但持续变化时最多等待 max_delay
+    debouncer.add(&event(EventKind::Create(CreateKind::File), std::slice::from_ref(&audio_path)), now);
+    debouncer.add(&event(EventKind::Modify(ModifyKind::Any), std::slice::from_ref(&audio_path)), now + debounce / 2);
+    assert_eq!(debouncer.due_in(now + debounce / 2), Some(debounce));
+    debouncer.add(&event(EventKind::Modify(ModifyKind::Any), std::slice::from_ref(&audio_path)), now + max_delay);
+    assert_eq!(debouncer.due_in(now + max_delay), Some(Duration::ZERO));
+
+    let renamed = [PathBuf::from(app_config::AUDIO_PATH).join("A"), PathBuf::from(app_config::AUDIO_PATH).join("B")];
+    debouncer.add(&event(EventKind::Modify(ModifyKind::Name(RenameMode::Both)), &renamed), now);
+    assert_eq!(debouncer.take(), Some(watcher::Changes::Update(UpdateArgs {
+        paths: vec![PathBuf::from("Album").join("01.flac")],
+        renames: vec![RenamedPath { from: PathBuf::from("A"), to: PathBuf::from("B") }],
+        refresh: Vec::new(),
+    })));
+    assert_eq!(debouncer.take(), None);
+
+    // 封面图片和编码设置变化时刷新所在的专辑目录，删除的文件也根据文件名判断
+    let album_path = PathBuf::from(app_config::AUDIO_PATH).join("Album");
+    debouncer.add(&event(EventKind::Create(CreateKind::File), &[album_path.join("Scans").join("Front.JPG")]), now);
+    debouncer.add(&event(EventKind::Remove(RemoveKind::File), &[album_path.join("cover.png")]), now);
+    debouncer.add(&event(EventKind::Modify(ModifyKind::Any), &[album_path.join("CD1").join(app_config::ENCODING_OVERRIDE_FILE_NAME)]), now);
+    assert_eq!(debouncer.take(), Some(watcher::Changes::Update(UpdateArgs {
+        paths: Vec::new(),
+        renames: Vec::new(),
+        refresh: vec![PathBuf::from("Album"), PathBuf::from("Album").join("CD1")],
+    })));
+
+    // 事件丢失时重新扫描
+    debouncer.add(&event(EventKind::Other, &[]).set_flag(Flag::Rescan), now);
+    assert_eq!(debouncer.take(), Some(watcher::Changes::Rescan));
+}
+
+#[test]
+fn test_change_log() {
+    setup();
+    let test_data = test_file_info("change-log-test", vec![]);
+
+    let since = change_log::latest_seq();
+    file_info::set(&test_data.file_info_hash, &test_data);
+    // 内容相同时不记录变更
+    file_info::set(&test_data.file_info_hash, &test_data);
+    file_info::remove(&test_data.file_info_hash);
+
+    let page = change_log::list_since(since, usize::MAX);
+    assert!(page.snapshot.is_none());
+    let changes: Vec<ChangeType> = page.changes.iter()
+        .filter(|entry| entry.file_info_hash == test_data.file_info_hash)
+        .map(|entry| entry.change_type)
+        .collect();
+    assert_eq!(changes, vec![ChangeType::Insert, ChangeType::Remove]);
+    assert!(page.changes.windows(2).all(|pair| pair[0].seq < pair[1].seq));
+    assert!(page.latest_seq > since);
+
+    // 并发追加时序号连续，不会跳过
+    let since = change_log::latest_seq();
+    (0..32).into_par_iter().for_each(|index| {
+        change_log::append(ChangeType::Insert, &format!("ChangeLogTest{}", index));
+    });
+    let page = change_log::list_since(since, usize::MAX);
+    assert!(page.changes.windows(2).all(|pair| pair[0].seq + 1 == pair[1].seq));
+    assert!(page.changes.len() >= 32);
+}
+
+#[test]
+fn test_file_move() {
+    setup();
+    let file_info = |path: &str| test_file_info(path, vec![]);
+    let first = file_info("test-move/A/01.flac");
+    let second = file_info("test-move/B/01.flac");
+    let third = file_info("test-move/C/01.flac");
+    file_info::set(&first.file_info_hash, &first);
+
+    // 移动多次后仍然可以通过最初的 Hash 找到文件
+    let since = change_log::latest_seq();
+    file_info::rename(&first.file_info_hash, &second);
+    file_info::rename(&second.file_info_hash, &third);
+    assert!(file_info::get(&first.file_info_hash).is_none());
+    assert_eq!(file_info::resolve(&first.file_info_hash), Some(third.file_info_hash.clone()));
+    assert_eq!(file_info::find(&second.file_info_hash).unwrap().path, third.path);
+
+    // 客户端持有移动前的 Hash 时返回移动记录，而不是删除和新增
+    let diff = file_info::diff(&HashSet::from([first.file_info_hash.clone()]));
+    assert!(!diff.removed.contains(&first.file_info_hash));
+    assert!(!diff.added.iter().any(|added| added.file_info_hash == third.file_info_hash));
+    let moved = diff.moved.iter().find(|moved| moved.from == first.file_info_hash).unwrap();
+    assert_eq!(moved.file_info.file_info_hash, third.file_info_hash);
+
+    let page = change_log::list_since(since, usize::MAX);
+    let changes: Vec<(ChangeType, Option<String>)> = page.changes.iter()
+        .filter(|entry| entry.file_info_hash == third.file_info_hash)
+        .map(|entry| (entry.change_type, entry.previous_file_info_hash.clone()))
+        .collect();
+    assert_eq!(changes, vec![(ChangeType::Moved, Some(second.file_info_hash.clone()))]);
+
+    // 移动回原来的位置
+    file_info::rename(&third.file_info_hash, &first);
+    assert_eq!(file_info::resolve(&first.file_info_hash), Some(first.file_info_hash.clone()));
+    assert_eq!(file_info::resolve(&second.file_info_hash), Some(first.file_info_hash.clone()));
+    assert_eq!(file_info::resolve(&third.file_info_hash), Some(first.file_info_hash.clone()));
+
+    // 删除后不再能找到
+    file_info::remove(&first.file_info_hash);
+    assert_eq!(file_info::resolve(&second.file_info_hash), None);
+    assert_eq!(file_info::resolve(&third.file_info_hash), None);
+}
+
+#[test]
+fn test_track_identity() {
+    setup();
+    let file_info = |path: &str, album: &str| test_file_info(path, vec![MediaInfo {
+        track: 1,
+        disc: 1,
+        title: Some("Identity Title".to_string()),
+        artist: Some("Identity Artist".to_string()),
+        album: Some(album.to_string()),
+        album_artist: Some("Identity Artist".to_string()),
+        audio_hash: "TestIdentityAudio".to_string(),
+        ..Default::default()
+    }]);
+    let first = file_info("test-identity/A/01.flac", "Identity Album");
+    file_info::set(&first.file_info_hash, &first);
+    let ids = identity::get_file(&first.file_info_hash).unwrap();
+    assert_eq!(ids.tracks.len(), 1);
+    let track = identity::get_track(&ids.tracks[0]).unwrap();
+    assert_eq!(track.file_info_hash, first.file_info_hash);
+    // 专辑和歌手 ID 由标签生成，与汇总的专辑一致
+    assert_eq!(ids.artists[0], identity::artist_id("Identity Artist"));
+    assert_eq!(library::get_album(&ids.albums[0]).unwrap().summary.title, "Identity Album");
+
+    // 音频和标签都相同的重复文件是不同的音轨，专辑和歌手相同
+    let duplicate = file_info("test-identity/B/01.flac", "identity  album");
+    file_info::set(&duplicate.file_info_hash, &duplicate);
+    let duplicate_ids = identity::get_file(&duplicate.file_info_hash).unwrap();
+    assert_ne!(duplicate_ids.tracks, ids.tracks);
+    assert_eq!(duplicate_ids.albums, ids.albums);
+    assert_eq!(duplicate_ids.artists, ids.artists);
+
+    // 移动并修改标签后沿用音轨 ID，专辑 ID 根据新的标签生成
+    let moved = file_info("test-identity/C/01.flac", "Another Album");
+    file_info::rename(&first.file_info_hash, &moved);
+    assert!(identity::get_file(&first.file_info_hash).is_none());
+    let moved_ids = identity::get_file(&moved.file_info_hash).unwrap();
+    assert_eq!(moved_ids.tracks, ids.tracks);
+    assert_ne!(moved_ids.albums, ids.albums);
+    assert_eq!(identity::get_track(&ids.tracks[0]).unwrap().file_info_hash, moved.file_info_hash);
+
+    // 汇总结果根据变更记录更新
+    assert_eq!(library::get_album(&moved_ids.albums[0]).unwrap().summary.title, "Another Album");
+    assert_eq!(library::get_album(&ids.albums[0]).unwrap().summary.track_count, 1);
+
+    file_info::remove(&moved.file_info_hash);
+    file_info::remove(&duplicate.file_info_hash);
+    assert!(identity::get_track(&ids.tracks[0]).is_none());
+    assert!(library::get_album(&moved_ids.albums[0]).is_none());
+    assert!(identity::get_file(&duplicate.file_info_hash).is_none());
+}
+
+#[test]
+fn test_library() {
+    setup();
+    let media = |disc: u32, track: u32, artist: Option<&str>, album: Option<&str>, year: Option<u32>| MediaInfo {
+        track,
+        disc,
+        title: Some(format!("Track {}", track)),
+        artist: artist.map(str::to_string),
+        album: album.map(str::to_string),
+        year,
+        duration: 1000,
+        ..Default::default()
+    };
+    let mut cover = test_file_info("test-library/Album/CD1/01.flac", vec![media(1, 1, Some("Library Artist"), Some("Library Album"), Some(2001))]);
+    cover.cover_hash = Some("LibraryCover".to_string());
+    let mut cue = test_file_info("test-library/Cue/album.cue", vec![
+        media(1, 2, Some("Cue Artist"), Some("Cue Album"), None),
+        media(1, 1, Some("Cue Artist"), Some("Cue Album"), None),
+    ]);
+    cue.file_type = "cuesheet".to_string();
+    cue.cue_media_file_info_hash = Some("Test-test-library/Cue/album.flac".to_string());
+    let file_infos: HashMap<String, FileInfo> = [
+        cover,
+        test_file_info("test-library/Album/CD2/01.flac", vec![media(2, 1, Some("Library Artist"), Some("Library Album"), Some(1999))]),
+        test_file_info("test-library/Various/01.flac", vec![media(1, 1, Some("First Artist"), Some("Various Hits"), None)]),
+        test_file_info("test-library/Various/02.flac", vec![media(1, 2, Some("Second Artist"), Some("Various Hits"), None)]),
+        test_file_info("test-library/Loose/01.flac", vec![media(1, 1, None, None, None)]),
+        test_file_info("test-library/Cue/album.flac", vec![media(1, 1, Some("Cue Artist"), Some("Cue Album"), None)]),
+        cue,
+    ].into_iter().map(|file_info| (file_info.file_info_hash.clone(), file_info)).collect();
+    let result = library::build(&file_infos);
+    let album = |title: &str| result.albums.iter().find(|album| album.summary.title == title).unwrap();
+    assert_eq!(result.albums.len(), 4);
+
+    // 光碟目录属于同一专辑，按光碟拆分
+    let library_album = album("Library Album");
+    assert_eq!(library_album.summary.artist.as_deref(), Some("Library Artist"));
+    assert_eq!(library_album.summary.year, Some(1999));
+    assert_eq!(library_album.summary.cover_hash.as_deref(), Some("LibraryCover"));
+    assert_eq!((library_album.summary.disc_count, library_album.summary.track_count, library_album.summary.duration), (2, 2, 2000));
+
+    // 合辑没有统一的歌手，每个歌手都有这张专辑
+    let various = album("Various Hits");
+    assert_eq!(various.summary.artist, None);
+    let first_artist = result.artists.iter().find(|artist| artist.summary.name == "First Artist").unwrap();
+    assert_eq!(first_artist.albums[0].id, various.summary.id);
+    assert_eq!(first_artist.summary.track_count, 1);
+
+    // 没有标签时使用目录名，整轨文件不重复计入
+    assert_eq!(album("Loose").summary.track_count, 1);
+    let cue_tracks: Vec<u32> = album("Cue Album").discs[0].tracks.iter().map(|track| track.track).collect();
+    assert_eq!(cue_tracks, vec![1, 2]);
+
+    // 分碟目录的写法与查找封面时相同
+    for name in ["CD1", "cd 2", "Disc_3", "Disk-04", " Disc.5 "] {
+        assert!(file_utils::is_disc_dir_name(name), "{}", name);
+    }
+    for name in ["CD", "Discography", "CD1A"] {
+        assert!(!file_utils::is_disc_dir_name(name), "{}", name);
+    }
+    let separated = library::build(&[
+        test_file_info("test-library/Separated/CD_1/01.flac", vec![media(1, 1, None, None, None)]),
+        test_file_info("test-library/Separated/CD_2/01.flac", vec![media(2, 1, None, None, None)]),
+    ].into_iter().map(|file_info| (file_info.file_info_hash.clone(), file_info)).collect());
+    assert_eq!(separated.albums.len(), 1);
+    assert_eq!(separated.albums[0].summary.title, "Separated");
+}
+
+#[test]
+fn test_media_tags() {
+    setup();
+    // 旧的文件信息没有新增的标签
+    let media: MediaInfo = serde_json::from_str(r#"{"track":1,"disc":1,"audioHash":"","indexTime":0,"duration":0,"bitrate":0}"#).unwrap();
+    assert!(media.artists.is_empty() && media.genres.is_empty() && !media.compilation);
+    let value = serde_json::to_value(&media).unwrap();
+    assert!(value.get("compilation").is_none() && value.get("genres").is_none());
+
+    let media = MediaInfo {
+        track: 1,
+        disc: 1,
+        artist: Some("Tag Artist; Tag Guest".to_string()),
+        artists: vec!["Tag Artist".to_string(), "Tag Guest".to_string()],
+        album: Some("Tag Album".to_string()),
+        genres: vec!["Rock".to_string(), "Pop".to_string()],
+        year: Some(2001),
+        compilation: true,
+        musicbrainz_release_id: Some("f5093c06-23e3-404f-aeaa-40f72885ee3a".to_string()),
+        duration: 1000,
+        ..Default::default()
+    };
+    let value = serde_json::to_value(&media).unwrap();
+    assert_eq!(value["compilation"], true);
+    assert_eq!(value["musicbrainzReleaseId"], "f5093c06-23e3-404f-aeaa-40f72885ee3a");
+
+    // 合辑没有专辑歌手，多个歌手的音轨计入每个歌手
+    let file_info = test_file_info("test-tags/01.flac", vec![media.clone(), MediaInfo { track: 2, genres: vec!["Pop".to_string()], ..media }]);
+    let result = library::build(&HashMap::from([(file_info.file_info_hash.clone(), file_info)]));
+    let album = &result.albums[0].summary;
+    assert_eq!(album.artist, None);
+    assert!(album.compilation);
+    assert_eq!(album.genres, vec!["Pop".to_string(), "Rock".to_string()]);
+    let artists: Vec<(&str, usize)> = result.artists.iter()
+        .map(|artist| (artist.summary.name.as_str(), artist.summary.track_count))
+        .collect();
+    assert_eq!(artists, vec![("Tag Artist", 2), ("Tag Guest", 2)]);
+
+    // 流派按音轨计数，专辑计入所有音轨的流派
+    let genres: Vec<(&str, usize, usize)> = result.genres.iter()
+        .map(|genre| (genre.summary.name.as_str(), genre.summary.album_count, genre.summary.track_count))
+        .collect();
+    assert_eq!(genres, vec![("Pop", 1, 2), ("Rock", 1, 1)]);
+    assert_eq!(result.genres[0].summary.id, identity::genre_id("pop"));
+    assert_eq!(result.genres[1].albums[0].id, album.id);
+}
+
+#[test]
+fn test_tag_items() {
+    setup();
+    let simple = SimpleFileInfo::new(Path::new("test-tag-items/01.flac"), 0, 0);
+    let read = |tag: &Tag| {
+        let mut media = MediaInfo::default();
+        let mut warnings = Vec::new();
+        media.read_tag(tag, &simple, None, &mut None, &mut warnings);
+        assert!(warnings.is_empty());
+        media
+    };
+    let text = |key: ItemKey, value: &str| TagItem::new(key, ItemValue::Text(value.to_string()));
+
+    // 多个值可以是单独的标签项，也可以用 \0 分隔，分号不分隔
+    let mut tag = Tag::new(TagType::VorbisComments);
+    tag.push_item_unchecked(text(ItemKey::Genre, "Rock\0 Pop \0"));
+    tag.push_item_unchecked(text(ItemKey::Genre, "Rock"));
+    tag.push_item_unchecked(text(ItemKey::Genre, "Rhythm; Blues"));
+    tag.push_item_unchecked(text(ItemKey::TrackArtist, "AC/DC; Friends"));
+    // 不认识的标签键不区分大小写，开关和总数类的标签
+    tag.push_item_unchecked(text(ItemKey::Unknown("musicbrainz_trackid".to_string()), " 4e0d8649-1f89-44f3-91af-4c0dbee81f28 "));
+    tag.push_item_unchecked(text(ItemKey::FlagCompilation, "Yes"));
+    tag.push_item_unchecked(text(ItemKey::TrackTotal, "12"));
+    tag.push_item_unchecked(text(ItemKey::DiscTotal, "0"));
+    let media = read(&tag);
+    assert_eq!(media.genres, vec!["Rock", "Pop", "Rhythm; Blues"]);
+    assert_eq!(media.artists, vec!["AC/DC; Friends"]);
+    assert_eq!(media.musicbrainz_recording_id.as_deref(), Some("4e0d8649-1f89-44f3-91af-4c0dbee81f28"));
+    assert!(media.compilation);
+    assert_eq!((media.total_tracks, media.total_discs), (Some(12), None));
+
+    let mut tag = Tag::new(TagType::VorbisComments);
+    tag.push_item_unchecked(text(ItemKey::FlagCompilation, "0"));
+    tag.push_item_unchecked(text(ItemKey::TrackTotal, "twelve"));
+    tag.push_item_unchecked(text(ItemKey::Unknown("MUSICBRAINZ_ALBUMID".to_string()), " "));
+    let media = read(&tag);
+    assert!(!media.compilation);
+    assert_eq!((media.total_tracks, media.musicbrainz_release_id), (None, None));
+
+    // MP3 的 MusicBrainz ID 按 TXXX 帧的描述和 UFID 帧的所有者读取
+    let frame = |id: &str, value: FrameValue| Frame::new(id, value, FrameFlags::default()).unwrap();
+    let user_text = |description: &str, content: &str| FrameValue::UserText(EncodedTextFrame {
+        encoding: TextEncoding::UTF8,
+        description: description.to_string(),
+        content: content.to_string(),
+    });
+    let mut id3v2 = Id3v2Tag::default();
+    id3v2.insert(frame("TXXX", user_text("MusicBrainz Album Id", "f5093c06-23e3-404f-aeaa-40f72885ee3a")));
+    id3v2.insert(frame("TXXX", user_text("MusicBrainz Artist Id", "66c662b6-6e2f-4930-8610-912e24c63ed1")));
+    id3v2.insert(frame("UFID", FrameValue::Binary([b"http://musicbrainz.org\0".as_slice(), b"4e0d8649-1f89-44f3-91af-4c0dbee81f28"].concat())));
+    let mut media = MediaInfo::default();
+    media.read_id3v2_tag(&id3v2);
+    assert_eq!(media.musicbrainz_release_id.as_deref(), Some("f5093c06-23e3-404f-aeaa-40f72885ee3a"));
+    assert_eq!(media.musicbrainz_recording_id.as_deref(), Some("4e0d8649-1f89-44f3-91af-4c0dbee81f28"));
+
+    // cuesheet 注释中的整张专辑信息
+    let sheet = cuna::CueSheet::new(r#"REM GENRE "Jazz; Soul"
+REM COMMENT "Remastered"
+REM TOTALDISCS 2
+REM DATE 1959-08-17
+CATALOG 0886972137525
+TITLE "Album"
+FILE "image.wav" WAVE
+  TRACK 01 AUDIO
+    INDEX 01 00:00:00
+"#).unwrap();
+    let mut media = MediaInfo { total_discs: Some(1), ..Default::default() };
+    media.read_cue_comments(&sheet);
+    assert_eq!(media.genres, vec!["Jazz; Soul"]);
+    assert_eq!(media.comment.as_deref(), Some("Remastered"));
+    assert_eq!((media.total_discs, media.year), (Some(2), Some(1959)));
+    assert_eq!(media.catalog_number.as_deref(), Some("0886972137525"));
+}
+
+#[test]
+fn test_tag_number() {
+    setup();
+    let number = |number, total, side| Some(TagNumber { number, total, side });
+    assert_eq!(tag_utils::parse_number("3"), number(3, None, None));
+    assert_eq!(tag_utils::parse_number(" 03 / 12 "), number(3, Some(12), None));
+    assert_eq!(tag_utils::parse_number("3/"), number(3, None, None));
+    assert_eq!(tag_utils::parse_number("B2"), number(2, None, Some(2)));
+    assert_eq!(tag_utils::parse_number("a1/6"), number(1, Some(6), Some(1)));
+    assert_eq!(tag_utils::parse_number("IV"), number(4, None, None));
+    assert_eq!(tag_utils::parse_number("xiv"), number(14, None, None));
+    assert_eq!(tag_utils::parse_number("XCIX"), number(99, None, None));
+    // 不规范的罗马数字和单独的黑胶唱片面无法解析
+    for text in ["IIII", "VX", "IC", "C", "L", "l"] {
+        assert_eq!(tag_utils::parse_number(text), None, "{}", text);
+    }
+    assert_eq!(tag_utils::parse_number("C1"), number(1, None, Some(3)));
+    assert_eq!(tag_utils::parse_number(""), None);
+    assert_eq!(tag_utils::parse_number("Side A"), None);
+    assert_eq!(tag_utils::parse_number("/12"), None);
+}
+
+#[test]
+fn test_file_warnings() {
+    setup();
+    // 无法读取的文件记录问题，不会中止扫描
+    let simple = SimpleFileInfo::new(Path::new("test-warnings/missing.flac"), 0, 0);
+    let file_info = FileInfo::from_simple(&simple).unwrap();
+    let kinds: Vec<FileWarningKind> = file_info.warnings.iter().map(|warning| warning.kind).collect();
+    assert_eq!(kinds, vec![FileWarningKind::Properties, FileWarningKind::Tags, FileWarningKind::AudioHash]);
+
+    // 旧的文件信息没有问题列表
+    let mut value = serde_json::to_value(&file_info).unwrap();
+    assert_eq!(value["warnings"][0]["kind"], "properties");
+    value.as_object_mut().unwrap().remove("warnings");
+    let file_info: FileInfo = serde_json::from_value(value).unwrap();
+    assert!(file_info.warnings.is_empty());
+
+    // 无法解析的序号和二进制的序号记录问题，使用默认值
+    let mut tag = Tag::new(TagType::VorbisComments);
+    tag.insert_item_unchecked(TagItem::new(ItemKey::TrackNumber, ItemValue::Text("first".to_string())));
+    tag.insert_item_unchecked(TagItem::new(ItemKey::DiscNumber, ItemValue::Binary(vec![1, 2])));
+    let mut media = MediaInfo::default();
+    let mut warnings = Vec::new();
+    media.read_tag(&tag, &simple, None, &mut None, &mut warnings);
+    assert_eq!((media.track, media.disc), (1, 1));
+    let kinds: Vec<FileWarningKind> = warnings.iter().map(|warning| warning.kind).collect();
+    assert_eq!(kinds, vec![FileWarningKind::TagValue, FileWarningKind::TagValue]);
+    assert!(warnings[0].message.contains("first"));
+    assert!(warnings[1].message.contains("binary"));
+}
+
+#[test]
+fn test_http_range() {
+    setup();
+    let range = |start, end| ByteRange { start, end };
+    assert_eq!(http_range::parse_range("bytes=0-499", 1000), Some(vec![range(0, 499)]));
+    assert_eq!(http_range::parse_range("bytes=-200", 1000), Some(vec![range(800, 999)]));
+    assert_eq!(http_range::parse_range("bytes=900-", 1000), Some(vec![range(900, 999)]));
+    assert_eq!(http_range::parse_range("bytes=900-2000", 1000), Some(vec![range(900, 999)]));
+    // 合并重叠和相邻的范围
+    assert_eq!(
+        http_range::parse_range("bytes=500-599, 0-99, 50-199, 200-299", 1000),
+        Some(vec![range(0, 299), range(500, 599)])
+    );
+    // 不可满足的范围
+    assert_eq!(http_range::parse_range("bytes=1000-1100", 1000), Some(vec![]));
+    // 格式错误时按完整请求处理
+    assert_eq!(http_range::parse_range("bytes=500-100", 1000), None);
+    assert_eq!(http_range::parse_range("items=0-1", 1000), None);
+    assert_eq!(http_range::parse_range("bytes=abc", 1000), None);
+}
+
+#[actix_web::test]
+async fn test_serve_file() {
+    setup();
+    let path = app_config::CACHE_PATH.join("stream-test.flac");
+    let data: Vec<u8> = (0..1000).map(|index| (index % 251) as u8).collect();
+    fs::create_dir_all(app_config::CACHE_PATH.as_path()).unwrap();
+    fs::write(&path, &data).unwrap();
+    let app = init_service(App::new().route("/stream-test", web::get().to(move |request: HttpRequest| {
+        stream::serve_file(request, path.clone(), "audio/flac", "\"stream-test\"".to_string())
+    }))).await;
+    let request = |headers: &[(&'static str, &str)]| {
+        headers.iter()
+            .fold(TestRequest::get().uri("/stream-test"), |request, (name, value)| request.insert_header((*name, *value)))
+            .to_request()
+    };
+    let header = |response: &ServiceResponse, name: &str| {
+        response.headers().get(name).map(|value| value.to_str().unwrap().to_string())
+    };
+
+    // 完整文件
+    let response = call_service(&app, request(&[])).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "accept-ranges").as_deref(), Some("bytes"));
+    assert_eq!(read_body(response).await, data);
+
+    // 单个范围
+    let response = call_service(&app, request(&[("range", "bytes=100-199")])).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(header(&response, "content-range").as_deref(), Some("bytes 100-199/1000"));
+    assert_eq!(header(&response, "content-type").as_deref(), Some("audio/flac"));
+    assert_eq!(read_body(response).await, data[100..200]);
+
+    // 多个范围，声明的长度与响应体一致
+    let response = call_service(&app, request(&[("range", "bytes=0-9, 990-")])).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    let content_type = header(&response, "content-type").unwrap();
+    let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap().to_string();
+    let size = response.response().body().size();
+    let body = read_body(response).await;
+    let mut expected = Vec::new();
+    for (start, end) in [(0, 9), (990, 999)] {
+        expected.extend(format!("\r\n--{}\r\nContent-Type: audio/flac\r\nContent-Range: bytes {}-{}/1000\r\n\r\n", boundary, start, end).into_bytes());
+        expected.extend(&data[start..=end]);
+    }
+    expected.extend(format!("\r\n--{}--\r\n", boundary).into_bytes());
+    assert_eq!(body, expected);
+    assert_eq!(size, BodySize::Sized(expected.len() as u64));
+
+    // If-Range 与 ETag 一致时返回范围，否则返回完整文件
+    let response = call_service(&app, request(&[("range", "bytes=0-9"), ("if-range", "\"stream-test\"")])).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    let response = call_service(&app, request(&[("range", "bytes=0-9"), ("if-range", "\"changed\"")])).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(read_body(response).await.len(), data.len());
+
+    // 不可满足的范围
+    let response = call_service(&app, request(&[("range", "bytes=1000-1100")])).await;
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(header(&response, "content-range").as_deref(), Some("bytes */1000"));
+}
+
+#[test]
+fn test_resolve_media_path() {
+    setup();
+    let path = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+    let is_denied = |list: &[&str]| {
+        file_utils::resolve_media_path(&path(list)).unwrap_err().kind() == std::io::ErrorKind::PermissionDenied
+    };
+    assert!(is_denied(&["..", "etc", "passwd"]));
+    assert!(is_denied(&["album", "..", "..", "secret.flac"]));
+    assert!(is_denied(&["/etc/passwd"]));
+    assert!(is_denied(&["album/../../secret.flac"]));
+    assert!(is_denied(&[]));
+}
+
+#[test]
+fn test_stream_format() {
+    setup();
+    assert_eq!(transcoder::parse_bit_rate("96k"), Some(96000));
+    assert_eq!(transcoder::parse_bit_rate("320K"), Some(320000));
+    assert_eq!(transcoder::parse_bit_rate("128000"), Some(128000));
+    assert_eq!(transcoder::parse_bit_rate("0k"), None);
+    assert_eq!(transcoder::parse_bit_rate("fast"), None);
+
+    let opus = transcoder::find_stream_format("opus").unwrap();
+    assert_eq!(opus.format_name, "ogg");
+    assert_eq!(opus.codec, "libopus");
+    assert!(transcoder::find_stream_format("wma").is_none());
+}
+
+#[test]
+fn test_transcode_cache() {
+    setup();
+    let opus = transcoder::find_stream_format("opus").unwrap();
+    let variant = Preset::custom(opus, 96000).name;
+    assert_eq!(variant, "opus-96k");
+    // 与内置预设相同时使用内置预设
+    assert_eq!(Preset::custom(opus, 128000).name, "opus-128");
+    transcode_cache::prune(&std::collections::HashSet::new());
+
+    // 同时请求相同的缓存只转码一次
+    let count = std::sync::atomic::AtomicUsize::new(0);
+    let paths: Vec<PathBuf> = (0..8).into_par_iter().map(|_| {
+        transcode_cache::get_or_transcode("test-audio-a", &variant, opus.extension, |path| {
+            count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            fs::write(path, [0u8; 100])?;
+            Ok(())
+        }).unwrap()
+    }).collect();
+    assert_eq!(count.load(std::sync::atomic::Ordering::SeqCst), 1);
+    assert!(paths.iter().all(|path| path == &paths[0] && path.is_file()));
+
+    // 超出容量时淘汰最久没有访问的缓存
+    transcode_cache::get_or_transcode("test-audio-b", &variant, opus.extension, |path| {
+        fs::write(path, [0u8; 100])?;
+        Ok(())
+    }).unwrap();
+    assert_eq!(repository::transcode_cache::total_size(), 200);
+    transcode_cache::evict(100);
+    assert!(transcode_cache::get("test-audio-a", &variant).is_none());
+    assert!(!paths[0].exists());
+    assert!(transcode_cache::get("test-audio-b", &variant).is_some());
+
+    // 源音频不在媒体库中时删除缓存
+    transcode_cache::prune(&std::collections::HashSet::from(["test-audio-c".to_string()]));
+    assert!(transcode_cache::get("test-audio-b", &variant).is_none());
+}
+
+#[test]
+fn test_transcode_preset() {
+    setup();
+    let names = |formats: &[&str]| formats.iter().map(|format| format.to_string()).collect::<Vec<String>>();
+    let select = |max_bit_rate, formats: &[&str]| {
+        transcode_preset::select(max_bit_rate, &names(formats)).map(|preset| preset.name.as_str())
+    };
+    assert_eq!(select(Some(96000), &[]), Some("opus-64"));
+    assert_eq!(select(Some(160000), &["opus", "aac"]), Some("opus-128"));
+    assert_eq!(select(Some(300000), &["opus", "aac"]), Some("aac-256"));
+    assert_eq!(select(None, &["mp3"]), Some("mp3-320"));
+    assert_eq!(select(None, &[]), Some("flac-16-44"));
+    // 都超过上限时选择码率最低的预设
+    assert_eq!(select(Some(32000), &["aac", "mp3"]), Some("aac-256"));
+    assert_eq!(select(None, &["wma"]), None);
+
+    let cancel = CancellationToken::new();
+    let transcoder = transcode_preset::find("opus-64").unwrap().transcoder(&cancel);
+    assert_eq!(transcoder.codec.as_deref(), Some("libopus"));
+    cancel.cancel();
+    assert!(transcoder.cancel.is_cancelled());
+    assert_eq!(transcoder.sample_rate, Some(48000));
+    assert_eq!(transcoder.bit_rate, Some(64000));
+}
+
+#[test]
+fn test_cover_thumbnail() -> Result<()> {
+    setup();
+    let origin_path = app_config::ORIGIN_COVER_PATH.join("testcover");
+    fs::create_dir_all(origin_path.parent().unwrap())?;
+    let image = image::RgbaImage::from_pixel(600, 300, image::Rgba([255, 0, 0, 128]));
+    image.save_with_format(&origin_path, image::ImageFormat::Png)?;
+    assert_eq!(image_utils::sniff_mime_type(&fs::read(&origin_path)?), Some("image/png"));
+    assert_eq!(image_utils::sniff_mime_type(b"not an image"), None);
+
+    // 保持宽高比，小图不放大
+    let thumbnail_path = app_config::SMALL_COVER_PATH.join("256").join("testcover.jpg");
+    image_utils::convert_to_thumbnail(&origin_path, &thumbnail_path, 256)?;
+    let thumbnail = image::open(&thumbnail_path)?;
+    assert_eq!((thumbnail.width(), thumbnail.height()), (256, 128));
+    assert_eq!(image_utils::sniff_mime_type(&fs::read(&thumbnail_path)?), Some("image/jpeg"));
+
+    let large_path = app_config::SMALL_COVER_PATH.join("1024").join("testcover.jpg");
+    image_utils::convert_to_thumbnail(&origin_path, &large_path, 1024)?;
+    assert_eq!(image::open(&large_path)?.width(), 600);
+    Ok(())
+}
+
+#[actix_web::test]
+async fn test_cover_service() -> Result<()> {
+    setup();
+    let origin_path = app_config::ORIGIN_COVER_PATH.join("testcoverservice");
+    fs::create_dir_all(origin_path.parent().unwrap())?;
+    image::RgbaImage::from_pixel(300, 300, image::Rgba([0, 0, 255, 255])).save_with_format(&origin_path, image::ImageFormat::Png)?;
+    let app = init_service(App::new().service(cover::cover)).await;
+    let get = |uri: &str| TestRequest::get().uri(uri);
+
+    // 原图使用文件内容判断的类型，封面内容由 Hash 决定，可以永久缓存
+    let response = call_service(&app, get("/cover/testcoverservice").to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers();
+    assert_eq!(headers.get("content-type").unwrap(), "image/png");
+    assert_eq!(headers.get("etag").unwrap(), "\"testcoverservice-original\"");
+    assert!(headers.get("cache-control").unwrap().to_str()?.contains("immutable"));
+    assert_eq!(read_body(response).await, fs::read(&origin_path)?);
+
+    let response = call_service(&app, get("/cover/testcoverservice?size=128").to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("content-type").unwrap(), "image/jpeg");
+    assert_eq!(response.headers().get("etag").unwrap(), "\"testcoverservice-128\"");
+
+    // ETag 一致时不返回内容
+    let request = get("/cover/testcoverservice?size=128")
+        .insert_header(("if-none-match", "\"other\", \"testcoverservice-128\""))
+        .to_request();
+    let response = call_service(&app, request).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert!(response.headers().get("cache-control").unwrap().to_str()?.contains("immutable"));
+
+    // 不支持的尺寸和不是字母数字的 Hash
+    let response = call_service(&app, get("/cover/testcoverservice?size=100").to_request()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = call_service(&app, get("/cover/test-cover").to_request()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = call_service(&app, get("/cover/..%2Ftestcoverservice").to_request()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    Ok(())
+}
+
+#[test]
+fn test_sidecar_cover() -> Result<()> {
+    setup();
+    let album_dir = app_config::CACHE_PATH.join("test-sidecar/Album");
+    let _ = fs::remove_dir_all(&album_dir);
+    fs::create_dir_all(album_dir.join("CD1"))?;
+    fs::create_dir_all(album_dir.join("Scans"))?;
+    fs::write(album_dir.join("Scans").join("front.jpg"), b"scans front")?;
+    fs::write(album_dir.join("Folder.JPG"), b"folder")?;
+    fs::write(album_dir.join("back.jpg"), b"back")?;
+    let track_path = album_dir.join("CD1").join("01.flac");
+
+    // 分碟目录中没有封面时查找上级目录，按文件名优先级选择
+    assert_eq!(cover_utils::find_sidecar_cover(&track_path), Some(album_dir.join("Folder.JPG")));
+    fs::write(album_dir.join("CD1").join("cover.png"), b"disc cover")?;
+    assert_eq!(cover_utils::find_sidecar_cover(&track_path), Some(album_dir.join("CD1").join("cover.png")));
+
+    // 默认优先使用内嵌封面
+    let embedded = || Some("embedded".to_string());
+    assert_eq!(cover_utils::select_cover(&track_path, embedded).as_deref(), Some("embedded"));
+    let sidecar_hash = cover_utils::select_cover(&track_path, || None).unwrap();
+    assert!(app_config::ORIGIN_COVER_PATH.join(&sidecar_hash).is_file());
+    fs::write(album_dir.join(app_config::COVER_PREFERENCE_FILE_NAME), "sidecar")?;
+    assert_eq!(cover_utils::select_cover(&track_path, embedded), Some(sidecar_hash));
+    Ok(())
 }
\ No newline at end of file